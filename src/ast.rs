@@ -1,5 +1,32 @@
 use std::fmt;
 
+use fraction::Fraction;
+
+// Source Locations
+
+/// Byte range `start..end` into the program source.
+/// Nodes built by the interpreter itself (not parsed) use `Span::default()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start.min(other.start), end: self.end.max(other.end) }
+    }
+
+    pub fn is_synthetic(&self) -> bool {
+        *self == Span::default()
+    }
+}
+
 // Type System
 
 #[derive(Debug, Clone, PartialEq)]
@@ -11,6 +38,10 @@ pub enum Type {
     Named(String),
     /// A typed discrete distribution, e.g. `Discrete<Weather>`.
     DistOf(String),
+    /// An array of values of one type, e.g. `[int]`.
+    Array(Box<Type>),
+    /// A function value, e.g. `fn(int, int) -> bool`.
+    Fn(Vec<Type>, Box<Type>),
 }
 
 impl fmt::Display for Type {
@@ -21,6 +52,11 @@ impl fmt::Display for Type {
             Type::Bool => write!(f, "bool"),
             Type::Named(n) => write!(f, "{}", n),
             Type::DistOf(n) => write!(f, "Discrete<{}>", n),
+            Type::Array(elem) => write!(f, "[{}]", elem),
+            Type::Fn(params, ret) => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), ret)
+            }
         }
     }
 }
@@ -40,6 +76,7 @@ pub struct EnumDef {
 
 /// Error class for probabilistic functions, determining how multiple rounds combine.
 #[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)] // RP / CoRP / BPP are complexity-class names
 pub enum ErrorClass {
     /// One-sided error: "no" is always correct, "yes" (Uncertain) may be wrong.
     /// Repeated rounds multiply error probabilities. Stop early on Certain(x).
@@ -50,6 +87,9 @@ pub enum ErrorClass {
     /// Two-sided error: both answers may be wrong with prob < 1/2.
     /// Use majority vote over k rounds; confidence from Chernoff bound.
     BPP,
+    /// Zero error (Las Vegas): a round returns `Certain(x)` or `Fail`. Retry
+    /// until a round succeeds, up to a cap derived from the confidence.
+    ZPP,
 }
 
 impl fmt::Display for ErrorClass {
@@ -58,6 +98,27 @@ impl fmt::Display for ErrorClass {
             ErrorClass::RP => write!(f, "RP"),
             ErrorClass::CoRP => write!(f, "coRP"),
             ErrorClass::BPP => write!(f, "BPP"),
+            ErrorClass::ZPP => write!(f, "ZPP"),
+        }
+    }
+}
+
+/// How `map(...) with confidence` shares its error budget across elements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapBudget {
+    /// Split the budget evenly across all elements up front (union bound).
+    Even,
+    /// Give each element an even share of what is left, so budget an element
+    /// did not use (e.g. by stopping on `Certain` in round one) passes on to
+    /// the elements after it.
+    Adaptive,
+}
+
+impl fmt::Display for MapBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapBudget::Even => write!(f, "even"),
+            MapBudget::Adaptive => write!(f, "adaptive"),
         }
     }
 }
@@ -68,6 +129,8 @@ impl fmt::Display for ErrorClass {
 pub struct FuncParam {
     pub name: String,
     pub ty: Type,
+    /// Source range of the declared type.
+    pub ty_span: Span,
 }
 
 /// A regular (deterministic) function definition.
@@ -76,6 +139,8 @@ pub struct FuncDef {
     pub name: String,
     pub params: Vec<FuncParam>,
     pub return_type: Type,
+    /// Source range of the declared return type.
+    pub return_type_span: Span,
     pub body: Vec<Statement>,
 }
 
@@ -85,9 +150,19 @@ pub struct PbFuncDef {
     pub name: String,
     pub params: Vec<FuncParam>,
     pub return_type: Type,
+    /// Source range of the declared return type.
+    pub return_type_span: Span,
     pub error_class: ErrorClass,
     /// Name of the distribution family describing error decay (e.g. "Geometric").
     pub error_distribution: String,
+    /// Declared probability that a single round is wrong (`error_bound: e`, or
+    /// `success_prob: p` stored as `1 - p`). `None` uses the class default.
+    pub error_bound: Option<f64>,
+    /// Source range of the `error_bound`/`success_prob` entry, or of the whole
+    /// metadata when there is none.
+    pub error_bound_span: Span,
+    /// Source range of the `error_class: ...` metadata entries.
+    pub metadata_span: Span,
     pub body: Vec<Statement>,
 }
 
@@ -104,6 +179,7 @@ pub enum ProgramItem {
 // Distributions
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Dist {
     Uniform(Box<Expr>, Box<Expr>),           // start, end (discrete, inclusive)
     UniformContinuous(Box<Expr>, Box<Expr>),  // start, end (continuous)
@@ -115,6 +191,12 @@ pub enum Dist {
     /// Bayesian Beta posterior: Beta(alpha, beta).
     /// Produced by `distribution_of(..., bayesian, N)`.
     Beta(Box<Expr>, Box<Expr>),              // alpha, beta
+    Normal(Box<Expr>, Box<Expr>),            // mu: mean, sigma: standard deviation
+    Poisson(Box<Expr>),                      // lambda: rate
+    Exponential(Box<Expr>),                  // rate
+    Gamma(Box<Expr>, Box<Expr>),             // shape (need not be an integer), scale
+    /// Number of failures before the r-th success.
+    NegativeBinomial(Box<Expr>, Box<Expr>),  // r: successes, p: success probability
 }
 
 // Distribution-of Extraction Mode
@@ -122,18 +204,124 @@ pub enum Dist {
 /// How to extract the underlying distribution of a probabilistic function.
 #[derive(Debug, Clone)]
 pub enum DistributionOfMode {
-    /// Use the error class metadata (RP → Geometric(0.5); BPP → Bernoulli(0.75)).
+    /// Enumerate a round's discrete random choices for the exact Bernoulli(p)
+    /// of returning Certain; otherwise use the error class and bound ε
+    /// (RP / coRP / ZPP → Geometric(1 - ε); BPP → Bernoulli(1 - ε)).
     Analytical,
     /// Run N single rounds; report empirical Certain probability as Bernoulli(p).
     Empirical(i64),
     /// Run N single rounds; produce a Beta posterior over the Certain probability.
     Bayesian(i64),
+    /// Run N single rounds; report the empirical distribution of the values
+    /// they return (inside `Certain`/`Uncertain`, or `Fail`) as a `DynDist`.
+    Outputs(i64),
+    /// Run N trials, each repeating rounds until one returns `Certain`; report
+    /// the empirical distribution of the rounds each trial took as a `DynDist`.
+    Rounds(i64),
+}
+
+// Sampling-based Inference
+
+/// Algorithm used by `infer` to approximate a model's posterior.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InferMethod {
+    /// Run the model from the prior and discard runs that fail an observation.
+    Rejection,
+    /// Likelihood weighting: weight each run by the likelihood of its observations.
+    Importance,
+    /// Single-site Metropolis-Hastings over the model's random choices.
+    MH,
+}
+
+impl fmt::Display for InferMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InferMethod::Rejection => write!(f, "rejection"),
+            InferMethod::Importance => write!(f, "importance"),
+            InferMethod::MH => write!(f, "mh"),
+        }
+    }
+}
+
+// Loops
+
+/// What a `for` loop iterates over.
+#[derive(Debug, Clone)]
+pub enum ForIterable {
+    /// `a..b`: the integers from `a` up to but excluding `b`.
+    Range(Expr, Expr),
+    /// Any expression evaluating to an array.
+    Array(Expr),
+}
+
+// Pattern Matching
+
+/// A pattern in a `match` arm.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatternKind {
+    /// `_`: matches anything.
+    Wildcard,
+    /// A lowercase name: matches anything and binds it to that name.
+    Binding(String),
+    Int(i64),
+    Bool(bool),
+    /// An enum variant such as `Sunny`.
+    Variant(String),
+    Certain(Box<Pattern>),
+    Uncertain(Box<Pattern>),
+    Fail,
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, span: Span) -> Self {
+        Pattern { kind, span }
+    }
+
+    /// Whether the pattern matches every value.
+    pub fn is_irrefutable(&self) -> bool {
+        matches!(self.kind, PatternKind::Wildcard | PatternKind::Binding(_))
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            PatternKind::Wildcard => write!(f, "_"),
+            PatternKind::Binding(name) | PatternKind::Variant(name) => write!(f, "{}", name),
+            PatternKind::Int(n) => write!(f, "{}", n),
+            PatternKind::Bool(b) => write!(f, "{}", b),
+            PatternKind::Certain(inner) => write!(f, "Certain({})", inner),
+            PatternKind::Uncertain(inner) => write!(f, "Uncertain({})", inner),
+            PatternKind::Fail => write!(f, "Fail"),
+        }
+    }
+}
+
+/// One arm of a `match`: `A | B => body`. The body is an expression in a
+/// `match` expression and a block in a `match` statement.
+#[derive(Debug, Clone)]
+pub struct MatchArm<B> {
+    /// Alternatives separated by `|`; the arm is taken if any of them matches.
+    pub patterns: Vec<Pattern>,
+    pub body: B,
 }
 
 // Statements
 
 #[derive(Debug, Clone)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
     Decl(Expr),
     Assign {
         name: Expr,
@@ -150,23 +338,60 @@ pub enum Statement {
         then_block: Vec<Statement>,
         else_block: Option<Vec<Statement>>,
     },
+    /// `while cond { body }`
+    While {
+        cond: Expr,
+        body: Vec<Statement>,
+    },
+    /// `for var in a..b { body }` or `for var in array { body }`
+    For {
+        var: String,
+        iterable: ForIterable,
+        body: Vec<Statement>,
+    },
+    Break,
+    Continue,
+    /// `observe(cond)`: condition the enclosing `posterior()` or `infer` model on `cond`.
+    Observe(Expr),
+    /// `observe(dist, value)`: condition on `value` having been drawn from `dist`.
+    ObserveFrom {
+        dist: Expr,
+        value: Expr,
+    },
+    /// `set_seed(n)`: reseed the random number generator.
+    SetSeed(Expr),
     /// `let result_var, info_var = func_name(args) with confidence >= confidence`
+    /// With `adaptive`, a BPP call may stop voting as soon as the result is settled.
     PbCallAssign {
         result_var: String,
         info_var: String,
         func_name: String,
         args: Vec<Expr>,
         confidence: f64,
+        adaptive: bool,
     },
-    /// `let var = map(func_name, array_expr) [with confidence >= confidence]`
-    /// When confidence is Some(c), func_name must be a pb function;
-    /// the error budget is split evenly across all array elements (union bound).
+    /// `let var[, info_var] = map(func_name, array_expr) [with confidence >= confidence [budget even|adaptive]]`
+    /// When confidence is Some(c), func_name must be a pb function and the
+    /// error budget is shared across the array elements as `budget` says;
+    /// `info_var` receives an `Info` for the whole call.
     /// When confidence is None, func_name must be a regular function.
     MapCallAssign {
         var: String,
+        info_var: Option<String>,
         func_name: String,
         array_expr: Expr,
         confidence: Option<f64>,
+        budget: MapBudget,
+    },
+    /// `let var[, info_var] = infer(func_name(args), method: m[, samples: N])`
+    /// Approximates the posterior of a model by sampling.
+    Infer {
+        var: String,
+        info_var: Option<String>,
+        func_name: String,
+        args: Vec<Expr>,
+        method: InferMethod,
+        samples: i64,
     },
     /// `let var = distribution_of(func_name(args), mode[, N])`
     /// Extracts the implicit underlying distribution of a pb function's per-round behaviour.
@@ -176,14 +401,39 @@ pub enum Statement {
         args: Vec<Expr>,
         mode: DistributionOfMode,
     },
+    /// `match scrutinee { pattern => { ... }, ... }`: run the first arm whose pattern matches.
+    Match {
+        scrutinee: Expr,
+        arms: Vec<MatchArm<Vec<Statement>>>,
+    },
 }
 
 // Expressions
 
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+
+    /// An expression built by the interpreter rather than parsed from source.
+    pub fn synthetic(kind: ExprKind) -> Self {
+        Expr { kind, span: Span::default() }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
+pub enum ExprKind {
     Int(i64),
     Float(f64),
+    /// An exact rational, built by the interpreter (e.g. an exact probability).
+    Frac(Fraction),
     Bool(bool),
     Var(String),
 
@@ -211,6 +461,7 @@ pub enum Expr {
     Or(Box<Expr>, Box<Expr>),
 
     // distributions: method call on a named distribution variable (legacy `:` syntax)
+    #[allow(dead_code)] // no longer produced by the parser
     DistMethodCall {
         var: String,
         method: String,
@@ -224,22 +475,202 @@ pub enum Expr {
         args: Vec<Expr>,
     },
 
+    // field access on a value with named fields (`info.rounds`)
+    Field {
+        expr: Box<Expr>,
+        field: String,
+    },
+
     // array literal
     Array(Vec<Expr>),
 
+    // array indexing `arr[i]` and slicing `arr[a..b]` (either bound may be omitted)
+    Index(Box<Expr>, Box<Expr>),
+    Slice {
+        expr: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+
     // distribution literal
     Dist(Dist),
 
     // probabilistic certainty markers (returned from pb functions)
     Certain(Box<Expr>),
     Uncertain(Box<Expr>),
+    /// `Fail`: a ZPP round that produced no answer.
+    Fail,
 
     // function call
     FuncCall(String, Vec<Expr>),
 
+    /// Anonymous function `|x: int| expr` or `|x: int| { statements }`; an
+    /// expression body is stored as a single `return` statement.
+    Lambda {
+        params: Vec<FuncParam>,
+        body: Vec<Statement>,
+    },
+
     // approximate equality: lhs ~= rhs (within tolerance)?
     // Default tolerance is 0.05.
     ApproxEq(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+
+    /// `match scrutinee { pattern => expr, ... }`: the value of the first arm whose pattern matches.
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm<Expr>>,
+    },
+}
+
+// Traversal
+
+impl Statement {
+    /// Call `f` on every expression in this statement, including those in
+    /// nested blocks and inside other expressions.
+    pub fn walk_exprs(&self, f: &mut impl FnMut(&Expr)) {
+        match &self.kind {
+            StmtKind::Decl(e)
+            | StmtKind::HardcodedOutput(e)
+            | StmtKind::Return(Some(e))
+            | StmtKind::Observe(e)
+            | StmtKind::SetSeed(e) => e.walk(f),
+            StmtKind::Assign { name, value } | StmtKind::DeclAssign { name, value } => {
+                name.walk(f);
+                value.walk(f);
+            }
+            StmtKind::ObserveFrom { dist, value } => {
+                dist.walk(f);
+                value.walk(f);
+            }
+            StmtKind::If { cond, then_block, else_block } => {
+                cond.walk(f);
+                walk_block(then_block, f);
+                if let Some(block) = else_block {
+                    walk_block(block, f);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                cond.walk(f);
+                walk_block(body, f);
+            }
+            StmtKind::For { iterable, body, .. } => {
+                match iterable {
+                    ForIterable::Range(a, b) => {
+                        a.walk(f);
+                        b.walk(f);
+                    }
+                    ForIterable::Array(e) => e.walk(f),
+                }
+                walk_block(body, f);
+            }
+            StmtKind::PbCallAssign { args, .. }
+            | StmtKind::Infer { args, .. }
+            | StmtKind::DistributionOf { args, .. } => {
+                args.iter().for_each(|a| a.walk(f));
+            }
+            StmtKind::MapCallAssign { array_expr, .. } => array_expr.walk(f),
+            StmtKind::Match { scrutinee, arms } => {
+                scrutinee.walk(f);
+                arms.iter().for_each(|arm| walk_block(&arm.body, f));
+            }
+            StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue => {}
+        }
+    }
+}
+
+impl Expr {
+    /// Call `f` on this expression and every expression nested inside it.
+    pub fn walk(&self, f: &mut impl FnMut(&Expr)) {
+        f(self);
+        match &self.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Frac(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Var(_)
+            | ExprKind::Fail => {}
+            ExprKind::Neg(e)
+            | ExprKind::Not(e)
+            | ExprKind::Certain(e)
+            | ExprKind::Uncertain(e)
+            | ExprKind::Field { expr: e, .. } => e.walk(f),
+            ExprKind::Add(a, b)
+            | ExprKind::Sub(a, b)
+            | ExprKind::Mul(a, b)
+            | ExprKind::Div(a, b)
+            | ExprKind::Mod(a, b)
+            | ExprKind::Eq(a, b)
+            | ExprKind::Neq(a, b)
+            | ExprKind::Lt(a, b)
+            | ExprKind::Lte(a, b)
+            | ExprKind::Gt(a, b)
+            | ExprKind::Gte(a, b)
+            | ExprKind::And(a, b)
+            | ExprKind::Or(a, b)
+            | ExprKind::Index(a, b) => {
+                a.walk(f);
+                b.walk(f);
+            }
+            ExprKind::ApproxEq(a, b, tol) => {
+                a.walk(f);
+                b.walk(f);
+                if let Some(t) = tol {
+                    t.walk(f);
+                }
+            }
+            ExprKind::Slice { expr, start, end } => {
+                expr.walk(f);
+                for bound in [start, end].into_iter().flatten() {
+                    bound.walk(f);
+                }
+            }
+            ExprKind::DistMethodCall { args, .. }
+            | ExprKind::Array(args)
+            | ExprKind::FuncCall(_, args) => args.iter().for_each(|a| a.walk(f)),
+            ExprKind::ExprMethodCall { expr, args, .. } => {
+                expr.walk(f);
+                args.iter().for_each(|a| a.walk(f));
+            }
+            ExprKind::Dist(d) => walk_dist(d, f),
+            ExprKind::Lambda { body, .. } => walk_block(body, f),
+            ExprKind::Match { scrutinee, arms } => {
+                scrutinee.walk(f);
+                arms.iter().for_each(|arm| arm.body.walk(f));
+            }
+        }
+    }
+}
+
+fn walk_block(block: &[Statement], f: &mut impl FnMut(&Expr)) {
+    for stmt in block {
+        stmt.walk_exprs(f);
+    }
+}
+
+fn walk_dist(dist: &Dist, f: &mut impl FnMut(&Expr)) {
+    match dist {
+        Dist::Uniform(a, b)
+        | Dist::UniformContinuous(a, b)
+        | Dist::Binomial(a, b)
+        | Dist::Beta(a, b)
+        | Dist::Normal(a, b)
+        | Dist::Gamma(a, b)
+        | Dist::NegativeBinomial(a, b) => {
+            a.walk(f);
+            b.walk(f);
+        }
+        Dist::Bernoulli(p) | Dist::Geometric(p) | Dist::Poisson(p) | Dist::Exponential(p) => p.walk(f),
+        Dist::Discrete(pairs) => {
+            for (v, p) in pairs {
+                v.walk(f);
+                p.walk(f);
+            }
+        }
+        Dist::CombinedDist(a, b) => {
+            walk_dist(a, f);
+            walk_dist(b, f);
+        }
+    }
 }
 
 // Display Helpers
@@ -260,9 +691,18 @@ pub fn format_dist(dist: &Dist) -> String {
         Dist::Binomial(n, p) => format!("Binomial({}, {})", n, p),
         Dist::Geometric(p) => format!("Geometric({})", p),
         Dist::Beta(alpha, beta) => format!("Beta({}, {})", alpha, beta),
+        Dist::Normal(mu, sigma) => format!("Normal({}, {})", mu, sigma),
+        Dist::Poisson(lambda) => format!("Poisson({})", lambda),
+        Dist::Exponential(rate) => format!("Exponential({})", rate),
+        Dist::Gamma(shape, scale) => format!("Gamma({}, {})", shape, scale),
+        Dist::NegativeBinomial(r, p) => format!("NegativeBinomial({}, {})", r, p),
     }
 }
 
+fn fmt_patterns(patterns: &[Pattern]) -> String {
+    patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(" | ")
+}
+
 fn fmt_args(args: &[Expr]) -> String {
     args.iter()
         .map(|a| format!("{}", a))
@@ -271,88 +711,159 @@ fn fmt_args(args: &[Expr]) -> String {
 }
 
 impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl fmt::Display for ExprKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Expr::Int(i) => write!(f, "{}", i),
-            Expr::Float(fl) => write!(f, "{}", fl),
-            Expr::Bool(b) => write!(f, "{}", b),
-            Expr::Var(name) => write!(f, "{}", name),
-            Expr::Neg(inner) => write!(f, "(-{})", inner),
-            Expr::Not(inner) => write!(f, "(!{})", inner),
-            Expr::Add(l, r) => write!(f, "({} + {})", l, r),
-            Expr::Sub(l, r) => write!(f, "({} - {})", l, r),
-            Expr::Mul(l, r) => write!(f, "({} * {})", l, r),
-            Expr::Div(l, r) => write!(f, "({} / {})", l, r),
-            Expr::Mod(l, r) => write!(f, "({} % {})", l, r),
-            Expr::Eq(l, r) => write!(f, "({} == {})", l, r),
-            Expr::Neq(l, r) => write!(f, "({} != {})", l, r),
-            Expr::Lt(l, r) => write!(f, "({} < {})", l, r),
-            Expr::Lte(l, r) => write!(f, "({} <= {})", l, r),
-            Expr::Gt(l, r) => write!(f, "({} > {})", l, r),
-            Expr::Gte(l, r) => write!(f, "({} >= {})", l, r),
-            Expr::And(l, r) => write!(f, "({} && {})", l, r),
-            Expr::Or(l, r) => write!(f, "({} || {})", l, r),
-            Expr::Array(elems) => {
+            ExprKind::Int(i) => write!(f, "{}", i),
+            ExprKind::Float(fl) => write!(f, "{}", fl),
+            ExprKind::Frac(frac) => write!(f, "{}", frac),
+            ExprKind::Bool(b) => write!(f, "{}", b),
+            ExprKind::Var(name) => write!(f, "{}", name),
+            ExprKind::Neg(inner) => write!(f, "(-{})", inner),
+            ExprKind::Not(inner) => write!(f, "(!{})", inner),
+            ExprKind::Add(l, r) => write!(f, "({} + {})", l, r),
+            ExprKind::Sub(l, r) => write!(f, "({} - {})", l, r),
+            ExprKind::Mul(l, r) => write!(f, "({} * {})", l, r),
+            ExprKind::Div(l, r) => write!(f, "({} / {})", l, r),
+            ExprKind::Mod(l, r) => write!(f, "({} % {})", l, r),
+            ExprKind::Eq(l, r) => write!(f, "({} == {})", l, r),
+            ExprKind::Neq(l, r) => write!(f, "({} != {})", l, r),
+            ExprKind::Lt(l, r) => write!(f, "({} < {})", l, r),
+            ExprKind::Lte(l, r) => write!(f, "({} <= {})", l, r),
+            ExprKind::Gt(l, r) => write!(f, "({} > {})", l, r),
+            ExprKind::Gte(l, r) => write!(f, "({} >= {})", l, r),
+            ExprKind::And(l, r) => write!(f, "({} && {})", l, r),
+            ExprKind::Or(l, r) => write!(f, "({} || {})", l, r),
+            ExprKind::Array(elems) => {
                 let parts: Vec<String> = elems.iter().map(|e| format!("{}", e)).collect();
                 write!(f, "[{}]", parts.join(", "))
             }
-            Expr::Dist(d) => write!(f, "{}", format_dist(d)),
-            Expr::DistMethodCall { var, method, args } => {
+            ExprKind::Index(arr, idx) => write!(f, "{}[{}]", arr, idx),
+            ExprKind::Slice { expr, start, end } => {
+                let bound = |b: &Option<Box<Expr>>| b.as_ref().map(|e| e.to_string()).unwrap_or_default();
+                write!(f, "{}[{}..{}]", expr, bound(start), bound(end))
+            }
+            ExprKind::Dist(d) => write!(f, "{}", format_dist(d)),
+            ExprKind::DistMethodCall { var, method, args } => {
                 write!(f, "{}:{}({})", var, method, fmt_args(args))
             }
-            Expr::ExprMethodCall { expr, method, args } => {
+            ExprKind::ExprMethodCall { expr, method, args } => {
                 write!(f, "{}.{}({})", expr, method, fmt_args(args))
             }
-            Expr::Certain(inner) => write!(f, "Certain({})", inner),
-            Expr::Uncertain(inner) => write!(f, "Uncertain({})", inner),
-            Expr::FuncCall(name, args) => write!(f, "{}({})", name, fmt_args(args)),
-            Expr::ApproxEq(l, r, None) => write!(f, "({} ~= {})", l, r),
-            Expr::ApproxEq(l, r, Some(t)) => write!(f, "({} ~= {} within {})", l, r, t),
+            ExprKind::Field { expr, field } => write!(f, "{}.{}", expr, field),
+            ExprKind::Certain(inner) => write!(f, "Certain({})", inner),
+            ExprKind::Uncertain(inner) => write!(f, "Uncertain({})", inner),
+            ExprKind::Fail => write!(f, "Fail"),
+            ExprKind::FuncCall(name, args) => write!(f, "{}({})", name, fmt_args(args)),
+            ExprKind::Lambda { params, body } => {
+                let params: Vec<String> = params.iter().map(|p| format!("{}: {}", p.name, p.ty)).collect();
+                match body.as_slice() {
+                    [Statement { kind: StmtKind::Return(Some(expr)), .. }] => {
+                        write!(f, "|{}| {}", params.join(", "), expr)
+                    }
+                    _ => write!(f, "|{}| {{ {} statements }}", params.join(", "), body.len()),
+                }
+            }
+            ExprKind::ApproxEq(l, r, None) => write!(f, "({} ~= {})", l, r),
+            ExprKind::ApproxEq(l, r, Some(t)) => write!(f, "({} ~= {} within {})", l, r, t),
+            ExprKind::Match { scrutinee, arms } => {
+                let arms: Vec<String> = arms
+                    .iter()
+                    .map(|arm| format!("{} => {}", fmt_patterns(&arm.patterns), arm.body))
+                    .collect();
+                write!(f, "match {} {{ {} }}", scrutinee, arms.join(", "))
+            }
         }
     }
 }
 
 impl fmt::Display for Statement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Statement::Decl(name) => write!(f, "let {};", name),
-            Statement::DeclAssign { name, value } => write!(f, "let {} = {};", name, value),
-            Statement::Assign { name, value } => write!(f, "{} = {};", name, value),
-            Statement::HardcodedOutput(expr) => write!(f, "output({});", expr),
-            Statement::Return(Some(expr)) => write!(f, "return {};", expr),
-            Statement::Return(None) => write!(f, "return;"),
-            Statement::If { cond, then_block, else_block } => {
+        match &self.kind {
+            StmtKind::Decl(name) => write!(f, "let {};", name),
+            StmtKind::DeclAssign { name, value } => write!(f, "let {} = {};", name, value),
+            StmtKind::Assign { name, value } => write!(f, "{} = {};", name, value),
+            StmtKind::HardcodedOutput(expr) => write!(f, "output({});", expr),
+            StmtKind::Return(Some(expr)) => write!(f, "return {};", expr),
+            StmtKind::Return(None) => write!(f, "return;"),
+            StmtKind::If { cond, then_block, else_block } => {
                 write!(f, "if {} {{ {} statements }}", cond, then_block.len())?;
                 if let Some(else_b) = else_block {
                     write!(f, " else {{ {} statements }}", else_b.len())?;
                 }
                 Ok(())
             }
-            Statement::PbCallAssign { result_var, info_var, func_name, args, confidence } => {
+            StmtKind::While { cond, body } => {
+                write!(f, "while {} {{ {} statements }}", cond, body.len())
+            }
+            StmtKind::For { var, iterable, body } => match iterable {
+                ForIterable::Range(a, b) => {
+                    write!(f, "for {} in {}..{} {{ {} statements }}", var, a, b, body.len())
+                }
+                ForIterable::Array(arr) => {
+                    write!(f, "for {} in {} {{ {} statements }}", var, arr, body.len())
+                }
+            },
+            StmtKind::Break => write!(f, "break;"),
+            StmtKind::Continue => write!(f, "continue;"),
+            StmtKind::Observe(cond) => write!(f, "observe({});", cond),
+            StmtKind::ObserveFrom { dist, value } => write!(f, "observe({}, {});", dist, value),
+            StmtKind::SetSeed(seed) => write!(f, "set_seed({});", seed),
+            StmtKind::PbCallAssign { result_var, info_var, func_name, args, confidence, adaptive } => {
                 write!(
                     f,
-                    "let {}, {} = {}({}) with confidence >= {};",
-                    result_var, info_var, func_name, fmt_args(args), confidence
+                    "let {}, {} = {}({}) with confidence >= {}{};",
+                    result_var, info_var, func_name, fmt_args(args), confidence,
+                    if *adaptive { " adaptive" } else { "" }
                 )
             }
-            Statement::MapCallAssign { var, func_name, array_expr, confidence } => {
-                match confidence {
-                    Some(c) => write!(
-                        f,
-                        "let {} = map({}, {}) with confidence >= {};",
-                        var, func_name, array_expr, c
-                    ),
-                    None => write!(f, "let {} = map({}, {});", var, func_name, array_expr),
+            StmtKind::MapCallAssign { var, info_var, func_name, array_expr, confidence, budget } => {
+                write!(f, "let {}", var)?;
+                if let Some(info) = info_var {
+                    write!(f, ", {}", info)?;
+                }
+                write!(f, " = map({}, {})", func_name, array_expr)?;
+                if let Some(c) = confidence {
+                    write!(f, " with confidence >= {}", c)?;
+                    if *budget != MapBudget::Even {
+                        write!(f, " budget {}", budget)?;
+                    }
                 }
+                write!(f, ";")
+            }
+            StmtKind::Infer { var, info_var, func_name, args, method, samples } => {
+                let vars = match info_var {
+                    Some(info) => format!("{}, {}", var, info),
+                    None => var.clone(),
+                };
+                write!(
+                    f,
+                    "let {} = infer({}({}), method: {}, samples: {});",
+                    vars, func_name, fmt_args(args), method, samples
+                )
             }
-            Statement::DistributionOf { var, func_name, args, mode } => {
+            StmtKind::DistributionOf { var, func_name, args, mode } => {
                 let mode_str = match mode {
                     DistributionOfMode::Analytical => "analytical".to_string(),
                     DistributionOfMode::Empirical(n) => format!("empirical, {}", n),
                     DistributionOfMode::Bayesian(n) => format!("bayesian, {}", n),
+                    DistributionOfMode::Outputs(n) => format!("outputs, {}", n),
+                    DistributionOfMode::Rounds(n) => format!("rounds, {}", n),
                 };
                 write!(f, "let {} = distribution_of({}({}), {});", var, func_name, fmt_args(args), mode_str)
             }
+            StmtKind::Match { scrutinee, arms } => {
+                let arms: Vec<String> = arms
+                    .iter()
+                    .map(|arm| format!("{} => {{ {} statements }}", fmt_patterns(&arm.patterns), arm.body.len()))
+                    .collect();
+                write!(f, "match {} {{ {} }}", scrutinee, arms.join(", "))
+            }
         }
     }
 }
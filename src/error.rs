@@ -0,0 +1,174 @@
+/// Structured errors for the YAPPL parser and interpreter.
+///
+/// Every fallible entry point (`parse`, `collect_output`, `run_to_string`,
+/// `run_to_html`) returns `Result<_, YapplError>` so embedders can match on
+/// the kind of failure instead of inspecting message text.
+use std::fmt;
+
+use crate::ast::Span;
+
+/// Shorthand used throughout the parser and interpreter.
+pub type Result<T, E = YapplError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum YapplError {
+    /// The source text could not be parsed.
+    ParseError { line: usize, message: String },
+    /// An operation was applied to values of the wrong type.
+    TypeError(String),
+    /// A variable was read before it was assigned.
+    UndefinedVariable(String),
+    /// A call referred to a function that has not been defined.
+    UndefinedFunction(String),
+    /// Integer division by zero.
+    DivisionByZero,
+    /// An array index or slice bound fell outside the array.
+    IndexOutOfBounds { index: i64, len: usize },
+    /// A function or built-in was called with the wrong number of arguments.
+    ArityError { name: String, expected: usize, got: usize },
+    /// A probabilistic function was used in a way that breaks its calling
+    /// contract (e.g. called without a confidence, or returned a value that is
+    /// neither `Certain(v)` nor `Uncertain(v)`).
+    ProbabilisticContractViolation(String),
+    /// An `observe` saw a false condition (or an impossible value) outside of
+    /// `posterior()` and `infer`. Inside them it rejects the current run instead.
+    ObservationFailed,
+    /// Exact enumeration met a draw it cannot walk: a continuous or unbounded
+    /// distribution, or more execution paths than the enumeration allows.
+    NotEnumerable(String),
+    /// Any other failure raised while executing the program.
+    RuntimeError(String),
+    /// Another error, tagged with the source range that caused it.
+    Spanned(Span, Box<YapplError>),
+    /// Several type errors found together by the static checker, in source order.
+    TypeErrors(Vec<YapplError>),
+}
+
+impl YapplError {
+    /// The underlying error, with any location wrapper removed.
+    pub fn kind(&self) -> &YapplError {
+        match self {
+            YapplError::Spanned(_, inner) => inner.kind(),
+            other => other,
+        }
+    }
+
+    /// Where the error happened; for several type errors, where the first one did.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            YapplError::Spanned(span, _) => Some(*span),
+            YapplError::TypeErrors(errors) => errors.first().and_then(YapplError::span),
+            _ => None,
+        }
+    }
+
+    /// Attach `span` unless the error already has a location. Errors bubble
+    /// up from the innermost expression, so the first span attached wins.
+    pub fn with_span(self, span: Span) -> YapplError {
+        if self.span().is_some() || span.is_synthetic() {
+            self
+        } else {
+            YapplError::Spanned(span, Box::new(self))
+        }
+    }
+
+    /// Render the error with its location: file name, line, column, the
+    /// offending source line and a caret underline beneath the span.
+    pub fn render(&self, source: &str, filename: &str) -> String {
+        if let YapplError::TypeErrors(errors) = self {
+            let rendered: Vec<String> = errors.iter().map(|e| e.render(source, filename)).collect();
+            return rendered.join("\n\n");
+        }
+        let Some(span) = self.span() else {
+            return self.to_string();
+        };
+        let (line, col) = line_col(source, span.start);
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map(|n| n + 1).unwrap_or(0);
+        let line_end = source[start..].find('\n').map(|n| start + n).unwrap_or(source.len());
+        let line_text = source[line_start..line_end].trim_end_matches('\r');
+        // Underline to the end of the span, or to the end of the line if the
+        // span covers several lines. Tabs are kept so the carets line up.
+        let underline_end = span.end.clamp(start, line_start + line_text.len());
+        let width = source[start..underline_end].chars().count().max(1);
+        let pad: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "{msg}\n{gutter}--> {filename}:{line}:{col}\n{gutter} |\n{line} | {text}\n{gutter} | {pad}{carets}",
+            msg = self,
+            text = line_text,
+            carets = "^".repeat(width),
+        )
+    }
+}
+
+/// 1-based line and column (in characters) of the byte `offset` in `source`.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|n| n + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+impl fmt::Display for YapplError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YapplError::ParseError { line, message } => {
+                write!(f, "Parse error on line {}: {}", line, message)
+            }
+            YapplError::TypeError(msg) => write!(f, "Type error: {}", msg),
+            YapplError::UndefinedVariable(name) => write!(f, "Undefined variable: '{}'", name),
+            YapplError::UndefinedFunction(name) => write!(f, "Undefined function: '{}'", name),
+            YapplError::DivisionByZero => write!(f, "Runtime error: division by zero"),
+            YapplError::IndexOutOfBounds { index, len } => write!(
+                f,
+                "Runtime error: index {} out of bounds for array of length {}",
+                index, len
+            ),
+            YapplError::ArityError { name, expected, got } => write!(
+                f,
+                "Function '{}' expects {} arguments, got {}",
+                name, expected, got
+            ),
+            YapplError::ProbabilisticContractViolation(msg) => {
+                write!(f, "Probabilistic contract violation: {}", msg)
+            }
+            YapplError::ObservationFailed => {
+                write!(f, "Runtime error: observation failed outside of posterior() or infer")
+            }
+            YapplError::NotEnumerable(msg) | YapplError::RuntimeError(msg) => {
+                write!(f, "Runtime error: {}", msg)
+            }
+            // The location is only shown by `render`, which has the source.
+            YapplError::Spanned(..) => write!(f, "{}", self.kind()),
+            YapplError::TypeErrors(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for YapplError {}
+
+/// Build a `YapplError::TypeError` from a format string.
+macro_rules! type_err {
+    ($($arg:tt)*) => { $crate::error::YapplError::TypeError(format!($($arg)*)) };
+}
+
+/// Build a `YapplError::RuntimeError` from a format string.
+macro_rules! runtime_err {
+    ($($arg:tt)*) => { $crate::error::YapplError::RuntimeError(format!($($arg)*)) };
+}
+
+/// Build a `YapplError::ProbabilisticContractViolation` from a format string.
+macro_rules! contract_err {
+    ($($arg:tt)*) => {
+        $crate::error::YapplError::ProbabilisticContractViolation(format!($($arg)*))
+    };
+}
+
+pub(crate) use {contract_err, runtime_err, type_err};
@@ -1,11 +1,17 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::rc::Rc;
 
 use fraction::Fraction;
 use fraction::ToPrimitive;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
 use crate::ast::*;
+use crate::error::{Result, YapplError, contract_err, runtime_err, type_err};
+use crate::markov::{Chain, Unreachable};
+use crate::prob::Prob;
 use crate::visualiser::{self, HistogramData, HistKind};
 
 // Runtime Value
@@ -20,8 +26,19 @@ pub enum RuntimeValue {
     Certain(Box<RuntimeValue>),
     /// Returned by a pb function round to signal a probabilistic result.
     Uncertain(Box<RuntimeValue>),
+    /// Returned by a ZPP round that produced no answer, and by a ZPP call that
+    /// ran out of rounds.
+    Fail,
     /// Metadata produced alongside a pb function call result.
-    Info { rounds: u64, confidence: f64 },
+    Info(PbInfo),
+    /// The outcome of checking a pb function against its declared error class.
+    Verification(VerifyReport),
+    /// A hidden Markov model built by `hmm()`.
+    Hmm(Box<HmmModel>),
+    /// A function passed around as a value (a named function or a lambda).
+    Func(Rc<FuncValue>),
+    /// Diagnostics produced alongside an `infer` result.
+    InferInfo { method: InferMethod, samples: u64, acceptance_rate: f64, ess: f64 },
     /// An ordered collection of runtime values.
     Array(Vec<RuntimeValue>),
     /// A distribution histogram ready to be rendered (produced by `:visualise()`).
@@ -32,8 +49,205 @@ pub enum RuntimeValue {
     /// A value of a user-defined enum type, e.g. `Sunny` of type `Weather`.
     EnumVariant(String, String), // (type_name, variant_name)
     /// A dynamic discrete distribution over arbitrary state values.
-    /// Produced by `bind()` and `step()` for Markov chain computations.
-    DynDist(Vec<(RuntimeValue, f64)>),
+    /// Produced by `bind()` and `step()` for Markov chain computations, and by
+    /// `posterior()` for exact inference.
+    DynDist(Vec<(RuntimeValue, Prob)>),
+}
+
+/// What a pb function call did to reach its answer.
+#[derive(Debug, Clone, Default)]
+pub struct PbInfo {
+    pub rounds: u64,
+    pub confidence: f64,
+    /// The declared (or default) per-round error bound ε.
+    pub error_bound: f64,
+    /// Whether the call finished before using its full round budget.
+    pub stopped_early: bool,
+    /// The raw `Certain`/`Uncertain`/`Fail` value returned by each round, in order.
+    pub trace: Vec<RuntimeValue>,
+    /// Chance that a ZPP call exhausts its round cap (ZPP only).
+    pub fail_prob: Option<f64>,
+    /// Number of rounds voting for each distinct answer, most votes first (BPP only).
+    pub votes: Option<Vec<(RuntimeValue, u64)>>,
+}
+
+impl PbInfo {
+    /// Info for a call that ran the rounds in `trace` out of a budget of `max_rounds`.
+    fn new(model: &ErrorModel, trace: Vec<RuntimeValue>, max_rounds: u64, confidence: f64) -> Self {
+        let rounds = trace.len() as u64;
+        PbInfo {
+            rounds,
+            confidence,
+            error_bound: model.per_round,
+            stopped_early: rounds < max_rounds,
+            trace,
+            ..Default::default()
+        }
+    }
+
+    /// Read a field for `info.<name>` access.
+    fn field(&self, name: &str) -> Result<RuntimeValue> {
+        let value = match name {
+            "rounds" => RuntimeValue::Int(self.rounds as i64),
+            "confidence" => RuntimeValue::Float(self.confidence),
+            "error_bound" => RuntimeValue::Float(self.error_bound),
+            "stopped_early" => RuntimeValue::Bool(self.stopped_early),
+            "trace" => RuntimeValue::Array(self.trace.clone()),
+            "votes" => match &self.votes {
+                Some(votes) => RuntimeValue::Array(
+                    votes
+                        .iter()
+                        .map(|(v, n)| RuntimeValue::Array(vec![v.clone(), RuntimeValue::Int(*n as i64)]))
+                        .collect(),
+                ),
+                None => return Err(runtime_err!("'votes' is only recorded for BPP calls")),
+            },
+            "fail_prob" => match self.fail_prob {
+                Some(p) => RuntimeValue::Float(p),
+                None => return Err(runtime_err!("'fail_prob' is only recorded for ZPP calls")),
+            },
+            _ => {
+                return Err(runtime_err!(
+                    "Info has no field '{}' (expected rounds, confidence, error_bound, \
+                     stopped_early, trace, votes or fail_prob)",
+                    name
+                ));
+            }
+        };
+        Ok(value)
+    }
+}
+
+impl fmt::Display for PbInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Info {{ rounds: {}, confidence: {:.6}", self.rounds, self.confidence)?;
+        if let Some(p) = self.fail_prob {
+            write!(f, ", fail_prob: {:.6}", p)?;
+        }
+        if let Some(votes) = &self.votes {
+            let parts: Vec<String> = votes.iter().map(|(v, n)| format!("{}: {}", v, n)).collect();
+            write!(f, ", votes: {{{}}}", parts.join(", "))?;
+        }
+        write!(f, " }}")
+    }
+}
+
+/// What `verify` found when running a pb function's rounds on each input.
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub func: String,
+    pub error_class: ErrorClass,
+    pub error_bound: f64,
+    /// Rounds run per input.
+    pub trials: u64,
+    pub rows: Vec<VerifyRow>,
+}
+
+/// The rounds run on one input.
+#[derive(Debug, Clone)]
+pub struct VerifyRow {
+    pub args: Vec<RuntimeValue>,
+    /// The right answer: from the reference function, or else inferred from
+    /// the rounds themselves.
+    pub expected: RuntimeValue,
+    /// Rounds that did not give `expected` (for ZPP, rounds that gave `Fail`).
+    pub errors: u64,
+    /// 95% Wilson score interval for the per-round error rate.
+    pub interval: (f64, f64),
+    pub violations: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.rows.iter().all(|row| row.violations.is_empty())
+    }
+
+    fn violation_count(&self) -> usize {
+        self.rows.iter().map(|row| row.violations.len()).sum()
+    }
+
+    /// Read a field for `report.<name>` access.
+    fn field(&self, name: &str) -> Result<RuntimeValue> {
+        Ok(match name {
+            "passed" => RuntimeValue::Bool(self.passed()),
+            "violations" => RuntimeValue::Int(self.violation_count() as i64),
+            "trials" => RuntimeValue::Int(self.trials as i64),
+            "error_rates" => RuntimeValue::Array(
+                self.rows
+                    .iter()
+                    .map(|row| RuntimeValue::Float(row.errors as f64 / self.trials as f64))
+                    .collect(),
+            ),
+            _ => {
+                return Err(runtime_err!(
+                    "verify report has no field '{}' (expected passed, violations, trials or error_rates)",
+                    name
+                ));
+            }
+        })
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "verify {}: {}, per-round error bound {}, {} trials per input",
+            self.func, self.error_class, self.error_bound, self.trials
+        )?;
+        for row in &self.rows {
+            let args: Vec<String> = row.args.iter().map(|a| a.to_string()).collect();
+            writeln!(
+                f,
+                "  {}({}) = {}: {} errors, rate {:.4} (95% CI {:.4}..{:.4}){}",
+                self.func,
+                args.join(", "),
+                row.expected,
+                row.errors,
+                row.errors as f64 / self.trials as f64,
+                row.interval.0,
+                row.interval.1,
+                if row.violations.is_empty() { "" } else { " VIOLATION" }
+            )?;
+            for violation in &row.violations {
+                writeln!(f, "    {}", violation)?;
+            }
+        }
+        match self.violation_count() {
+            0 => write!(f, "passed"),
+            n => write!(f, "FAILED: {} violation(s)", n),
+        }
+    }
+}
+
+/// A hidden Markov model: the hidden state moves by `transition` and each
+/// state emits an observation drawn from `emission`.
+#[derive(Debug, Clone)]
+pub struct HmmModel {
+    pub transition: Rc<FuncValue>,
+    pub emission: Rc<FuncValue>,
+    /// The hidden state's distribution at the first observation.
+    pub initial: Vec<(RuntimeValue, Prob)>,
+}
+
+impl fmt::Display for HmmModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HMM({}, {})", self.transition.name, self.emission.name)
+    }
+}
+
+/// A function as a value: a named function, a pb function, or a lambda
+/// together with the variables it captured where it was written. As a
+/// transition (for `bind`, `step`, `hmm` and the Markov analysis builtins) a
+/// pb function steps to the value a single round returns.
+#[derive(Debug, Clone)]
+pub struct FuncValue {
+    /// The function's name, or `lambda`.
+    pub name: String,
+    pub params: Vec<FuncParam>,
+    pub body: Vec<Statement>,
+    pub captured: HashMap<String, RuntimeValue>,
+    pub pb: bool,
 }
 
 // Output Line
@@ -47,21 +261,29 @@ pub enum OutputLine {
 }
 
 impl RuntimeValue {
-    /// Extract a numeric value (Int, Float, or Frac) as f64, panicking otherwise.
-    fn as_f64(&self) -> f64 {
+    /// Extract a numeric value (Int, Float, or Frac) as f64, or fail with a type error.
+    fn as_f64(&self) -> Result<f64> {
+        match self {
+            RuntimeValue::Int(n) => Ok(*n as f64),
+            RuntimeValue::Float(n) => Ok(*n),
+            RuntimeValue::Frac(f) => Ok(f.to_f64().unwrap_or(f64::NAN)),
+            other => Err(type_err!("expected a number, got {}", other)),
+        }
+    }
+
+    /// Extract an integer, or fail with a type error.
+    fn as_int(&self) -> Result<i64> {
         match self {
-            RuntimeValue::Int(n) => *n as f64,
-            RuntimeValue::Float(n) => *n,
-            RuntimeValue::Frac(f) => f.to_f64().unwrap_or(f64::NAN),
-            other => panic!("Type error: expected a number, got {}", other),
+            RuntimeValue::Int(n) => Ok(*n),
+            other => Err(type_err!("expected an integer, got {}", other)),
         }
     }
 
-    /// Extract a boolean, panicking otherwise.
-    fn as_bool(&self) -> bool {
+    /// Extract a boolean, or fail with a type error.
+    fn as_bool(&self) -> Result<bool> {
         match self {
-            RuntimeValue::Bool(b) => *b,
-            other => panic!("Type error: expected bool, got {}", other),
+            RuntimeValue::Bool(b) => Ok(*b),
+            other => Err(type_err!("expected bool, got {}", other)),
         }
     }
 }
@@ -81,9 +303,16 @@ impl fmt::Display for RuntimeValue {
             RuntimeValue::Dist(d) => write!(f, "{}", format_dist(d)),
             RuntimeValue::Certain(inner) => write!(f, "Certain({})", inner),
             RuntimeValue::Uncertain(inner) => write!(f, "Uncertain({})", inner),
-            RuntimeValue::Info { rounds, confidence } => {
-                write!(f, "Info {{ rounds: {}, confidence: {:.6} }}", rounds, confidence)
-            }
+            RuntimeValue::Fail => write!(f, "Fail"),
+            RuntimeValue::Info(info) => write!(f, "{}", info),
+            RuntimeValue::Verification(report) => write!(f, "{}", report),
+            RuntimeValue::Hmm(model) => write!(f, "{}", model),
+            RuntimeValue::Func(func) => write!(f, "<fn {}>", func.name),
+            RuntimeValue::InferInfo { method, samples, acceptance_rate, ess } => write!(
+                f,
+                "InferInfo {{ method: {}, samples: {}, acceptance_rate: {:.4}, ess: {:.1} }}",
+                method, samples, acceptance_rate, ess
+            ),
             RuntimeValue::Array(elems) => {
                 let parts: Vec<String> = elems.iter().map(|e| format!("{}", e)).collect();
                 write!(f, "[{}]", parts.join(", "))
@@ -94,7 +323,7 @@ impl fmt::Display for RuntimeValue {
             RuntimeValue::DynDist(outcomes) => {
                 let parts: Vec<String> = outcomes
                     .iter()
-                    .map(|(v, p)| format!("{}: {:.4}", v, p))
+                    .map(|(v, p)| format!("{}: {:.4}", v, p.to_f64()))
                     .collect();
                 write!(f, "DynDist{{{}}}", parts.join(", "))
             }
@@ -105,6 +334,11 @@ impl fmt::Display for RuntimeValue {
 // Flow Control
 
 enum FlowControl {
+    /// Carry on with the next statement.
+    Normal,
+    /// Leave the innermost loop.
+    Break,
+    /// Skip to the next iteration of the innermost loop.
     Continue,
     Return(RuntimeValue),
 }
@@ -124,10 +358,217 @@ struct RuntimeEnv {
     enum_variants: HashMap<String, String>,
     /// Lines accumulated by `output(...)` statements.
     output: Vec<OutputLine>,
+    /// Number of loops enclosing the statement being executed, so `break` and
+    /// `continue` outside a loop can be rejected. Reset for each function call.
+    loop_depth: usize,
+    /// Set while `posterior()` or `infer` is running a model; shared with child
+    /// environments so that `.sample()` calls inside nested calls are tracked.
+    inference: Rc<RefCell<Option<Inference>>>,
+    /// The one random number generator for the whole run, shared with child
+    /// environments so that a seed fixes every draw.
+    rng: SharedRng,
+    /// Set while the rounds of a pb function that calls other pb functions
+    /// are running; shared with child environments so that nested calls made
+    /// through regular functions draw on the same budget.
+    nested_budget: Option<Rc<NestedBudget>>,
+    /// Set in `--verify` mode: every pb function call made by the program, as
+    /// `(function name, arguments)`, so each can be verified afterwards.
+    pb_calls: Option<PbCallLog>,
+}
+
+/// `(function name, arguments)` for each pb function call, in call order.
+type PbCallLog = Rc<RefCell<Vec<(String, Vec<RuntimeValue>)>>>;
+
+/// A seedable generator shared between environments. Each draw borrows it
+/// only for that draw, so sampling may nest (e.g. a distribution parameter
+/// that is itself sampled) without a double borrow.
+#[derive(Clone)]
+struct SharedRng(Rc<RefCell<StdRng>>);
+
+impl SharedRng {
+    /// Seeded from `seed`, or from OS entropy when there is none.
+    fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        SharedRng(Rc::new(RefCell::new(rng)))
+    }
+
+    fn reseed(&self, seed: u64) {
+        *self.0.borrow_mut() = StdRng::seed_from_u64(seed);
+    }
+}
+
+impl RngCore for SharedRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.borrow_mut().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.borrow_mut().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.borrow_mut().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand::Error> {
+        self.0.borrow_mut().try_fill_bytes(dest)
+    }
+}
+
+// Nested pb Calls
+
+/// The share of a pb function call's error budget set aside for the pb
+/// functions it calls as expressions. The number of nested calls is not known
+/// up front, so the j-th call (j = 1, 2, ...) is given pool · 6/(π²j²); these
+/// shares sum to the pool however many calls are made.
+struct NestedBudget {
+    pool: f64,
+    calls: Cell<u32>,
+    /// Error actually reported by the nested calls so far.
+    spent: Cell<f64>,
+}
+
+impl NestedBudget {
+    fn new(pool: f64) -> Self {
+        NestedBudget { pool, calls: Cell::new(0), spent: Cell::new(0.0) }
+    }
+
+    /// Error allowed for the next nested call.
+    fn next_share(&self) -> f64 {
+        let j = self.calls.get() + 1;
+        self.calls.set(j);
+        self.pool * 6.0 / (std::f64::consts::PI.powi(2) * f64::from(j).powi(2))
+    }
+
+    fn spend(&self, error: f64) {
+        self.spent.set(self.spent.get() + error);
+    }
+}
+
+// Inference State
+
+/// How `.sample()` and `observe` behave while a model is being run for inference.
+enum Inference {
+    /// `posterior()`: replay one path of an exact enumeration.
+    Enumerate(Enumeration),
+    /// `infer`: one sampled run of the model.
+    Sample(SampleRun),
+}
+
+/// Replay state for enumerating every path through a model's discrete random
+/// choices. Each run of the model follows `prefix` for its first choices and
+/// takes the first option for any later ones; the choices actually made are
+/// recorded in `trace` so the next unexplored path can be derived from it.
+struct Enumeration {
+    prefix: Vec<usize>,
+    /// Paths the enumeration may explore. A single draw with more outcomes
+    /// than this could never finish, so it is refused before any is taken.
+    max_paths: usize,
+    /// `(chosen index, number of options)` for each choice made on this path.
+    trace: Vec<(usize, usize)>,
+    /// Probability of the choices made so far on this path.
+    weight: Prob,
+}
+
+impl Enumeration {
+    fn replay(prefix: Vec<usize>, max_paths: usize) -> Self {
+        Enumeration { prefix, max_paths, trace: Vec::new(), weight: Prob::one() }
+    }
+
+    fn choose(&mut self, n_options: usize) -> usize {
+        let i = self.prefix.get(self.trace.len()).copied().unwrap_or(0);
+        self.trace.push((i, n_options));
+        i
+    }
+
+    /// The prefix for the next path in depth-first order: advance the deepest
+    /// choice that still has untried options, dropping everything after it.
+    fn next_prefix(&self) -> Option<Vec<usize>> {
+        let pos = self.trace.iter().rposition(|&(i, n)| i + 1 < n)?;
+        let mut prefix: Vec<usize> = self.trace[..pos].iter().map(|&(i, _)| i).collect();
+        prefix.push(self.trace[pos].0 + 1);
+        Some(prefix)
+    }
+}
+
+/// z-score for the 95% confidence intervals reported by `verify`.
+const VERIFY_Z: f64 = 1.96;
+
+/// Error budget given to the nested pb calls of a round run on its own, as
+/// `verify` and `distribution_of` do, rather than as part of a call.
+const SINGLE_ROUND_NESTED_ERROR: f64 = 1e-6;
+
+/// Rounds per input when `--verify` checks the pb calls a program made.
+const VERIFY_CLI_TRIALS: u64 = 1000;
+
+/// Rounds a `distribution_of(..., rounds, N)` trial may run before giving up
+/// on the function ever returning `Certain`.
+const MAX_ROUNDS_UNTIL_CERTAIN: i64 = 10_000;
+
+/// Upper bound on the number of paths `posterior()` will explore.
+const MAX_ENUMERATION_PATHS: usize = 1_000_000;
+
+/// Upper bound on the paths `distribution_of(..., analytical)` explores before
+/// falling back to the declared error model. Each path rebuilds the outcomes
+/// of every draw it makes, so this is kept small.
+const MAX_ANALYTICAL_PATHS: usize = 4_096;
+
+/// A single sampled run of a model under `infer`.
+struct SampleRun {
+    method: InferMethod,
+    /// Values to reuse for each random choice, by position (MH proposals).
+    /// `None` entries, and positions past the end, are sampled afresh.
+    replay: Vec<Option<RuntimeValue>>,
+    trace: Vec<Draw>,
+    /// Log-likelihood of the `observe(dist, value)` statements seen so far.
+    log_weight: f64,
+}
+
+impl SampleRun {
+    fn new(method: InferMethod, replay: Vec<Option<RuntimeValue>>) -> Self {
+        SampleRun { method, replay, trace: Vec::new(), log_weight: 0.0 }
+    }
+
+    /// Log of prior probability times likelihood for this run.
+    fn log_score(&self) -> f64 {
+        self.log_weight + self.trace.iter().map(|d| d.log_prob).sum::<f64>()
+    }
+}
+
+/// A random choice made during a sampled run.
+struct Draw {
+    value: RuntimeValue,
+    /// Log-probability (or log-density) of `value` under its distribution.
+    /// Only tracked for MH, which needs it for the acceptance ratio.
+    log_prob: f64,
+    /// Whether the value was freshly sampled rather than replayed.
+    fresh: bool,
 }
 
+/// Weighted samples collected by one of the `infer` methods.
+struct InferSamples {
+    weighted: Vec<(RuntimeValue, f64)>,
+    accepted: usize,
+    ess: f64,
+}
+
+/// Number of evenly spaced quantiles at which `prob(pred)` evaluates the
+/// predicate for continuous distributions.
+const PROB_GRID_POINTS: usize = 1000;
+
+/// Number of prior runs MH tries while looking for one that satisfies the observations.
+const MAX_MH_INIT_ATTEMPTS: usize = 10_000;
+
+/// Chance that an MH step proposes a whole new run from the prior instead of
+/// changing a single choice. Without these moves the chain can get stuck when
+/// a hard `observe` ties several choices together.
+const MH_GLOBAL_MOVE_PROB: f64 = 0.1;
+
 impl RuntimeEnv {
-    fn new() -> Self {
+    fn new(seed: Option<u64>) -> Self {
         RuntimeEnv {
             vars: HashMap::new(),
             funcs: HashMap::new(),
@@ -135,6 +576,11 @@ impl RuntimeEnv {
             enum_types: HashMap::new(),
             enum_variants: HashMap::new(),
             output: Vec::<OutputLine>::new(),
+            loop_depth: 0,
+            inference: Rc::new(RefCell::new(None)),
+            rng: SharedRng::new(seed),
+            nested_budget: None,
+            pb_calls: None,
         }
     }
 
@@ -156,11 +602,25 @@ impl RuntimeEnv {
             enum_types: self.enum_types.clone(),
             enum_variants: self.enum_variants.clone(),
             output: Vec::<OutputLine>::new(),
+            loop_depth: 0,
+            inference: Rc::clone(&self.inference),
+            rng: self.rng.clone(),
+            nested_budget: self.nested_budget.clone(),
+            pb_calls: self.pb_calls.clone(),
         }
     }
 
     // Fraction Helpers
 
+    /// Evaluate a distribution parameter as an exact `Fraction`, keeping exact
+    /// rationals as they are and converting floats with `float_to_frac`.
+    fn eval_frac(&self, expr: &Expr) -> Result<Fraction> {
+        match self.eval_expr(expr)? {
+            RuntimeValue::Frac(frac) => Ok(frac),
+            v => Ok(Self::float_to_frac(v.as_f64()?)),
+        }
+    }
+
     /// Convert a user-specified `f64` (parsed from source like `0.5`, `0.1`) to an
     /// exact `Fraction`.  Uses the shortest decimal string representation so that
     /// `0.1` → `1/10`, `0.5` → `1/2`, `0.75` → `3/4`, etc.
@@ -189,7 +649,7 @@ impl RuntimeEnv {
         if n >= 0 {
             Fraction::from(n as u64)
         } else {
-            -Fraction::from((-n) as u64)
+            -Fraction::from(n.unsigned_abs())
         }
     }
 
@@ -202,50 +662,48 @@ impl RuntimeEnv {
         (v * factor).round() / factor
     }
 
-    /// Raise `base` to the power `exp` using repeated multiplication.
-    fn pow_frac(base: Fraction, exp: u64) -> Fraction {
-        let mut result = Fraction::from(1u64);
-        for _ in 0..exp {
-            result = result * base.clone();
-        }
-        result
+    // Expression Evaluation
+
+    /// Evaluate `expr`, tagging any error with the span of the innermost
+    /// expression that raised it.
+    fn eval_expr(&self, expr: &Expr) -> Result<RuntimeValue> {
+        self.eval_expr_kind(&expr.kind).map_err(|e| e.with_span(expr.span))
     }
 
-    // Expression Evaluation
+    fn eval_expr_kind(&self, expr: &ExprKind) -> Result<RuntimeValue> {
+        Ok(match expr {
+            ExprKind::Int(n) => RuntimeValue::Int(*n),
+            ExprKind::Float(n) => RuntimeValue::Float(*n),
+            ExprKind::Frac(frac) => RuntimeValue::Frac(*frac),
+            ExprKind::Bool(b) => RuntimeValue::Bool(*b),
 
-    fn eval_expr(&self, expr: &Expr) -> RuntimeValue {
-        match expr {
-            Expr::Int(n) => RuntimeValue::Int(*n),
-            Expr::Float(n) => RuntimeValue::Float(*n),
-            Expr::Bool(b) => RuntimeValue::Bool(*b),
-
-            Expr::Var(name) => self
-                .vars
-                .get(name)
-                .cloned()
-                .unwrap_or_else(|| {
-                    // Check if it's a known enum variant (e.g. Sunny, Cloudy).
-                    if let Some(type_name) = self.enum_variants.get(name) {
-                        RuntimeValue::EnumVariant(type_name.clone(), name.clone())
-                    } else {
-                        panic!("Undefined variable: '{}'", name)
-                    }
-                }),
+            ExprKind::Var(name) => match self.vars.get(name) {
+                Some(v) => v.clone(),
+                // Check if it's a known enum variant (e.g. Sunny, Cloudy).
+                None => match self.enum_variants.get(name) {
+                    Some(type_name) => RuntimeValue::EnumVariant(type_name.clone(), name.clone()),
+                    // A function name used as a value.
+                    None => match self.named_func(name) {
+                        Some(func) => RuntimeValue::Func(func),
+                        None => return Err(YapplError::UndefinedVariable(name.clone())),
+                    },
+                },
+            },
 
             // Unary
-            Expr::Neg(inner) => match self.eval_expr(inner) {
-                RuntimeValue::Int(n) => RuntimeValue::Int(-n),
+            ExprKind::Neg(inner) => match self.eval_expr(inner)? {
+                RuntimeValue::Int(n) => RuntimeValue::Int(n.checked_neg().ok_or_else(int_overflow)?),
                 RuntimeValue::Float(n) => RuntimeValue::Float(-n),
-                v => panic!("Type error: cannot negate {}", v),
+                v => return Err(type_err!("cannot negate {}", v)),
             },
-            Expr::Not(inner) => match self.eval_expr(inner) {
+            ExprKind::Not(inner) => match self.eval_expr(inner)? {
                 RuntimeValue::Bool(b) => RuntimeValue::Bool(!b),
-                v => panic!("Type error: '!' requires bool, got {}", v),
+                v => return Err(type_err!("'!' requires bool, got {}", v)),
             },
 
             // Arithmetic
-            Expr::Add(a, b) => match (self.eval_expr(a), self.eval_expr(b)) {
-                (RuntimeValue::Int(x), RuntimeValue::Int(y)) => RuntimeValue::Int(x + y),
+            ExprKind::Add(a, b) => match (self.eval_expr(a)?, self.eval_expr(b)?) {
+                (RuntimeValue::Int(x), RuntimeValue::Int(y)) => RuntimeValue::Int(x.checked_add(y).ok_or_else(int_overflow)?),
                 (RuntimeValue::Float(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x + y),
                 (RuntimeValue::Int(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x as f64 + y),
                 (RuntimeValue::Float(x), RuntimeValue::Int(y)) => RuntimeValue::Float(x + y as f64),
@@ -260,10 +718,10 @@ impl RuntimeEnv {
                 (RuntimeValue::Dist(d1), RuntimeValue::Dist(d2)) => {
                     RuntimeValue::Dist(Dist::CombinedDist(Box::new(d1), Box::new(d2)))
                 }
-                (a, b) => panic!("Type error: cannot add {} and {}", a, b),
+                (a, b) => return Err(type_err!("cannot add {} and {}", a, b)),
             },
-            Expr::Sub(a, b) => match (self.eval_expr(a), self.eval_expr(b)) {
-                (RuntimeValue::Int(x), RuntimeValue::Int(y)) => RuntimeValue::Int(x - y),
+            ExprKind::Sub(a, b) => match (self.eval_expr(a)?, self.eval_expr(b)?) {
+                (RuntimeValue::Int(x), RuntimeValue::Int(y)) => RuntimeValue::Int(x.checked_sub(y).ok_or_else(int_overflow)?),
                 (RuntimeValue::Float(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x - y),
                 (RuntimeValue::Int(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x as f64 - y),
                 (RuntimeValue::Float(x), RuntimeValue::Int(y)) => RuntimeValue::Float(x - y as f64),
@@ -272,10 +730,10 @@ impl RuntimeEnv {
                 (RuntimeValue::Int(x),  RuntimeValue::Frac(y)) => RuntimeValue::Frac(Self::int_to_frac(x) - y),
                 (RuntimeValue::Frac(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x.to_f64().unwrap_or(0.0) - y),
                 (RuntimeValue::Float(x), RuntimeValue::Frac(y)) => RuntimeValue::Float(x - y.to_f64().unwrap_or(0.0)),
-                (a, b) => panic!("Type error: cannot subtract {} from {}", b, a),
+                (a, b) => return Err(type_err!("cannot subtract {} from {}", b, a)),
             },
-            Expr::Mul(a, b) => match (self.eval_expr(a), self.eval_expr(b)) {
-                (RuntimeValue::Int(x), RuntimeValue::Int(y)) => RuntimeValue::Int(x * y),
+            ExprKind::Mul(a, b) => match (self.eval_expr(a)?, self.eval_expr(b)?) {
+                (RuntimeValue::Int(x), RuntimeValue::Int(y)) => RuntimeValue::Int(x.checked_mul(y).ok_or_else(int_overflow)?),
                 (RuntimeValue::Float(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x * y),
                 (RuntimeValue::Int(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x as f64 * y),
                 (RuntimeValue::Float(x), RuntimeValue::Int(y)) => RuntimeValue::Float(x * y as f64),
@@ -284,11 +742,11 @@ impl RuntimeEnv {
                 (RuntimeValue::Int(x),  RuntimeValue::Frac(y)) => RuntimeValue::Frac(Self::int_to_frac(x) * y),
                 (RuntimeValue::Frac(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x.to_f64().unwrap_or(0.0) * y),
                 (RuntimeValue::Float(x), RuntimeValue::Frac(y)) => RuntimeValue::Float(x * y.to_f64().unwrap_or(0.0)),
-                (a, b) => panic!("Type error: cannot multiply {} and {}", a, b),
+                (a, b) => return Err(type_err!("cannot multiply {} and {}", a, b)),
             },
-            Expr::Div(a, b) => match (self.eval_expr(a), self.eval_expr(b)) {
+            ExprKind::Div(a, b) => match (self.eval_expr(a)?, self.eval_expr(b)?) {
                 (RuntimeValue::Int(x), RuntimeValue::Int(y)) => {
-                    if y == 0 { panic!("Runtime error: division by zero"); }
+                    if y == 0 { return Err(YapplError::DivisionByZero); }
                     RuntimeValue::Float(x as f64 / y as f64)
                 }
                 (RuntimeValue::Float(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x / y),
@@ -297,280 +755,380 @@ impl RuntimeEnv {
                 (RuntimeValue::Frac(x), RuntimeValue::Frac(y)) => RuntimeValue::Frac(x / y),
                 (RuntimeValue::Frac(x), RuntimeValue::Int(y))  => RuntimeValue::Frac(x / Self::int_to_frac(y)),
                 (RuntimeValue::Int(x),  RuntimeValue::Frac(y)) => RuntimeValue::Frac(Self::int_to_frac(x) / y),
-                (a, b) => panic!("Type error: cannot divide {} by {}", a, b),
+                (a, b) => return Err(type_err!("cannot divide {} by {}", a, b)),
             },
-            Expr::Mod(a, b) => match (self.eval_expr(a), self.eval_expr(b)) {
-                (RuntimeValue::Int(x), RuntimeValue::Int(y)) => RuntimeValue::Int(x.rem_euclid(y)),
+            ExprKind::Mod(a, b) => match (self.eval_expr(a)?, self.eval_expr(b)?) {
+                (RuntimeValue::Int(x), RuntimeValue::Int(y)) => {
+                    if y == 0 { return Err(YapplError::DivisionByZero); }
+                    RuntimeValue::Int(x.checked_rem_euclid(y).ok_or_else(int_overflow)?)
+                }
                 (RuntimeValue::Float(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x % y),
                 (RuntimeValue::Int(x), RuntimeValue::Float(y)) => RuntimeValue::Float(x as f64 % y),
                 (RuntimeValue::Float(x), RuntimeValue::Int(y)) => RuntimeValue::Float(x % y as f64),
-                (a, b) => panic!("Type error: cannot compute {} mod {}", a, b),
+                (a, b) => return Err(type_err!("cannot compute {} mod {}", a, b)),
             },
 
             // Comparison
-            Expr::Eq(a, b) => RuntimeValue::Bool(self.eval_numeric_eq(a, b)),
-            Expr::Neq(a, b) => RuntimeValue::Bool(!self.eval_numeric_eq(a, b)),
-            Expr::Lt(a, b) => RuntimeValue::Bool(self.eval_expr(a).as_f64() < self.eval_expr(b).as_f64()),
-            Expr::Lte(a, b) => RuntimeValue::Bool(self.eval_expr(a).as_f64() <= self.eval_expr(b).as_f64()),
-            Expr::Gt(a, b) => RuntimeValue::Bool(self.eval_expr(a).as_f64() > self.eval_expr(b).as_f64()),
-            Expr::Gte(a, b) => RuntimeValue::Bool(self.eval_expr(a).as_f64() >= self.eval_expr(b).as_f64()),
+            ExprKind::Eq(a, b) => RuntimeValue::Bool(self.eval_numeric_eq(a, b)?),
+            ExprKind::Neq(a, b) => RuntimeValue::Bool(!self.eval_numeric_eq(a, b)?),
+            ExprKind::Lt(a, b) => RuntimeValue::Bool(self.eval_expr(a)?.as_f64()? < self.eval_expr(b)?.as_f64()?),
+            ExprKind::Lte(a, b) => RuntimeValue::Bool(self.eval_expr(a)?.as_f64()? <= self.eval_expr(b)?.as_f64()?),
+            ExprKind::Gt(a, b) => RuntimeValue::Bool(self.eval_expr(a)?.as_f64()? > self.eval_expr(b)?.as_f64()?),
+            ExprKind::Gte(a, b) => RuntimeValue::Bool(self.eval_expr(a)?.as_f64()? >= self.eval_expr(b)?.as_f64()?),
 
             // Logical
-            Expr::And(a, b) => match (self.eval_expr(a), self.eval_expr(b)) {
+            ExprKind::And(a, b) => match (self.eval_expr(a)?, self.eval_expr(b)?) {
                 (RuntimeValue::Bool(x), RuntimeValue::Bool(y)) => RuntimeValue::Bool(x && y),
-                (a, b) => panic!("Type error: '&&' requires bool operands, got {} and {}", a, b),
+                (a, b) => return Err(type_err!("'&&' requires bool operands, got {} and {}", a, b)),
             },
-            Expr::Or(a, b) => match (self.eval_expr(a), self.eval_expr(b)) {
+            ExprKind::Or(a, b) => match (self.eval_expr(a)?, self.eval_expr(b)?) {
                 (RuntimeValue::Bool(x), RuntimeValue::Bool(y)) => RuntimeValue::Bool(x || y),
-                (a, b) => panic!("Type error: '||' requires bool operands, got {} and {}", a, b),
+                (a, b) => return Err(type_err!("'||' requires bool operands, got {} and {}", a, b)),
             },
 
             // Arrays
-            Expr::Array(elems) => {
-                RuntimeValue::Array(elems.iter().map(|e| self.eval_expr(e)).collect())
+            ExprKind::Array(elems) => {
+                RuntimeValue::Array(elems.iter().map(|e| self.eval_expr(e)).collect::<Result<_>>()?)
+            }
+
+            ExprKind::Index(arr, idx) => {
+                let elems = self.eval_array(arr)?;
+                let i = self.eval_expr(idx)?.as_int()?;
+                let pos = check_index(i, elems.len())?;
+                elems[pos].clone()
+            }
+            ExprKind::Slice { expr, start, end } => {
+                let elems = self.eval_array(expr)?;
+                let len = elems.len();
+                // Slice bounds may equal the length (an empty tail), unlike indices.
+                let bound = |e: &Expr| -> Result<usize> {
+                    let i = self.eval_expr(e)?.as_int()?;
+                    if i < 0 || i as usize > len {
+                        return Err(YapplError::IndexOutOfBounds { index: i, len });
+                    }
+                    Ok(i as usize)
+                };
+                let start = start.as_deref().map(bound).transpose()?.unwrap_or(0);
+                let end = end.as_deref().map(bound).transpose()?.unwrap_or(len);
+                if start > end {
+                    return Err(runtime_err!("slice start {} is greater than end {}", start, end));
+                }
+                RuntimeValue::Array(elems[start..end].to_vec())
             }
 
             // Distributions
-            Expr::Dist(d) => RuntimeValue::Dist(d.clone()),
+            ExprKind::Dist(Dist::Discrete(pairs)) => RuntimeValue::Dist(Dist::Discrete(
+                pairs
+                    .iter()
+                    .map(|(v, p)| Ok((Box::new(self.resolve_literal(v)?), Box::new(self.resolve_literal(p)?))))
+                    .collect::<Result<_>>()?,
+            )),
+            ExprKind::Dist(d) => RuntimeValue::Dist(d.clone()),
 
-            Expr::DistMethodCall { var, method, args } => {
+            ExprKind::DistMethodCall { var, method, args } => {
                 let dist = match self.vars.get(var) {
                     Some(RuntimeValue::Dist(d)) => d.clone(),
-                    Some(v) => panic!("Type error: '{}' is not a distribution (got {})", var, v),
-                    None => panic!("Undefined variable: {}", var),
+                    Some(v) => return Err(type_err!("'{}' is not a distribution (got {})", var, v)),
+                    None => return Err(YapplError::UndefinedVariable(var.clone())),
                 };
-                self.eval_dist_method(&dist, method, args)
+                self.eval_dist_method(&dist, method, args)?
             }
 
-            Expr::ExprMethodCall { expr, method, args } => {
-                match self.eval_expr(expr) {
-                    RuntimeValue::Dist(dist) => self.eval_dist_method(&dist, method, args),
+            ExprKind::ExprMethodCall { expr, method, args } => {
+                match self.eval_expr(expr)? {
+                    RuntimeValue::Dist(dist) => self.eval_dist_method(&dist, method, args)?,
                     RuntimeValue::DynDist(outcomes) => {
-                        self.eval_dyn_dist_method(outcomes, method, args)
+                        self.eval_dyn_dist_method(outcomes, method, args)?
                     }
-                    v => panic!("Type error: cannot call method '{}' on {}", method, v),
+                    RuntimeValue::Hmm(model) => self.eval_hmm_method(&model, method, args)?,
+                    v => return Err(type_err!("cannot call method '{}' on {}", method, v)),
                 }
             }
 
+            ExprKind::Field { expr, field } => match self.eval_expr(expr)? {
+                RuntimeValue::Info(info) => info.field(field)?,
+                RuntimeValue::Verification(report) => report.field(field)?,
+                v => return Err(type_err!("cannot read field '{}' of {}", field, v)),
+            },
+
             // Certainty markers
-            Expr::Certain(inner) => RuntimeValue::Certain(Box::new(self.eval_expr(inner))),
-            Expr::Uncertain(inner) => RuntimeValue::Uncertain(Box::new(self.eval_expr(inner))),
+            ExprKind::Certain(inner) => RuntimeValue::Certain(Box::new(self.eval_expr(inner)?)),
+            ExprKind::Fail => RuntimeValue::Fail,
+            ExprKind::Uncertain(inner) => RuntimeValue::Uncertain(Box::new(self.eval_expr(inner)?)),
 
             // Function calls
-            Expr::FuncCall(name, args) => self.eval_func_call(name, args),
+            ExprKind::FuncCall(name, args) => self.eval_func_call(name, args)?,
+            ExprKind::Lambda { params, body } => self.make_closure(params, body),
+            ExprKind::Match { scrutinee, arms } => self.eval_match(scrutinee, arms)?,
 
             // Approximate equality
-            Expr::ApproxEq(a, b, tol_expr) => {
-                let tolerance = tol_expr
-                    .as_ref()
-                    .map(|e| self.eval_expr(e).as_f64())
-                    .unwrap_or(0.05);
-                let result = match (self.eval_expr(a), self.eval_expr(b)) {
+            ExprKind::ApproxEq(a, b, tol_expr) => {
+                let tolerance = match tol_expr {
+                    Some(e) => self.eval_expr(e)?.as_f64()?,
+                    None => 0.05,
+                };
+                let result = match (self.eval_expr(a)?, self.eval_expr(b)?) {
                     (RuntimeValue::Dist(d1), RuntimeValue::Dist(d2)) => {
-                        self.dist_approx_eq(&d1, &d2, tolerance)
+                        self.dist_approx_eq(&d1, &d2, tolerance)?
                     }
-                    (lhs, rhs) => (lhs.as_f64() - rhs.as_f64()).abs() <= tolerance,
+                    (lhs @ RuntimeValue::DynDist(_), rhs) | (lhs, rhs @ RuntimeValue::DynDist(_)) => {
+                        total_variation(&self.outcome_weights(&lhs)?, &self.outcome_weights(&rhs)?) <= tolerance
+                    }
+                    (lhs, rhs) => (lhs.as_f64()? - rhs.as_f64()?).abs() <= tolerance,
                 };
                 RuntimeValue::Bool(result)
             }
-        }
+        })
     }
 
-    fn eval_numeric_eq(&self, a: &Expr, b: &Expr) -> bool {
-        match (self.eval_expr(a), self.eval_expr(b)) {
+    fn eval_numeric_eq(&self, a: &Expr, b: &Expr) -> Result<bool> {
+        Ok(match (self.eval_expr(a)?, self.eval_expr(b)?) {
             (RuntimeValue::Bool(x), RuntimeValue::Bool(y)) => x == y,
-            (RuntimeValue::Dist(d1), RuntimeValue::Dist(d2)) => self.dist_exact_eq(&d1, &d2),
+            (RuntimeValue::Dist(d1), RuntimeValue::Dist(d2)) => self.dist_exact_eq(&d1, &d2)?,
+            (lhs @ RuntimeValue::DynDist(_), rhs) | (lhs, rhs @ RuntimeValue::DynDist(_)) => {
+                total_variation(&self.outcome_weights(&lhs)?, &self.outcome_weights(&rhs)?) < 1e-12
+            }
             (RuntimeValue::EnumVariant(t1, v1), RuntimeValue::EnumVariant(t2, v2)) => {
                 t1 == t2 && v1 == v2
             }
-            (lhs, rhs) => lhs.as_f64() == rhs.as_f64(),
-        }
+            (RuntimeValue::Fail, rhs) => matches!(rhs, RuntimeValue::Fail),
+            (_, RuntimeValue::Fail) => false,
+            (lhs, rhs) => lhs.as_f64()? == rhs.as_f64()?,
+        })
     }
 
     // Distribution Equality
 
     /// Exact structural equality: same distribution type and identical evaluated parameters.
-    fn dist_exact_eq(&self, d1: &Dist, d2: &Dist) -> bool {
-        match (d1, d2) {
+    fn dist_exact_eq(&self, d1: &Dist, d2: &Dist) -> Result<bool> {
+        Ok(match (d1, d2) {
             (Dist::Uniform(a1, b1), Dist::Uniform(a2, b2)) => {
-                self.eval_expr(a1).as_f64() == self.eval_expr(a2).as_f64()
-                    && self.eval_expr(b1).as_f64() == self.eval_expr(b2).as_f64()
+                self.eval_expr(a1)?.as_f64()? == self.eval_expr(a2)?.as_f64()?
+                    && self.eval_expr(b1)?.as_f64()? == self.eval_expr(b2)?.as_f64()?
             }
             (Dist::UniformContinuous(a1, b1), Dist::UniformContinuous(a2, b2)) => {
-                self.eval_expr(a1).as_f64() == self.eval_expr(a2).as_f64()
-                    && self.eval_expr(b1).as_f64() == self.eval_expr(b2).as_f64()
+                self.eval_expr(a1)?.as_f64()? == self.eval_expr(a2)?.as_f64()?
+                    && self.eval_expr(b1)?.as_f64()? == self.eval_expr(b2)?.as_f64()?
             }
             (Dist::Bernoulli(p1), Dist::Bernoulli(p2)) => {
-                self.eval_expr(p1).as_f64() == self.eval_expr(p2).as_f64()
+                self.eval_expr(p1)?.as_f64()? == self.eval_expr(p2)?.as_f64()?
             }
             (Dist::Binomial(n1, p1), Dist::Binomial(n2, p2)) => {
-                self.eval_expr(n1).as_f64() == self.eval_expr(n2).as_f64()
-                    && self.eval_expr(p1).as_f64() == self.eval_expr(p2).as_f64()
+                self.eval_expr(n1)?.as_f64()? == self.eval_expr(n2)?.as_f64()?
+                    && self.eval_expr(p1)?.as_f64()? == self.eval_expr(p2)?.as_f64()?
             }
             (Dist::Geometric(p1), Dist::Geometric(p2)) => {
-                self.eval_expr(p1).as_f64() == self.eval_expr(p2).as_f64()
+                self.eval_expr(p1)?.as_f64()? == self.eval_expr(p2)?.as_f64()?
             }
-            (Dist::Beta(a1, b1), Dist::Beta(a2, b2)) => {
-                self.eval_expr(a1).as_f64() == self.eval_expr(a2).as_f64()
-                    && self.eval_expr(b1).as_f64() == self.eval_expr(b2).as_f64()
+            (Dist::Beta(a1, b1), Dist::Beta(a2, b2))
+            | (Dist::Normal(a1, b1), Dist::Normal(a2, b2))
+            | (Dist::Gamma(a1, b1), Dist::Gamma(a2, b2))
+            | (Dist::NegativeBinomial(a1, b1), Dist::NegativeBinomial(a2, b2)) => {
+                self.eval_expr(a1)?.as_f64()? == self.eval_expr(a2)?.as_f64()?
+                    && self.eval_expr(b1)?.as_f64()? == self.eval_expr(b2)?.as_f64()?
+            }
+            (Dist::Poisson(l1), Dist::Poisson(l2))
+            | (Dist::Exponential(l1), Dist::Exponential(l2)) => {
+                self.eval_expr(l1)?.as_f64()? == self.eval_expr(l2)?.as_f64()?
             }
             (Dist::Discrete(pairs1), Dist::Discrete(pairs2)) => {
                 if pairs1.len() != pairs2.len() {
-                    return false;
+                    return Ok(false);
                 }
-                let mut map1: HashMap<i64, f64> = HashMap::new();
+                // Keys may be numbers or enum variants.
+                let mut map1: HashMap<String, f64> = HashMap::new();
                 for (v, p) in pairs1 {
-                    map1.insert(self.eval_expr(v).as_f64() as i64, self.eval_expr(p).as_f64());
+                    map1.insert(Self::dyn_key(&self.eval_expr(v)?), self.eval_expr(p)?.as_f64()?);
                 }
                 for (v, p) in pairs2 {
-                    let key = self.eval_expr(v).as_f64() as i64;
+                    let key = Self::dyn_key(&self.eval_expr(v)?);
                     match map1.get(&key) {
-                        Some(&p1) if (p1 - self.eval_expr(p).as_f64()).abs() < 1e-12 => {}
-                        _ => return false,
+                        Some(&p1) if (p1 - self.eval_expr(p)?.as_f64()?).abs() < 1e-12 => {}
+                        _ => return Ok(false),
                     }
                 }
                 true
             }
             (Dist::CombinedDist(d1a, d1b), Dist::CombinedDist(d2a, d2b)) => {
-                self.dist_exact_eq(d1a, d2a) && self.dist_exact_eq(d1b, d2b)
+                self.dist_exact_eq(d1a, d2a)? && self.dist_exact_eq(d1b, d2b)?
             }
             _ => false,
-        }
+        })
     }
 
     /// Returns true if the distribution is discrete (can be enumerated as integer outcomes).
     fn dist_is_discrete(&self, dist: &Dist) -> bool {
         match dist {
             Dist::Uniform(_,_) | Dist::Discrete(_) | Dist::Bernoulli(_)
-            | Dist::Binomial(_,_) | Dist::Geometric(_) => true,
+            | Dist::Binomial(_,_) | Dist::Geometric(_)
+            | Dist::Poisson(_) | Dist::NegativeBinomial(_,_) => true,
             Dist::CombinedDist(d1, d2) => self.dist_is_discrete(d1) && self.dist_is_discrete(d2),
-            Dist::UniformContinuous(_,_) | Dist::Beta(_,_) => false,
+            Dist::UniformContinuous(_,_) | Dist::Beta(_,_)
+            | Dist::Normal(_,_) | Dist::Exponential(_) | Dist::Gamma(_,_) => false,
         }
     }
 
     /// Analytical mean of a distribution as f64.
-    fn dist_mean_f64(&self, dist: &Dist) -> f64 {
-        match dist {
+    fn dist_mean_f64(&self, dist: &Dist) -> Result<f64> {
+        Ok(match dist {
             Dist::Uniform(a, b) | Dist::UniformContinuous(a, b) => {
-                (self.eval_expr(a).as_f64() + self.eval_expr(b).as_f64()) / 2.0
+                (self.eval_expr(a)?.as_f64()? + self.eval_expr(b)?.as_f64()?) / 2.0
             }
-            Dist::Bernoulli(p) => self.eval_expr(p).as_f64(),
-            Dist::Binomial(n, p) => self.eval_expr(n).as_f64() * self.eval_expr(p).as_f64(),
-            Dist::Geometric(p) => 1.0 / self.eval_expr(p).as_f64(),
+            Dist::Bernoulli(p) => self.eval_expr(p)?.as_f64()?,
+            Dist::Binomial(n, p) => self.eval_expr(n)?.as_f64()? * self.eval_expr(p)?.as_f64()?,
+            Dist::Geometric(p) => 1.0 / self.eval_expr(p)?.as_f64()?,
             Dist::Beta(alpha, beta) => {
-                let a = self.eval_expr(alpha).as_f64();
-                let b = self.eval_expr(beta).as_f64();
+                let a = self.eval_expr(alpha)?.as_f64()?;
+                let b = self.eval_expr(beta)?.as_f64()?;
                 a / (a + b)
             }
             Dist::Discrete(_) => {
-                let outcomes = self.get_dist_outcomes(dist);
+                let outcomes = self.get_dist_outcomes(dist)?;
                 outcomes.iter()
-                    .map(|(v, p)| *v as f64 * p.to_f64().unwrap_or(0.0))
+                    .map(|(v, p)| *v as f64 * p.to_f64())
                     .sum()
             }
-            Dist::CombinedDist(d1, d2) => self.dist_mean_f64(d1) + self.dist_mean_f64(d2),
-            _ => panic!("dist_mean_f64: unsupported distribution type"),
-        }
+            Dist::CombinedDist(d1, d2) => self.dist_mean_f64(d1)? + self.dist_mean_f64(d2)?,
+            Dist::Normal(mu, _) => self.eval_expr(mu)?.as_f64()?,
+            Dist::Poisson(lambda) => self.eval_expr(lambda)?.as_f64()?,
+            Dist::Exponential(rate) => 1.0 / self.eval_expr(rate)?.as_f64()?,
+            Dist::Gamma(shape, scale) => self.eval_expr(shape)?.as_f64()? * self.eval_expr(scale)?.as_f64()?,
+            Dist::NegativeBinomial(r, p) => {
+                let pv = self.eval_expr(p)?.as_f64()?;
+                self.eval_expr(r)?.as_f64()? * (1.0 - pv) / pv
+            }
+        })
     }
 
     /// Analytical variance of a distribution as f64.
-    fn dist_variance_f64(&self, dist: &Dist) -> f64 {
-        match dist {
+    fn dist_variance_f64(&self, dist: &Dist) -> Result<f64> {
+        Ok(match dist {
             Dist::Uniform(a, b) => {
-                let n = self.eval_expr(b).as_f64() - self.eval_expr(a).as_f64() + 1.0;
+                let n = self.eval_expr(b)?.as_f64()? - self.eval_expr(a)?.as_f64()? + 1.0;
                 (n * n - 1.0) / 12.0
             }
             Dist::UniformContinuous(a, b) => {
-                let range = self.eval_expr(b).as_f64() - self.eval_expr(a).as_f64();
+                let range = self.eval_expr(b)?.as_f64()? - self.eval_expr(a)?.as_f64()?;
                 range * range / 12.0
             }
             Dist::Bernoulli(p) => {
-                let pv = self.eval_expr(p).as_f64();
+                let pv = self.eval_expr(p)?.as_f64()?;
                 pv * (1.0 - pv)
             }
             Dist::Binomial(n, p) => {
-                let nv = self.eval_expr(n).as_f64();
-                let pv = self.eval_expr(p).as_f64();
+                let nv = self.eval_expr(n)?.as_f64()?;
+                let pv = self.eval_expr(p)?.as_f64()?;
                 nv * pv * (1.0 - pv)
             }
             Dist::Geometric(p) => {
-                let pv = self.eval_expr(p).as_f64();
+                let pv = self.eval_expr(p)?.as_f64()?;
                 (1.0 - pv) / (pv * pv)
             }
             Dist::Beta(alpha, beta) => {
-                let a = self.eval_expr(alpha).as_f64();
-                let b = self.eval_expr(beta).as_f64();
+                let a = self.eval_expr(alpha)?.as_f64()?;
+                let b = self.eval_expr(beta)?.as_f64()?;
                 let s = a + b;
                 a * b / (s * s * (s + 1.0))
             }
             Dist::Discrete(_) => {
-                let mean = self.dist_mean_f64(dist);
-                let outcomes = self.get_dist_outcomes(dist);
+                let mean = self.dist_mean_f64(dist)?;
+                let outcomes = self.get_dist_outcomes(dist)?;
                 outcomes.iter()
                     .map(|(v, p)| {
                         let d = *v as f64 - mean;
-                        d * d * p.to_f64().unwrap_or(0.0)
+                        d * d * p.to_f64()
                     })
                     .sum()
             }
             Dist::CombinedDist(d1, d2) => {
-                self.dist_variance_f64(d1) + self.dist_variance_f64(d2)
+                self.dist_variance_f64(d1)? + self.dist_variance_f64(d2)?
             }
-            _ => panic!("dist_variance_f64: unsupported distribution type"),
-        }
+            Dist::Normal(_, sigma) => self.eval_expr(sigma)?.as_f64()?.powi(2),
+            Dist::Poisson(lambda) => self.eval_expr(lambda)?.as_f64()?,
+            Dist::Exponential(rate) => 1.0 / self.eval_expr(rate)?.as_f64()?.powi(2),
+            Dist::Gamma(shape, scale) => {
+                self.eval_expr(shape)?.as_f64()? * self.eval_expr(scale)?.as_f64()?.powi(2)
+            }
+            Dist::NegativeBinomial(r, p) => {
+                let pv = self.eval_expr(p)?.as_f64()?;
+                self.eval_expr(r)?.as_f64()? * (1.0 - pv) / (pv * pv)
+            }
+        })
     }
 
     /// Approximate equality using Total Variation distance (discrete) or moment comparison (continuous).
-    fn dist_approx_eq(&self, d1: &Dist, d2: &Dist, tolerance: f64) -> bool {
+    fn dist_approx_eq(&self, d1: &Dist, d2: &Dist, tolerance: f64) -> Result<bool> {
         let disc1 = self.dist_is_discrete(d1);
         let disc2 = self.dist_is_discrete(d2);
 
         if disc1 && disc2 {
             // Total Variation distance: 0.5 * Σ|p1(x) - p2(x)|
-            let outcomes1 = self.get_dist_outcomes(d1);
-            let outcomes2 = self.get_dist_outcomes(d2);
+            // Outcomes are keyed by label so enum-valued distributions compare too.
+            let outcomes1 = self.get_dist_outcomes_dynamic(d1)?;
+            let outcomes2 = self.get_dist_outcomes_dynamic(d2)?;
 
-            let mut map1: HashMap<i64, f64> = HashMap::new();
+            let mut map1: HashMap<String, f64> = HashMap::new();
             for (v, p) in &outcomes1 {
-                *map1.entry(*v).or_insert(0.0) += p.to_f64().unwrap_or(0.0);
+                *map1.entry(Self::dyn_key(v)).or_insert(0.0) += p.to_f64();
             }
-            let mut map2: HashMap<i64, f64> = HashMap::new();
+            let mut map2: HashMap<String, f64> = HashMap::new();
             for (v, p) in &outcomes2 {
-                *map2.entry(*v).or_insert(0.0) += p.to_f64().unwrap_or(0.0);
+                *map2.entry(Self::dyn_key(v)).or_insert(0.0) += p.to_f64();
             }
 
-            // Union of all keys
-            let mut all_keys: Vec<i64> = map1.keys().chain(map2.keys()).cloned().collect();
-            all_keys.sort_unstable();
-            all_keys.dedup();
-
-            let tv: f64 = all_keys.iter()
-                .map(|k| {
-                    let p1 = map1.get(k).cloned().unwrap_or(0.0);
-                    let p2 = map2.get(k).cloned().unwrap_or(0.0);
-                    (p1 - p2).abs()
-                })
-                .sum::<f64>() * 0.5;
-
-            tv <= tolerance
+            Ok(total_variation(&map1, &map2) <= tolerance)
         } else if !disc1 && !disc2 {
             // CLT-inspired moment comparison: compare mean and std.
             // Both must be within `tolerance` (as a relative fraction of the larger std).
-            let mean1 = self.dist_mean_f64(d1);
-            let mean2 = self.dist_mean_f64(d2);
-            let std1 = self.dist_variance_f64(d1).sqrt();
-            let std2 = self.dist_variance_f64(d2).sqrt();
+            let mean1 = self.dist_mean_f64(d1)?;
+            let mean2 = self.dist_mean_f64(d2)?;
+            let std1 = self.dist_variance_f64(d1)?.sqrt();
+            let std2 = self.dist_variance_f64(d2)?.sqrt();
 
             let scale = std1.max(std2).max(1e-10);
-            (mean1 - mean2).abs() / scale <= tolerance
-                && (std1 - std2).abs() / scale <= tolerance
+            Ok((mean1 - mean2).abs() / scale <= tolerance
+                && (std1 - std2).abs() / scale <= tolerance)
         } else {
-            panic!("Cannot use ~= between a discrete and a continuous distribution")
+            Err(type_err!("cannot use ~= between a discrete and a continuous distribution"))
+        }
+    }
+
+    /// The probability of each outcome of a discrete distribution or DynDist,
+    /// keyed so that equal numbers (and booleans, as 0 and 1) share an entry.
+    fn outcome_weights(&self, value: &RuntimeValue) -> Result<HashMap<String, f64>> {
+        let outcomes = match value {
+            RuntimeValue::Dist(d) if self.dist_is_discrete(d) => self.get_dist_outcomes_dynamic(d)?,
+            RuntimeValue::Dist(_) => {
+                return Err(type_err!("cannot compare a continuous distribution with a DynDist"));
+            }
+            RuntimeValue::DynDist(outcomes) => outcomes.clone(),
+            v => return Err(type_err!("expected a distribution, got {}", v)),
+        };
+        let mut weights: HashMap<String, f64> = HashMap::new();
+        for (v, p) in &outcomes {
+            let key = match exact_number(v) {
+                Some(x) => x.to_f64().to_string(),
+                None => Self::dyn_key(v),
+            };
+            *weights.entry(key).or_insert(0.0) += p.to_f64();
         }
+        Ok(weights)
     }
 
     // Markov Chain / Dynamic Distribution Helpers
 
+    /// Evaluate `expr` to a literal with the same value, so a `Discrete` built
+    /// from local variables (e.g. a function's parameters) keeps its values
+    /// after leaving their scope. Literals and enum variants are kept as written.
+    fn resolve_literal(&self, expr: &Expr) -> Result<Expr> {
+        if matches!(&expr.kind, ExprKind::Int(_) | ExprKind::Float(_) | ExprKind::Frac(_) | ExprKind::Bool(_))
+            || matches!(&expr.kind, ExprKind::Var(name) if !self.vars.contains_key(name) && self.enum_variants.contains_key(name))
+        {
+            return Ok(expr.clone());
+        }
+        Ok(literal_expr(&self.eval_expr(expr)?, expr.span).unwrap_or_else(|| expr.clone()))
+    }
+
     /// A stable key for merging outcomes in bind/step.
     /// Uses "TypeName::Variant" for enum variants to avoid cross-enum collisions.
     fn dyn_key(v: &RuntimeValue) -> String {
@@ -583,23 +1141,23 @@ impl RuntimeEnv {
     /// Enumerate (state, probability) pairs for any distribution as general `RuntimeValue`s.
     /// For enum-keyed `Discrete` distributions this evaluates each key expression and returns
     /// `RuntimeValue::EnumVariant`; for numeric distributions it wraps values as `RuntimeValue::Int`.
-    fn get_dist_outcomes_dynamic(&self, dist: &Dist) -> Vec<(RuntimeValue, f64)> {
+    fn get_dist_outcomes_dynamic(&self, dist: &Dist) -> Result<Vec<(RuntimeValue, Prob)>> {
         match dist {
             // General Discrete: keys can be enum variants or numbers.
             Dist::Discrete(pairs) => pairs
                 .iter()
                 .map(|(v, p)| {
-                    let val = self.eval_expr(v);
-                    let prob = self.eval_expr(p).as_f64();
-                    (val, prob)
+                    let val = self.eval_expr(v)?;
+                    let prob = self.eval_frac(p)?;
+                    Ok((val, Prob::Exact(prob)))
                 })
                 .collect(),
             // All other distributions produce integer outcomes.
-            _ => self
-                .get_dist_outcomes(dist)
+            _ => Ok(self
+                .get_dist_outcomes(dist)?
                 .into_iter()
-                .map(|(v, p)| (RuntimeValue::Int(v), p.to_f64().unwrap_or(0.0)))
-                .collect(),
+                .map(|(v, p)| (RuntimeValue::Int(v), p))
+                .collect()),
         }
     }
 
@@ -607,44 +1165,142 @@ impl RuntimeEnv {
     /// Given a distribution over states and a transition function S → Dist<S>,
     /// computes the resulting marginal distribution over new states by:
     ///   result(s') = Σ_s  P(s) · P_func(s)(s')
-    fn eval_bind(&self, dist_val: RuntimeValue, func_name: &str) -> RuntimeValue {
-        let outcomes: Vec<(RuntimeValue, f64)> = match &dist_val {
-            RuntimeValue::Dist(d) => self.get_dist_outcomes_dynamic(d),
+    fn eval_bind(&self, dist_val: RuntimeValue, func: &FuncValue) -> Result<RuntimeValue> {
+        let outcomes: Vec<(RuntimeValue, Prob)> = match &dist_val {
+            RuntimeValue::Dist(d) => self.value_outcomes(d)?,
             RuntimeValue::DynDist(o) => o.clone(),
-            v => panic!("bind() first argument must be a distribution, got {}", v),
+            v => return Err(type_err!("bind() first argument must be a distribution, got {}", v)),
         };
+        Ok(RuntimeValue::DynDist(self.bind_outcomes(&outcomes, func)?))
+    }
 
-        let func = self
-            .funcs
-            .get(func_name)
-            .cloned()
-            .unwrap_or_else(|| panic!("bind(): undefined function '{}'", func_name));
-
+    /// The outcomes of `bind()`, merged and sorted by state.
+    fn bind_outcomes(&self, outcomes: &[(RuntimeValue, Prob)], func: &FuncValue) -> Result<Vec<(RuntimeValue, Prob)>> {
         // Merge new outcomes: key → (RuntimeValue, accumulated_prob)
-        let mut merged: HashMap<String, (RuntimeValue, f64)> = HashMap::new();
+        let mut merged: HashMap<String, (RuntimeValue, Prob)> = HashMap::new();
 
-        for (state, prior_prob) in &outcomes {
-            let new_dist_val = self.call_func(&func, std::slice::from_ref(state));
-            let new_outcomes: Vec<(RuntimeValue, f64)> = match &new_dist_val {
-                RuntimeValue::Dist(d) => self.get_dist_outcomes_dynamic(d),
-                RuntimeValue::DynDist(o) => o.clone(),
-                v => panic!(
-                    "Transition function '{}' must return a distribution, got {}",
-                    func_name, v
-                ),
-            };
-            for (new_state, new_prob) in new_outcomes {
+        for (state, prior_prob) in outcomes {
+            for (new_state, new_prob) in self.call_transition(func, state)? {
                 let key = Self::dyn_key(&new_state);
-                let entry = merged.entry(key).or_insert((new_state, 0.0));
-                entry.1 += prior_prob * new_prob;
+                let entry = merged.entry(key).or_insert((new_state, Prob::zero()));
+                entry.1 = entry.1 + *prior_prob * new_prob;
+            }
+        }
+
+        Ok(sorted_outcomes(merged))
+    }
+
+    /// Resolve the function argument of a higher-order builtin: a lambda, a
+    /// variable holding a function, or the name of a regular or pb function.
+    fn func_arg(&self, builtin: &str, position: &str, expr: &Expr) -> Result<Rc<FuncValue>> {
+        if let ExprKind::Var(name) = &expr.kind
+            && !self.vars.contains_key(name)
+            && !self.enum_variants.contains_key(name)
+            && self.named_func(name).is_none()
+        {
+            return Err(YapplError::UndefinedFunction(name.clone()));
+        }
+        match self.eval_expr(expr)? {
+            RuntimeValue::Func(func) => Ok(func),
+            v => Err(type_err!("{}() {} argument must be a function, got {}", builtin, position, v)),
+        }
+    }
+
+    /// A named regular or pb function as a value.
+    fn named_func(&self, name: &str) -> Option<Rc<FuncValue>> {
+        let (params, body, pb) = match (self.funcs.get(name), self.pb_funcs.get(name)) {
+            (Some(func), _) => (func.params.clone(), func.body.clone(), false),
+            (None, Some(func)) => (func.params.clone(), func.body.clone(), true),
+            (None, None) => return None,
+        };
+        Some(Rc::new(FuncValue { name: name.to_string(), params, body, captured: HashMap::new(), pb }))
+    }
+
+    /// The body of the first arm whose pattern matches the scrutinee, evaluated
+    /// with that pattern's names bound.
+    fn eval_match(&self, scrutinee: &Expr, arms: &[MatchArm<Expr>]) -> Result<RuntimeValue> {
+        let value = self.eval_expr(scrutinee)?;
+        let (body, bindings) = select_arm(&value, arms)?;
+        if bindings.is_empty() {
+            return self.eval_expr(body);
+        }
+        let mut env = self.new_child();
+        env.vars = self.vars.clone();
+        env.vars.extend(bindings);
+        env.eval_expr(body)
+    }
+
+    /// A lambda closing over the variables currently in scope.
+    fn make_closure(&self, params: &[FuncParam], body: &[Statement]) -> RuntimeValue {
+        RuntimeValue::Func(Rc::new(FuncValue {
+            name: "lambda".to_string(),
+            params: params.to_vec(),
+            body: body.to_vec(),
+            captured: self.vars.clone(),
+            pb: false,
+        }))
+    }
+
+    /// Call a function value like a regular function.
+    /// Inside a pb function a pb function value is a nested call, as when
+    /// called by name.
+    fn call_func_value(&self, func: &FuncValue, args: &[RuntimeValue]) -> Result<RuntimeValue> {
+        if func.pb {
+            if self.nested_budget.is_some() && self.pb_funcs.contains_key(&func.name) {
+                return self.call_nested_pb_func(&func.name, args);
             }
+            return Err(pb_call_err(&func.name));
+        }
+        self.call_closure(func, args)
+    }
+
+    /// Run a function value's body on `args`, in an environment holding the
+    /// variables it captured.
+    fn call_closure(&self, func: &FuncValue, args: &[RuntimeValue]) -> Result<RuntimeValue> {
+        check_arity(&func.name, func.params.len(), args.len())?;
+        let mut env = self.new_child();
+        env.vars = func.captured.clone();
+        for (param, arg) in func.params.iter().zip(args.iter()) {
+            env.vars.insert(param.name.clone(), arg.clone());
+        }
+        match env.exec_block(&func.body)? {
+            FlowControl::Return(val) => Ok(val),
+            _ => Ok(RuntimeValue::Int(0)), // implicit return 0 if no return statement
+        }
+    }
+
+    /// The next-state distribution of a transition function (or an HMM
+    /// emission) at `state`. The function may return a distribution, or
+    /// sample and return a value, in which case every path through its
+    /// random choices is enumerated. A pb function's rounds only bound its
+    /// error, so it cannot be read as a next-state distribution.
+    fn call_transition(&self, func: &FuncValue, state: &RuntimeValue) -> Result<Vec<(RuntimeValue, Prob)>> {
+        if func.pb {
+            return Err(contract_err!(
+                "'{}' is a probabilistic function and cannot be used as a transition function",
+                func.name
+            ));
         }
+        let outer = self.inference.borrow_mut().take();
+        let what = format!("transition function '{}'", func.name);
+        let paths = self.enumerate_paths(&what, MAX_ENUMERATION_PATHS, || {
+            self.call_closure(func, std::slice::from_ref(state))
+        });
+        *self.inference.borrow_mut() = outer;
 
-        let mut result: Vec<(RuntimeValue, f64)> =
-            merged.into_values().collect();
-        // Sort by display label for deterministic output.
-        result.sort_by(|(a, _), (b, _)| format!("{}", a).cmp(&format!("{}", b)));
-        RuntimeValue::DynDist(result)
+        let mut merged: HashMap<String, (RuntimeValue, Prob)> = HashMap::new();
+        for (value, weight) in paths? {
+            let outcomes = match value {
+                RuntimeValue::Dist(d) => self.value_outcomes(&d)?,
+                RuntimeValue::DynDist(o) => o,
+                v => vec![(v, Prob::one())],
+            };
+            for (next, prob) in outcomes {
+                let entry = merged.entry(Self::dyn_key(&next)).or_insert((next, Prob::zero()));
+                entry.1 = entry.1 + weight * prob;
+            }
+        }
+        Ok(sorted_outcomes(merged))
     }
 
     /// Apply a Markov transition function `n` times starting from `initial_state`.
@@ -652,30 +1308,212 @@ impl RuntimeEnv {
     fn eval_step(
         &self,
         initial: RuntimeValue,
-        func_name: &str,
+        func: &FuncValue,
         n: usize,
-    ) -> RuntimeValue {
+    ) -> Result<RuntimeValue> {
         // Start from a delta distribution concentrated on the initial state.
-        let mut current = RuntimeValue::DynDist(vec![(initial, 1.0)]);
+        let mut current = RuntimeValue::DynDist(vec![(initial, Prob::one())]);
         for _ in 0..n {
-            current = self.eval_bind(current, func_name);
+            current = self.eval_bind(current, func)?;
+        }
+        Ok(current)
+    }
+
+    /// Exact inference on a hidden Markov model, given an array of observations:
+    ///   forward(obs)    → DynDist over the hidden state at the last observation
+    ///   likelihood(obs) → probability of the observations, as a fraction
+    ///   viterbi(obs)    → the most likely array of hidden states
+    fn eval_hmm_method(&self, model: &HmmModel, method: &str, args: &[Expr]) -> Result<RuntimeValue> {
+        if !matches!(method, "forward" | "likelihood" | "viterbi") {
+            return Err(type_err!(
+                "unknown HMM method '{}' (expected forward, likelihood or viterbi)",
+                method
+            ));
+        }
+        check_arity(method, 1, args.len())?;
+        let observations = self.eval_array(&args[0])?;
+        if observations.is_empty() {
+            return Err(runtime_err!("{}() needs at least one observation", method));
+        }
+        let impossible = || runtime_err!("{}(): the observations have probability 0 under {}", method, model);
+
+        if method == "viterbi" {
+            let (path, prob) = self.hmm_viterbi(model, &observations)?;
+            if prob.is_zero() {
+                return Err(impossible());
+            }
+            return Ok(RuntimeValue::Array(path));
+        }
+        let alpha = self.hmm_forward(model, &observations)?;
+        let likelihood = alpha.iter().fold(Prob::zero(), |acc, (_, p)| acc + *p);
+        if method == "likelihood" {
+            return Ok(prob_value(likelihood));
+        }
+        if likelihood.is_zero() {
+            return Err(impossible());
+        }
+        Ok(RuntimeValue::DynDist(alpha.into_iter().map(|(s, p)| (s, p / likelihood)).collect()))
+    }
+
+    /// The forward algorithm: α(s) = P(observations so far, current state = s).
+    /// Each step predicts the next state with `bind` and then weights every
+    /// state by the probability that it emits the next observation.
+    fn hmm_forward(&self, model: &HmmModel, observations: &[RuntimeValue]) -> Result<Vec<(RuntimeValue, Prob)>> {
+        let mut alpha = model.initial.clone();
+        for (t, observation) in observations.iter().enumerate() {
+            if t > 0 {
+                alpha = self.bind_outcomes(&alpha, &model.transition)?;
+            }
+            alpha = alpha
+                .into_iter()
+                .map(|(state, p)| {
+                    let emitted = outcome_prob(&self.call_transition(&model.emission, &state)?, observation);
+                    Ok((state, p * emitted))
+                })
+                .collect::<Result<_>>()?;
+        }
+        Ok(alpha)
+    }
+
+    /// The Viterbi algorithm: for each state, keep only the most likely path
+    /// ending there, extending every kept path by one transition per
+    /// observation. Returns the best final path and its joint probability.
+    fn hmm_viterbi(&self, model: &HmmModel, observations: &[RuntimeValue]) -> Result<(Vec<RuntimeValue>, Prob)> {
+        let emit = |state: &RuntimeValue, observation: &RuntimeValue| -> Result<Prob> {
+            Ok(outcome_prob(&self.call_transition(&model.emission, state)?, observation))
+        };
+
+        let mut best: Vec<(Vec<RuntimeValue>, Prob)> = Vec::new();
+        for (state, p) in &model.initial {
+            best.push((vec![state.clone()], *p * emit(state, &observations[0])?));
+        }
+        for observation in &observations[1..] {
+            // State key → (best path ending in that state, its probability).
+            let mut next: HashMap<String, (Vec<RuntimeValue>, Prob)> = HashMap::new();
+            for (path, p) in best.iter().filter(|(_, p)| !p.is_zero()) {
+                let last = path.last().expect("paths are never empty");
+                for (state, q) in self.call_transition(&model.transition, last)? {
+                    let candidate = *p * q;
+                    let key = Self::dyn_key(&state);
+                    if next.get(&key).is_some_and(|(_, kept)| kept.to_f64() >= candidate.to_f64()) {
+                        continue;
+                    }
+                    let mut extended = path.clone();
+                    extended.push(state);
+                    next.insert(key, (extended, candidate));
+                }
+            }
+            best = next
+                .into_values()
+                .map(|(path, p)| {
+                    let emitted = emit(path.last().expect("paths are never empty"), observation)?;
+                    Ok((path, p * emitted))
+                })
+                .collect::<Result<_>>()?;
+            // Sorting by final state keeps tie-breaking independent of hash order.
+            best.sort_by_key(|(path, _)| format!("{}", path[path.len() - 1]));
+        }
+
+        let mut winner: Option<(Vec<RuntimeValue>, Prob)> = None;
+        for (path, p) in best {
+            if winner.as_ref().is_none_or(|(_, w)| p.to_f64() > w.to_f64()) {
+                winner = Some((path, p));
+            }
+        }
+        Ok(winner.unwrap_or((Vec::new(), Prob::zero())))
+    }
+
+    /// Exact analysis of the chain a transition function defines on `states`:
+    ///   stationary(f, states)                  → DynDist over the states
+    ///   absorption_probs(f, states, start)     → DynDist over the absorbing states
+    ///   hitting_time(f, states, start, target) → expected steps, as a fraction
+    ///   is_ergodic(f, states)                  → bool
+    /// `states` is an enum type name (all of its variants) or an array of states.
+    fn eval_markov_analysis(&self, name: &str, args: &[Expr]) -> Result<RuntimeValue> {
+        let arity = match name {
+            "absorption_probs" => 3,
+            "hitting_time" => 4,
+            _ => 2,
+        };
+        check_arity(name, arity, args.len())?;
+        let func = self.func_arg(name, "first", &args[0])?;
+        let states = match &args[1].kind {
+            ExprKind::Var(type_name) if self.enum_types.contains_key(type_name) => self.enum_types[type_name]
+                .iter()
+                .map(|v| RuntimeValue::EnumVariant(type_name.clone(), v.clone()))
+                .collect(),
+            _ => self.eval_array(&args[1])?,
+        };
+        if states.is_empty() {
+            return Err(runtime_err!("{}() needs at least one state", name));
+        }
+        let keys: Vec<String> = states.iter().map(Self::dyn_key).collect();
+        let index = |expr: &Expr| -> Result<usize> {
+            let state = self.eval_expr(expr)?;
+            keys.iter()
+                .position(|k| *k == Self::dyn_key(&state))
+                .ok_or_else(|| runtime_err!("{}(): {} is not one of the states", name, state))
+        };
+
+        // Row i of the transition matrix is the distribution f(states[i]).
+        let mut p = vec![vec![Prob::zero(); states.len()]; states.len()];
+        for (i, state) in states.iter().enumerate() {
+            for (next, prob) in self.call_transition(&func, state)? {
+                let j = keys.iter().position(|k| *k == Self::dyn_key(&next)).ok_or_else(|| {
+                    runtime_err!("{}(): {} can move to {}, which is not one of the states", name, state, next)
+                })?;
+                p[i][j] = p[i][j] + prob;
+            }
         }
-        current
+        let chain = Chain { p };
+        let unreachable = |e: Unreachable, goal: &str| match e {
+            Unreachable::NoAbsorbingStates => runtime_err!("{}(): the chain has no absorbing states", name),
+            Unreachable::Escapes(i) => {
+                runtime_err!("{}(): from {} the chain never reaches {}", name, states[i], goal)
+            }
+            Unreachable::Singular => {
+                runtime_err!("{}(): the transition probabilities are too close to singular to solve", name)
+            }
+        };
+
+        Ok(match name {
+            "stationary" => {
+                let pi = chain.stationary().ok_or_else(|| {
+                    runtime_err!("stationary(): the chain has more than one stationary distribution")
+                })?;
+                RuntimeValue::DynDist(states.iter().cloned().zip(pi).collect())
+            }
+            "absorption_probs" => {
+                let probs = chain
+                    .absorption(index(&args[2])?)
+                    .map_err(|e| unreachable(e, "an absorbing state"))?;
+                RuntimeValue::DynDist(probs.into_iter().map(|(a, p)| (states[a].clone(), p)).collect())
+            }
+            "hitting_time" => {
+                let target = index(&args[3])?;
+                let steps = chain
+                    .hitting_time(index(&args[2])?, target)
+                    .map_err(|e| unreachable(e, &states[target].to_string()))?;
+                prob_value(steps)
+            }
+            _ => RuntimeValue::Bool(chain.is_ergodic()),
+        })
     }
 
-    /// Methods callable on a `DynDist` value (`:visualise()`, `:sample()`).
+    /// Methods callable on a `DynDist` value: `:visualise()`, `:sample()`,
+    /// `:expect(v)`, `:mean()`, `:min()`, `:max()` and the distribution queries.
     fn eval_dyn_dist_method(
         &self,
-        outcomes: Vec<(RuntimeValue, f64)>,
+        outcomes: Vec<(RuntimeValue, Prob)>,
         method: &str,
-        _args: &[Expr],
-    ) -> RuntimeValue {
-        match method {
+        args: &[Expr],
+    ) -> Result<RuntimeValue> {
+        Ok(match method {
             "visualise" | "visualize" => {
                 // Merge by display key (should already be merged, but be safe).
                 let mut merged: HashMap<String, f64> = HashMap::new();
                 for (state, prob) in &outcomes {
-                    *merged.entry(format!("{}", state)).or_insert(0.0) += prob;
+                    *merged.entry(format!("{}", state)).or_insert(0.0) += prob.to_f64();
                 }
                 let mut bars: Vec<(String, f64, String)> = merged
                     .into_iter()
@@ -699,88 +1537,1059 @@ impl RuntimeEnv {
                     bars,
                 })
             }
-            "sample" => {
-                let mut rng = rand::thread_rng();
-                let r: f64 = rng.r#gen();
-                let mut cumulative = 0.0;
-                for (state, prob) in &outcomes {
-                    cumulative += prob;
-                    if r < cumulative {
-                        return state.clone();
-                    }
-                }
-                outcomes
-                    .last()
-                    .map(|(s, _)| s.clone())
-                    .unwrap_or(RuntimeValue::Int(0))
+            "sample" if self.is_enumerating() => self.choose_path(outcomes)?,
+            "sample" if self.is_sampling() => self.draw(
+                || Ok(sample_outcomes(&mut self.rng.clone(), &outcomes)),
+                |v| Ok(outcome_prob(&outcomes, v).to_f64().ln()),
+            )?,
+            "sample" => sample_outcomes(&mut self.rng.clone(), &outcomes),
+            "expect" => {
+                check_arity("expect", 1, args.len())?;
+                prob_value(outcome_prob(&outcomes, &self.eval_expr(&args[0])?))
+            }
+            "variance" | "stddev" | "cdf" | "pdf" | "quantile" | "median" | "mode"
+            | "support" | "entropy" | "prob" => {
+                check_arity(method, query_arity(method), args.len())?;
+                self.query_outcomes(&outcomes, method, args)?
+            }
+            "mean" => {
+                check_arity("mean", 0, args.len())?;
+                let mut mean = 0.0;
+                for (v, p) in &outcomes {
+                    let x = match v {
+                        RuntimeValue::Bool(b) => *b as u8 as f64,
+                        other => other.as_f64()?,
+                    };
+                    mean += x * p.to_f64();
+                }
+                RuntimeValue::Float(mean)
+            }
+            "min" | "max" => {
+                check_arity(method, 0, args.len())?;
+                let mut extreme: Option<(RuntimeValue, f64)> = None;
+                for (v, _) in outcomes.into_iter().filter(|(_, p)| !p.is_zero()) {
+                    let x = match &v {
+                        RuntimeValue::Bool(b) => *b as u8 as f64,
+                        other => other.as_f64().map_err(|_| {
+                            type_err!("{}() needs numeric outcomes, got {}", method, other)
+                        })?,
+                    };
+                    let better = match &extreme {
+                        None => true,
+                        Some((_, e)) => if method == "min" { x < *e } else { x > *e },
+                    };
+                    if better {
+                        extreme = Some((v, x));
+                    }
+                }
+                extreme
+                    .map(|(v, _)| v)
+                    .ok_or_else(|| runtime_err!("{}() of a distribution with no outcomes", method))?
+            }
+            _ => return Err(runtime_err!("unknown method '{}' on dynamic distribution", method)),
+        })
+    }
+
+    // Exact Inference
+
+    fn is_enumerating(&self) -> bool {
+        matches!(*self.inference.borrow(), Some(Inference::Enumerate(_)))
+    }
+
+    /// Exact posterior of `func(args)`: run the model once per combination of
+    /// discrete random choices, weight each run by the probability of its
+    /// choices, drop runs rejected by `observe`, and normalise what is left.
+    fn eval_posterior(&self, func: &FuncDef, args: &[RuntimeValue]) -> Result<RuntimeValue> {
+        // Save any enclosing inference run so posterior() can be nested.
+        let outer = self.inference.borrow_mut().take();
+        let result = self.enumerate_paths(&format!("posterior({})", func.name), MAX_ENUMERATION_PATHS, || {
+            self.call_func(func, args)
+        });
+        *self.inference.borrow_mut() = outer;
+
+        let mut merged: HashMap<String, (RuntimeValue, Prob)> = HashMap::new();
+        let mut total = Prob::zero();
+        for (value, weight) in result? {
+            total = total + weight;
+            let entry = merged.entry(Self::dyn_key(&value)).or_insert((value, Prob::zero()));
+            entry.1 = entry.1 + weight;
+        }
+        if total.is_zero() {
+            return Err(runtime_err!(
+                "posterior({}): every execution path was rejected by observe()",
+                func.name
+            ));
+        }
+        for (_, p) in merged.values_mut() {
+            *p = *p / total;
+        }
+        Ok(RuntimeValue::DynDist(sorted_outcomes(merged)))
+    }
+
+    /// Exact per-round probability that pb function `func` returns `Certain`,
+    /// found by running one round along every path through its discrete
+    /// random choices. `None` if the round cannot be enumerated (it draws from
+    /// a continuous or unbounded distribution, or has too many paths), in
+    /// which case only the declared error model says anything about it.
+    fn exact_certain_prob(&self, func: &PbFuncDef, args: &[RuntimeValue]) -> Result<Option<Prob>> {
+        let outer = self.inference.borrow_mut().take();
+        let paths = self.enumerate_paths(&func.name, MAX_ANALYTICAL_PATHS, || self.call_pb_func_once(func, args));
+        *self.inference.borrow_mut() = outer;
+
+        let paths = match paths {
+            Ok(paths) => paths,
+            Err(e) if matches!(e.kind(), YapplError::NotEnumerable(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // A round without random choices is a single path of weight 1, so
+        // this is also exactly 0 or 1 for a deterministic body.
+        let certain = paths
+            .into_iter()
+            .filter(|(value, _)| matches!(value, RuntimeValue::Certain(_)))
+            .fold(Prob::zero(), |total, (_, weight)| total + weight);
+        Ok(Some(certain))
+    }
+
+    /// Run `run` along every path through its random choices, returning
+    /// `(result, path probability)` for each path that satisfied all of its
+    /// observations, or `NotEnumerable` if there are more than `max_paths`.
+    /// `what` names the computation in errors.
+    fn enumerate_paths(
+        &self,
+        what: &str,
+        max_paths: usize,
+        run: impl Fn() -> Result<RuntimeValue>,
+    ) -> Result<Vec<(RuntimeValue, Prob)>> {
+        let mut accepted = Vec::new();
+        let mut prefix = Vec::new();
+        for _ in 0..max_paths {
+            *self.inference.borrow_mut() = Some(Inference::Enumerate(Enumeration::replay(prefix, max_paths)));
+            let outcome = run();
+            let Some(Inference::Enumerate(path)) = self.inference.borrow_mut().take() else {
+                unreachable!("enumeration state replaced during posterior()");
+            };
+            match outcome {
+                Ok(value) => accepted.push((value, path.weight)),
+                Err(e) if matches!(e.kind(), YapplError::ObservationFailed) => {}
+                Err(e) => return Err(e),
             }
-            _ => panic!("Unknown method '{}' on dynamic distribution", method),
+            match path.next_prefix() {
+                Some(next) => prefix = next,
+                None => return Ok(accepted),
+            }
+        }
+        Err(YapplError::NotEnumerable(format!(
+            "{} has more than {} execution paths to enumerate",
+            what, max_paths
+        )))
+    }
+
+    /// Outcomes of `dist` as `.sample()` would produce them, for enumeration.
+    fn enumerable_outcomes(&self, dist: &Dist) -> Result<Vec<(RuntimeValue, Prob)>> {
+        match dist {
+            _ if !self.dist_is_discrete(dist) => Err(YapplError::NotEnumerable(format!(
+                "posterior() can only enumerate finite discrete distributions, not {}",
+                format_dist(dist)
+            ))),
+            Dist::Geometric(_) | Dist::Poisson(_) | Dist::NegativeBinomial(..) => {
+                Err(YapplError::NotEnumerable(format!(
+                    "posterior() can only enumerate finite discrete distributions, not {}",
+                    format_dist(dist)
+                )))
+            }
+            _ => self.value_outcomes(dist),
+        }
+    }
+
+    /// Outcomes of a finite `dist` as the values `.sample()` returns, which
+    /// are booleans rather than 0/1 for a Bernoulli.
+    fn value_outcomes(&self, dist: &Dist) -> Result<Vec<(RuntimeValue, Prob)>> {
+        let outcomes = self.get_dist_outcomes_dynamic(dist)?;
+        Ok(match dist {
+            Dist::Bernoulli(_) => outcomes
+                .into_iter()
+                .map(|(v, p)| (RuntimeValue::Bool(matches!(v, RuntimeValue::Int(1))), p))
+                .collect(),
+            _ => outcomes,
+        })
+    }
+
+    /// Make the next random choice on the current enumeration path.
+    fn choose_path(&self, mut outcomes: Vec<(RuntimeValue, Prob)>) -> Result<RuntimeValue> {
+        outcomes.retain(|(_, p)| !p.is_zero());
+        if outcomes.is_empty() {
+            return Err(runtime_err!("cannot sample from a distribution with no outcomes"));
+        }
+        let mut guard = self.inference.borrow_mut();
+        let Some(Inference::Enumerate(path)) = guard.as_mut() else {
+            unreachable!("choose_path() called outside of posterior()");
+        };
+        if outcomes.len() > path.max_paths {
+            return Err(YapplError::NotEnumerable(format!(
+                "a draw with {} outcomes exceeds the {} execution paths allowed",
+                outcomes.len(),
+                path.max_paths
+            )));
         }
+        let (value, prob) = outcomes.swap_remove(path.choose(outcomes.len()));
+        path.weight = path.weight * prob;
+        Ok(value)
+    }
+
+    /// `.sample()` on a distribution, routed through any active inference run.
+    fn sample_traced(&self, dist: &Dist) -> Result<RuntimeValue> {
+        if self.is_enumerating() {
+            let outcomes = self.enumerable_outcomes(dist)?;
+            self.choose_path(outcomes)
+        } else if self.is_sampling() {
+            self.draw(|| self.sample_dist(dist), |v| self.log_prob_of(dist, v))
+        } else {
+            self.sample_dist(dist)
+        }
+    }
+
+    /// Condition on `value` having been drawn from `dist`. Exact enumeration
+    /// scales the path by its probability, rejection sampling keeps the run with
+    /// that probability, and the other samplers add its log-likelihood.
+    fn observe_from(&self, dist: &RuntimeValue, value: &RuntimeValue) -> Result<()> {
+        let discrete = match dist {
+            RuntimeValue::Dist(d) => self.dist_is_discrete(d),
+            RuntimeValue::DynDist(_) => true,
+            other => return Err(type_err!("observe() expects a distribution, got {}", other)),
+        };
+        if self.is_enumerating() {
+            let p = match dist {
+                RuntimeValue::Dist(d) => outcome_prob(&self.enumerable_outcomes(d)?, value),
+                RuntimeValue::DynDist(outcomes) => outcome_prob(outcomes, value),
+                _ => unreachable!(),
+            };
+            if p.is_zero() {
+                return Err(YapplError::ObservationFailed);
+            }
+            if let Some(Inference::Enumerate(path)) = self.inference.borrow_mut().as_mut() {
+                path.weight = path.weight * p;
+            }
+            return Ok(());
+        }
+
+        let log_prob = match dist {
+            RuntimeValue::Dist(d) => self.log_prob_of(d, value)?,
+            RuntimeValue::DynDist(outcomes) => outcome_prob(outcomes, value).to_f64().ln(),
+            _ => unreachable!(),
+        };
+        if log_prob == f64::NEG_INFINITY {
+            return Err(YapplError::ObservationFailed);
+        }
+        match self.inference.borrow_mut().as_mut() {
+            Some(Inference::Sample(run)) if run.method == InferMethod::Rejection => {
+                if !discrete {
+                    return Err(runtime_err!(
+                        "rejection sampling cannot condition on a value of the continuous \
+                         distribution {}; use method: importance or mh",
+                        dist
+                    ));
+                }
+                if !self.rng.clone().gen_bool(log_prob.exp().min(1.0)) {
+                    return Err(YapplError::ObservationFailed);
+                }
+            }
+            Some(Inference::Sample(run)) => run.log_weight += log_prob,
+            // Outside of inference only an impossible observation is an error.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Sampling-based Inference
+
+    fn is_sampling(&self) -> bool {
+        matches!(*self.inference.borrow(), Some(Inference::Sample(_)))
+    }
+
+    /// Approximate posterior of `func(args)` by sampling, together with an
+    /// `InferInfo` describing how well the sampler did.
+    fn eval_infer(
+        &self,
+        func: &FuncDef,
+        args: &[RuntimeValue],
+        method: InferMethod,
+        samples: usize,
+    ) -> Result<(RuntimeValue, RuntimeValue)> {
+        // Save any enclosing inference run so infer can be nested.
+        let outer = self.inference.borrow_mut().take();
+        let result = match method {
+            InferMethod::MH => self.run_mh(func, args, samples),
+            _ => self.run_weighted(func, args, method, samples),
+        };
+        *self.inference.borrow_mut() = outer;
+        let InferSamples { weighted, accepted, ess } = result?;
+
+        let mut merged: HashMap<String, (RuntimeValue, f64)> = HashMap::new();
+        let mut total = 0.0;
+        for (value, weight) in weighted {
+            total += weight;
+            merged.entry(Self::dyn_key(&value)).or_insert((value, 0.0)).1 += weight;
+        }
+        if total == 0.0 {
+            return Err(runtime_err!("infer({}): every sample was rejected by observe()", func.name));
+        }
+        let merged = merged
+            .into_iter()
+            .map(|(key, (value, weight))| (key, (value, Prob::Approx(weight / total))))
+            .collect();
+        let info = RuntimeValue::InferInfo {
+            method,
+            samples: samples as u64,
+            acceptance_rate: accepted as f64 / samples as f64,
+            ess,
+        };
+        Ok((RuntimeValue::DynDist(sorted_outcomes(merged)), info))
+    }
+
+    /// Run `func` once under `run`. Returns its result, or `None` if an
+    /// observation rejected the run, along with the finished run.
+    fn run_sampled(
+        &self,
+        func: &FuncDef,
+        args: &[RuntimeValue],
+        run: SampleRun,
+    ) -> Result<(Option<RuntimeValue>, SampleRun)> {
+        *self.inference.borrow_mut() = Some(Inference::Sample(run));
+        let outcome = self.call_func(func, args);
+        let Some(Inference::Sample(run)) = self.inference.borrow_mut().take() else {
+            unreachable!("sampling state replaced during infer");
+        };
+        match outcome {
+            Ok(value) => Ok((Some(value), run)),
+            Err(e) if matches!(e.kind(), YapplError::ObservationFailed) => Ok((None, run)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Rejection sampling and likelihood weighting: independent runs from the
+    /// prior, each weighted by the likelihood of its observations.
+    fn run_weighted(
+        &self,
+        func: &FuncDef,
+        args: &[RuntimeValue],
+        method: InferMethod,
+        samples: usize,
+    ) -> Result<InferSamples> {
+        let mut runs = Vec::new();
+        for _ in 0..samples {
+            if let (Some(value), run) = self.run_sampled(func, args, SampleRun::new(method, Vec::new()))? {
+                runs.push((value, run.log_weight));
+            }
+        }
+        // Scale by the largest weight so exp() does not underflow.
+        let max_log = runs.iter().map(|(_, lw)| *lw).fold(f64::NEG_INFINITY, f64::max);
+        let weighted: Vec<(RuntimeValue, f64)> =
+            runs.into_iter().map(|(v, lw)| (v, (lw - max_log).exp())).collect();
+        let sum: f64 = weighted.iter().map(|(_, w)| w).sum();
+        let sum_sq: f64 = weighted.iter().map(|(_, w)| w * w).sum();
+        let ess = if sum_sq > 0.0 { sum * sum / sum_sq } else { 0.0 };
+        Ok(InferSamples { accepted: weighted.len(), weighted, ess })
+    }
+
+    /// Single-site Metropolis-Hastings: each step resamples one random choice
+    /// of the current run from its prior, replays the rest, and accepts the new
+    /// run with the usual MH probability. Occasionally the whole run is
+    /// proposed afresh from the prior instead.
+    fn run_mh(&self, func: &FuncDef, args: &[RuntimeValue], samples: usize) -> Result<InferSamples> {
+        let mut rng = self.rng.clone();
+        let mut current = None;
+        for _ in 0..MAX_MH_INIT_ATTEMPTS {
+            let fresh = SampleRun::new(InferMethod::MH, Vec::new());
+            if let (Some(value), run) = self.run_sampled(func, args, fresh)? {
+                current = Some((value, run));
+                break;
+            }
+        }
+        let Some((mut value, mut run)) = current else {
+            return Err(runtime_err!(
+                "infer({}, method: mh): no run satisfied observe() in {} attempts",
+                func.name, MAX_MH_INIT_ATTEMPTS
+            ));
+        };
+
+        let mut chain = Vec::with_capacity(samples);
+        let mut accepted = 0;
+        for _ in 0..samples {
+            if run.trace.is_empty() {
+                // Nothing random to propose: the chain stays put.
+                accepted += 1;
+            } else if rng.gen_bool(MH_GLOBAL_MOVE_PROB) {
+                // Independence proposal: the priors cancel, leaving the likelihood ratio.
+                let fresh = SampleRun::new(InferMethod::MH, Vec::new());
+                if let (Some(new_value), new_run) = self.run_sampled(func, args, fresh)?
+                    && rng.r#gen::<f64>().ln() < new_run.log_weight - run.log_weight
+                {
+                    value = new_value;
+                    run = new_run;
+                    accepted += 1;
+                }
+            } else {
+                let site = rng.gen_range(0..run.trace.len());
+                let replay = run
+                    .trace
+                    .iter()
+                    .enumerate()
+                    .map(|(i, d)| (i != site).then(|| d.value.clone()))
+                    .collect();
+                let (proposed, new_run) =
+                    self.run_sampled(func, args, SampleRun::new(InferMethod::MH, replay))?;
+                if let Some(new_value) = proposed
+                    && rng.r#gen::<f64>().ln() < mh_log_acceptance(&run, &new_run)
+                {
+                    value = new_value;
+                    run = new_run;
+                    accepted += 1;
+                }
+            }
+            chain.push(value.clone());
+        }
+        let ess = chain_ess(&chain);
+        Ok(InferSamples { weighted: chain.into_iter().map(|v| (v, 1.0)).collect(), accepted, ess })
+    }
+
+    /// Make a random choice during an `infer` run. MH proposals reuse the
+    /// previous run's value at this position while it is still possible under
+    /// `log_prob`; otherwise a fresh value is drawn with `sample`.
+    fn draw(
+        &self,
+        sample: impl FnOnce() -> Result<RuntimeValue>,
+        log_prob: impl Fn(&RuntimeValue) -> Result<f64>,
+    ) -> Result<RuntimeValue> {
+        let (method, replayed) = match self.inference.borrow().as_ref() {
+            Some(Inference::Sample(run)) => {
+                (run.method, run.replay.get(run.trace.len()).cloned().flatten())
+            }
+            _ => unreachable!("draw() called outside of infer"),
+        };
+        let mut draw = None;
+        if let Some(value) = replayed {
+            let lp = log_prob(&value)?;
+            if lp > f64::NEG_INFINITY {
+                draw = Some(Draw { value, log_prob: lp, fresh: false });
+            }
+        }
+        let draw = match draw {
+            Some(d) => d,
+            None => {
+                let value = sample()?;
+                let log_prob = if method == InferMethod::MH { log_prob(&value)? } else { 0.0 };
+                Draw { value, log_prob, fresh: true }
+            }
+        };
+        let value = draw.value.clone();
+        if let Some(Inference::Sample(run)) = self.inference.borrow_mut().as_mut() {
+            run.trace.push(draw);
+        }
+        Ok(value)
+    }
+
+    /// Log-probability (discrete) or log-density (continuous) of `value` under
+    /// `dist`. Values outside the support give negative infinity.
+    fn log_prob_of(&self, dist: &Dist, value: &RuntimeValue) -> Result<f64> {
+        let num = |v: &RuntimeValue| match v {
+            RuntimeValue::Int(_) | RuntimeValue::Float(_) | RuntimeValue::Frac(_) => v.as_f64().ok(),
+            _ => None,
+        };
+        Ok(match dist {
+            Dist::Bernoulli(p) => {
+                let p = check_probability("Bernoulli", self.eval_expr(p)?.as_f64()?)?;
+                match value {
+                    RuntimeValue::Bool(true) => p.ln(),
+                    RuntimeValue::Bool(false) => (1.0 - p).ln(),
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            Dist::Binomial(n, p) => {
+                let n = self.eval_expr(n)?.as_f64()?;
+                let p = check_probability("Binomial", self.eval_expr(p)?.as_f64()?)?;
+                match value {
+                    RuntimeValue::Int(k) if (0.0..=n).contains(&(*k as f64)) => {
+                        let k = *k as f64;
+                        ln_choose(n, k) + xlny(k, p) + xlny(n - k, 1.0 - p)
+                    }
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            Dist::Geometric(p) => {
+                let p = check_probability("Geometric", self.eval_expr(p)?.as_f64()?)?;
+                match value {
+                    RuntimeValue::Int(k) if *k >= 1 => xlny((k - 1) as f64, 1.0 - p) + p.ln(),
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            Dist::UniformContinuous(a, b) => {
+                let a = self.eval_expr(a)?.as_f64()?;
+                let b = self.eval_expr(b)?.as_f64()?;
+                match num(value) {
+                    Some(x) if a <= x && x < b => -(b - a).ln(),
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            Dist::Beta(alpha, beta) => {
+                let a = self.eval_expr(alpha)?.as_f64()?;
+                let b = self.eval_expr(beta)?.as_f64()?;
+                match num(value) {
+                    Some(x) if 0.0 < x && x < 1.0 => {
+                        (a - 1.0) * x.ln() + (b - 1.0) * (1.0 - x).ln()
+                            - (ln_gamma(a) + ln_gamma(b) - ln_gamma(a + b))
+                    }
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            Dist::Normal(mu, sigma) => {
+                let mu = self.eval_expr(mu)?.as_f64()?;
+                let sigma = check_positive("Normal", "standard deviation", self.eval_expr(sigma)?.as_f64()?)?;
+                match num(value) {
+                    Some(x) => {
+                        let z = (x - mu) / sigma;
+                        -0.5 * z * z - sigma.ln() - 0.5 * (2.0 * std::f64::consts::PI).ln()
+                    }
+                    None => f64::NEG_INFINITY,
+                }
+            }
+            Dist::Exponential(rate) => {
+                let rate = check_positive("Exponential", "rate", self.eval_expr(rate)?.as_f64()?)?;
+                match num(value) {
+                    Some(x) if x >= 0.0 => rate.ln() - rate * x,
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            Dist::Gamma(shape, scale) => {
+                let k = check_positive("Gamma", "shape", self.eval_expr(shape)?.as_f64()?)?;
+                let theta = check_positive("Gamma", "scale", self.eval_expr(scale)?.as_f64()?)?;
+                match num(value) {
+                    Some(x) if x > 0.0 => (k - 1.0) * x.ln() - x / theta - ln_gamma(k) - k * theta.ln(),
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            Dist::Poisson(lambda) => {
+                let lambda = check_non_negative("Poisson", "rate", self.eval_expr(lambda)?.as_f64()?)?;
+                match value {
+                    RuntimeValue::Int(k) if *k >= 0 => {
+                        let k = *k as f64;
+                        xlny(k, lambda) - lambda - ln_gamma(k + 1.0)
+                    }
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            Dist::NegativeBinomial(r, p) => {
+                let r = check_positive("NegativeBinomial", "number of successes", self.eval_expr(r)?.as_f64()?)?;
+                let p = check_probability("NegativeBinomial", self.eval_expr(p)?.as_f64()?)?;
+                match value {
+                    RuntimeValue::Int(k) if *k >= 0 => {
+                        let k = *k as f64;
+                        ln_gamma(k + r) - ln_gamma(k + 1.0) - ln_gamma(r) + r * p.ln() + xlny(k, 1.0 - p)
+                    }
+                    _ => f64::NEG_INFINITY,
+                }
+            }
+            // The remaining discrete distributions have finitely many outcomes.
+            _ if self.dist_is_discrete(dist) => {
+                outcome_prob(&self.get_dist_outcomes_dynamic(dist)?, value).to_f64().ln()
+            }
+            _ => return Err(runtime_err!("the density of {} cannot be computed", format_dist(dist))),
+        })
     }
 
     // Distribution Methods
 
-    fn eval_dist_method(&self, dist: &Dist, method: &str, args: &[Expr]) -> RuntimeValue {
-        match method {
-            "sample" => self.sample_dist(dist),
+    fn eval_dist_method(&self, dist: &Dist, method: &str, args: &[Expr]) -> Result<RuntimeValue> {
+        Ok(match method {
+            "sample" => self.sample_traced(dist)?,
 
             "visualise" | "visualize" => {
-                RuntimeValue::Visualisation(self.build_histogram_data(dist))
+                RuntimeValue::Visualisation(self.build_histogram_data(dist)?)
             }
 
             "expect" => {
                 if args.len() != 1 {
-                    panic!("expect() requires exactly 1 argument");
+                    return Err(YapplError::ArityError {
+                        name: "expect".to_string(),
+                        expected: 1,
+                        got: args.len(),
+                    });
+                }
+                let expected = self.eval_expr(&args[0])?;
+                if let (Dist::Discrete(_), RuntimeValue::EnumVariant(..)) = (dist, &expected) {
+                    // Categorical over an enum: look the variant up directly.
+                    let outcomes = self.get_dist_outcomes_dynamic(dist)?;
+                    return Ok(prob_value(outcome_prob(&outcomes, &expected)));
+                }
+                self.prob_of_outcome(dist, expected.as_f64()? as i64)?
+            }
+
+            "variance" | "stddev" | "cdf" | "pdf" | "quantile" | "median" | "mode"
+            | "support" | "entropy" | "prob" => {
+                check_arity(method, query_arity(method), args.len())?;
+                if let (Dist::Binomial(n, p), "variance" | "stddev") = (dist, method) {
+                    // n·p·(1 - p), without listing the n + 1 outcomes.
+                    let n = Prob::Exact(Fraction::from(self.eval_expr(n)?.as_f64()? as u64));
+                    let p = Prob::Exact(self.eval_frac(p)?);
+                    let var = n * p * (Prob::one() - p);
+                    return Ok(match method {
+                        "stddev" => RuntimeValue::Float(var.to_f64().sqrt()),
+                        _ => prob_value(var),
+                    });
+                }
+                match self.finite_outcomes(dist)? {
+                    Some(outcomes) => self.query_outcomes(&outcomes, method, args)?,
+                    None => self.query_dist(dist, method, args)?,
                 }
-                let expected = self.eval_expr(&args[0]).as_f64() as i64;
-                let prob = self.prob_of_outcome(dist, expected);
-                RuntimeValue::Frac(prob)
             }
 
             "min" => match dist {
-                Dist::Uniform(a, _) | Dist::UniformContinuous(a, _) => self.eval_expr(a),
-                _ => panic!("min() is only supported for uniform distributions"),
+                Dist::Uniform(a, _) | Dist::UniformContinuous(a, _) => self.eval_expr(a)?,
+                _ => return Err(runtime_err!("min() is only supported for uniform distributions")),
             },
 
             "max" => match dist {
-                Dist::Uniform(_, b) | Dist::UniformContinuous(_, b) => self.eval_expr(b),
-                _ => panic!("max() is only supported for uniform distributions"),
+                Dist::Uniform(_, b) | Dist::UniformContinuous(_, b) => self.eval_expr(b)?,
+                _ => return Err(runtime_err!("max() is only supported for uniform distributions")),
             },
 
             "mean" => match dist {
                 Dist::Uniform(a, b) | Dist::UniformContinuous(a, b) => {
-                    let av = self.eval_expr(a).as_f64();
-                    let bv = self.eval_expr(b).as_f64();
+                    let av = self.eval_expr(a)?.as_f64()?;
+                    let bv = self.eval_expr(b)?.as_f64()?;
                     RuntimeValue::Float((av + bv) / 2.0)
                 }
-                Dist::Bernoulli(p) => self.eval_expr(p),
+                Dist::Bernoulli(p) => self.eval_expr(p)?,
                 Dist::Binomial(n, p) => {
-                    let nv = self.eval_expr(n).as_f64();
-                    let pv = self.eval_expr(p).as_f64();
+                    let nv = self.eval_expr(n)?.as_f64()?;
+                    let pv = self.eval_expr(p)?.as_f64()?;
                     RuntimeValue::Float(nv * pv)
                 }
                 Dist::Geometric(p) => {
-                    let pv = self.eval_expr(p).as_f64();
+                    let pv = self.eval_expr(p)?.as_f64()?;
                     RuntimeValue::Float(1.0 / pv)
                 }
                 Dist::Beta(alpha, beta) => {
-                    let a = self.eval_expr(alpha).as_f64();
-                    let b = self.eval_expr(beta).as_f64();
+                    let a = self.eval_expr(alpha)?.as_f64()?;
+                    let b = self.eval_expr(beta)?.as_f64()?;
                     RuntimeValue::Float(a / (a + b))
                 }
-                _ => panic!("mean() is not supported for this distribution type"),
+                _ => RuntimeValue::Float(self.dist_mean_f64(dist)?),
+            },
+
+            _ => return Err(runtime_err!("unknown distribution method: '{}'", method)),
+        })
+    }
+
+    // Distribution Queries
+
+    /// Every outcome of a distribution with finite support, merged and with
+    /// exact probabilities; `None` for infinite or continuous distributions.
+    /// Bernoulli outcomes are `true`/`false`, as `.sample()` returns.
+    fn finite_outcomes(&self, dist: &Dist) -> Result<Option<Vec<(RuntimeValue, Prob)>>> {
+        let finite = match dist {
+            Dist::Uniform(..) | Dist::Discrete(_) | Dist::Bernoulli(_) | Dist::Binomial(..) => true,
+            Dist::CombinedDist(d1, d2) => {
+                self.finite_outcomes(d1)?.is_some() && self.finite_outcomes(d2)?.is_some()
+            }
+            _ => false,
+        };
+        if !finite {
+            return Ok(None);
+        }
+        let outcomes = match dist {
+            Dist::Bernoulli(_) => self.enumerable_outcomes(dist)?,
+            _ => self.get_dist_outcomes_dynamic(dist)?,
+        };
+        let mut merged: Vec<(RuntimeValue, Prob)> = Vec::new();
+        for (v, p) in outcomes {
+            match merged.iter_mut().find(|(m, _)| same_outcome(m, &v)) {
+                Some(entry) => entry.1 = entry.1 + p,
+                None => merged.push((v, p)),
+            }
+        }
+        Ok(Some(merged))
+    }
+
+    /// Distribution queries over an explicit list of outcomes (finite
+    /// distributions and `DynDist`). Results are exact where the
+    /// probabilities are.
+    fn query_outcomes(
+        &self,
+        outcomes: &[(RuntimeValue, Prob)],
+        method: &str,
+        args: &[Expr],
+    ) -> Result<RuntimeValue> {
+        let mut outcomes: Vec<(RuntimeValue, Prob)> =
+            outcomes.iter().filter(|(_, p)| !p.is_zero()).cloned().collect();
+        if outcomes.is_empty() {
+            return Err(runtime_err!("{}() needs a distribution with at least one outcome", method));
+        }
+        // Numeric outcomes are kept in increasing order; others keep their order.
+        let numeric: Option<Vec<Prob>> = outcomes.iter().map(|(v, _)| exact_number(v)).collect();
+        if let Some(xs) = &numeric {
+            let mut paired: Vec<(Prob, (RuntimeValue, Prob))> = xs.iter().copied().zip(outcomes).collect();
+            paired.sort_by(|(a, _), (b, _)| a.to_f64().total_cmp(&b.to_f64()));
+            outcomes = paired.into_iter().map(|(_, o)| o).collect();
+        }
+        let need_numbers = || -> Result<Vec<Prob>> {
+            let xs: Vec<Prob> = outcomes.iter().filter_map(|(v, _)| exact_number(v)).collect();
+            if xs.len() != outcomes.len() {
+                return Err(type_err!("{}() needs a distribution over numbers", method));
+            }
+            Ok(xs)
+        };
+
+        Ok(match method {
+            "variance" | "stddev" => {
+                let xs = need_numbers()?;
+                let mean = xs.iter().zip(&outcomes).fold(Prob::zero(), |acc, (x, (_, p))| acc + *x * *p);
+                let var = xs.iter().zip(&outcomes).fold(Prob::zero(), |acc, (x, (_, p))| {
+                    let d = *x - mean;
+                    acc + d * d * *p
+                });
+                if method == "stddev" { RuntimeValue::Float(var.to_f64().sqrt()) } else { prob_value(var) }
+            }
+            "cdf" => {
+                let xs = need_numbers()?;
+                let x = self.eval_expr(&args[0])?.as_f64()?;
+                let total = xs
+                    .iter()
+                    .zip(&outcomes)
+                    .filter(|(v, _)| v.to_f64() <= x)
+                    .fold(Prob::zero(), |acc, (_, (_, p))| acc + *p);
+                prob_value(total)
+            }
+            "pdf" => prob_value(outcome_prob(&outcomes, &self.eval_expr(&args[0])?)),
+            "quantile" | "median" => {
+                need_numbers()?;
+                let q = match method {
+                    "median" => 0.5,
+                    _ => check_quantile(self.eval_expr(&args[0])?.as_f64()?)?,
+                };
+                let mut cumulative = Prob::zero();
+                for (v, p) in &outcomes {
+                    cumulative = cumulative + *p;
+                    if cumulative.to_f64() >= q - 1e-12 {
+                        return Ok(v.clone());
+                    }
+                }
+                outcomes[outcomes.len() - 1].0.clone()
+            }
+            "mode" => {
+                // Ties go to the first (smallest) outcome.
+                let mut best = &outcomes[0];
+                for o in &outcomes[1..] {
+                    if o.1.to_f64() > best.1.to_f64() {
+                        best = o;
+                    }
+                }
+                best.0.clone()
+            }
+            "support" => RuntimeValue::Array(outcomes.into_iter().map(|(v, _)| v).collect()),
+            "entropy" => {
+                let h: f64 = outcomes.iter().map(|(_, p)| -xlny(p.to_f64(), p.to_f64())).sum();
+                RuntimeValue::Float(h)
+            }
+            "prob" => {
+                let pred = self.func_arg("prob", "first", &args[0])?;
+                let mut total = Prob::zero();
+                for (v, p) in &outcomes {
+                    if self.call_predicate("prob", &pred, v)? {
+                        total = total + *p;
+                    }
+                }
+                prob_value(total)
+            }
+            _ => unreachable!("not a distribution query: {}", method),
+        })
+    }
+
+    /// Distribution queries for distributions with infinite support
+    /// (Geometric, Poisson, NegativeBinomial) or a density.
+    fn query_dist(&self, dist: &Dist, method: &str, args: &[Expr]) -> Result<RuntimeValue> {
+        let discrete = self.dist_is_discrete(dist);
+        Ok(match method {
+            "variance" => match dist {
+                Dist::Geometric(p) => {
+                    let p = Prob::from(self.eval_frac(p)?);
+                    prob_value((Prob::one() - p) / (p * p))
+                }
+                Dist::Poisson(lambda) => prob_value(Prob::from(self.eval_frac(lambda)?)),
+                Dist::NegativeBinomial(r, p) => {
+                    let r = Prob::from(self.eval_frac(r)?);
+                    let p = Prob::from(self.eval_frac(p)?);
+                    prob_value(r * (Prob::one() - p) / (p * p))
+                }
+                _ => RuntimeValue::Float(self.dist_variance_f64(dist)?),
             },
+            "stddev" => RuntimeValue::Float(self.dist_variance_f64(dist)?.sqrt()),
+            "cdf" => {
+                let x = self.eval_expr(&args[0])?.as_f64()?;
+                match dist {
+                    Dist::Geometric(p) => {
+                        // P(X ≤ k) = 1 - (1 - p)^k
+                        let p = Prob::from(self.eval_frac(p)?);
+                        if x < 1.0 {
+                            prob_value(Prob::zero())
+                        } else {
+                            prob_value(Prob::one() - (Prob::one() - p).powi(x.floor() as u64))
+                        }
+                    }
+                    _ => RuntimeValue::Float(self.cdf_f64(dist, x)?),
+                }
+            }
+            "pdf" => {
+                let x = self.eval_expr(&args[0])?;
+                match (dist, &x) {
+                    (Dist::Geometric(p), RuntimeValue::Int(k)) if *k >= 1 => {
+                        let p = Prob::from(self.eval_frac(p)?);
+                        prob_value((Prob::one() - p).powi(*k as u64 - 1) * p)
+                    }
+                    _ => RuntimeValue::Float(self.log_prob_of(dist, &x)?.exp()),
+                }
+            }
+            "quantile" | "median" => {
+                let q = match method {
+                    "median" => 0.5,
+                    _ => check_quantile(self.eval_expr(&args[0])?.as_f64()?)?,
+                };
+                if discrete {
+                    self.discrete_quantile(dist, q)?
+                } else {
+                    RuntimeValue::Float(self.continuous_quantile(dist, q)?)
+                }
+            }
+            "mode" => self.dist_mode(dist)?,
+            "support" => {
+                let inf = RuntimeValue::Float(f64::INFINITY);
+                let (lo, hi) = match dist {
+                    // An array of every outcome would be infinite, and a float
+                    // bound does not belong in an int support.
+                    Dist::Geometric(_) | Dist::Poisson(_) | Dist::NegativeBinomial(..) => {
+                        let first = if matches!(dist, Dist::Geometric(_)) { 1 } else { 0 };
+                        return Err(runtime_err!(
+                            "{} has unbounded support {{{}, {}, ...}}; support() needs finitely many outcomes",
+                            format_dist(dist), first, first + 1
+                        ));
+                    }
+                    Dist::Exponential(_) | Dist::Gamma(..) => (RuntimeValue::Float(0.0), inf),
+                    Dist::Beta(..) => (RuntimeValue::Float(0.0), RuntimeValue::Float(1.0)),
+                    Dist::UniformContinuous(a, b) => (self.eval_expr(a)?, self.eval_expr(b)?),
+                    _ => (RuntimeValue::Float(f64::NEG_INFINITY), inf),
+                };
+                // Unbounded or continuous: the bounds of the support.
+                RuntimeValue::Array(vec![lo, hi])
+            }
+            "entropy" => RuntimeValue::Float(self.dist_entropy(dist)?),
+            "prob" => {
+                let pred = self.func_arg("prob", "first", &args[0])?;
+                let mut total = 0.0;
+                if discrete {
+                    // Sum until the remaining tail is negligible.
+                    let mut k = if matches!(dist, Dist::Geometric(_)) { 1 } else { 0 };
+                    let mut covered = 0.0;
+                    while covered < 1.0 - 1e-12 && k < 1_000_000 {
+                        let p = self.log_prob_of(dist, &RuntimeValue::Int(k))?.exp();
+                        if self.call_predicate("prob", &pred, &RuntimeValue::Int(k))? {
+                            total += p;
+                        }
+                        covered += p;
+                        k += 1;
+                    }
+                } else {
+                    // Evaluate the predicate at evenly spaced quantiles.
+                    for i in 0..PROB_GRID_POINTS {
+                        let q = (i as f64 + 0.5) / PROB_GRID_POINTS as f64;
+                        let x = RuntimeValue::Float(self.continuous_quantile(dist, q)?);
+                        if self.call_predicate("prob", &pred, &x)? {
+                            total += 1.0 / PROB_GRID_POINTS as f64;
+                        }
+                    }
+                }
+                RuntimeValue::Float(total)
+            }
+            _ => unreachable!("not a distribution query: {}", method),
+        })
+    }
+
+    /// Smallest k with P(X ≤ k) ≥ q for Geometric, Poisson and NegativeBinomial.
+    /// Their support is unbounded, so the 1-quantile is infinite.
+    fn discrete_quantile(&self, dist: &Dist, q: f64) -> Result<RuntimeValue> {
+        if q >= 1.0 {
+            return Ok(RuntimeValue::Float(f64::INFINITY));
+        }
+        let reaches = |k: i64| -> Result<bool> { Ok(self.cdf_f64(dist, k as f64)? >= q - 1e-12) };
+        let (mut lo, mut hi) = match dist {
+            // P(X ≤ k) = 1 - (1 - p)^k, so k = ⌈ln(1 - q) / ln(1 - p)⌉; the
+            // search below only corrects the rounding.
+            Dist::Geometric(p) => {
+                let p = self.eval_expr(p)?.as_f64()?;
+                let k = ((-q).ln_1p() / (-p).ln_1p()).ceil();
+                let k = if k.is_finite() { (k as i64).max(1) } else { 1 };
+                (k.saturating_sub(2).max(1), k.saturating_add(2))
+            }
+            _ => (0, 1),
+        };
+        // Double the upper end until it is past the quantile, then bisect.
+        while !reaches(hi)? {
+            if hi > i64::MAX / 2 {
+                return Err(runtime_err!("{}:quantile({}) is too large to find", format_dist(dist), q));
+            }
+            lo = hi;
+            hi *= 2;
+        }
+        if reaches(lo)? {
+            return Ok(RuntimeValue::Int(lo));
+        }
+        // Invariant: lo falls short of q, hi reaches it.
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if reaches(mid)? { hi = mid } else { lo = mid }
+        }
+        Ok(RuntimeValue::Int(hi))
+    }
+
+    /// P(X ≤ x) as f64 for distributions with infinite support or a density.
+    fn cdf_f64(&self, dist: &Dist, x: f64) -> Result<f64> {
+        let param = |e: &Expr| -> Result<f64> { self.eval_expr(e)?.as_f64() };
+        Ok(match dist {
+            Dist::Geometric(p) => {
+                // 1 - (1 - p)^k, accurate for p near 0.
+                if x < 1.0 { 0.0 } else { -(x.floor() * (-param(p)?).ln_1p()).exp_m1() }
+            }
+            Dist::Poisson(lambda) => {
+                // P(X ≤ k) = Q(k + 1, λ), the upper regularized gamma function.
+                if x < 0.0 { 0.0 } else { 1.0 - gamma_p(x.floor() + 1.0, param(lambda)?) }
+            }
+            Dist::NegativeBinomial(r, p) => {
+                if x < 0.0 { 0.0 } else { inc_beta(param(r)?, x.floor() + 1.0, param(p)?) }
+            }
+            Dist::UniformContinuous(a, b) => {
+                let (a, b) = (param(a)?, param(b)?);
+                ((x - a) / (b - a)).clamp(0.0, 1.0)
+            }
+            Dist::Normal(mu, sigma) => {
+                let z = (x - param(mu)?) / (param(sigma)? * std::f64::consts::SQRT_2);
+                0.5 * (1.0 + z.signum() * gamma_p(0.5, z * z))
+            }
+            Dist::Exponential(rate) => if x <= 0.0 { 0.0 } else { 1.0 - (-param(rate)? * x).exp() },
+            Dist::Gamma(shape, scale) => {
+                if x <= 0.0 { 0.0 } else { gamma_p(param(shape)?, x / param(scale)?) }
+            }
+            Dist::Beta(a, b) => inc_beta(param(a)?, param(b)?, x),
+            _ => return Err(runtime_err!("cdf() is not supported for {}", format_dist(dist))),
+        })
+    }
+
+    /// Inverse CDF of a continuous distribution, by bisection where there is
+    /// no closed form.
+    fn continuous_quantile(&self, dist: &Dist, q: f64) -> Result<f64> {
+        let param = |e: &Expr| -> Result<f64> { self.eval_expr(e)?.as_f64() };
+        let (mut lo, mut hi) = match dist {
+            Dist::UniformContinuous(a, b) => {
+                let (a, b) = (param(a)?, param(b)?);
+                return Ok(a + q * (b - a));
+            }
+            Dist::Exponential(rate) => return Ok(-(1.0 - q).ln() / param(rate)?),
+            Dist::Beta(..) => (0.0, 1.0),
+            // The tails are unbounded, so the extreme quantiles are infinite.
+            Dist::Normal(..) | Dist::Gamma(..) if q == 1.0 => return Ok(f64::INFINITY),
+            Dist::Normal(..) if q == 0.0 => return Ok(f64::NEG_INFINITY),
+            Dist::Normal(..) | Dist::Gamma(..) => {
+                let mean = self.dist_mean_f64(dist)?;
+                let sd = self.dist_variance_f64(dist)?.sqrt();
+                let lo = if matches!(dist, Dist::Normal(..)) { mean - 40.0 * sd } else { 0.0 };
+                (lo, mean + 40.0 * sd)
+            }
+            _ => return Err(runtime_err!("quantile() is not supported for {}", format_dist(dist))),
+        };
+        for _ in 0..200 {
+            let mid = 0.5 * (lo + hi);
+            if self.cdf_f64(dist, mid)? < q { lo = mid } else { hi = mid }
+        }
+        Ok(0.5 * (lo + hi))
+    }
+
+    /// Most likely value (or peak of the density) for distributions with
+    /// infinite support or a density.
+    fn dist_mode(&self, dist: &Dist) -> Result<RuntimeValue> {
+        let param = |e: &Expr| -> Result<f64> { self.eval_expr(e)?.as_f64() };
+        let no_unique_mode = || runtime_err!("{} has no unique mode", format_dist(dist));
+        Ok(match dist {
+            Dist::Geometric(_) => RuntimeValue::Int(1),
+            Dist::Poisson(lambda) => RuntimeValue::Int(param(lambda)?.floor() as i64),
+            Dist::NegativeBinomial(r, p) => {
+                let (r, p) = (param(r)?, param(p)?);
+                let m = if r > 1.0 { ((r - 1.0) * (1.0 - p) / p).floor() } else { 0.0 };
+                RuntimeValue::Int(m as i64)
+            }
+            Dist::Normal(mu, _) => RuntimeValue::Float(param(mu)?),
+            Dist::Exponential(_) => RuntimeValue::Float(0.0),
+            Dist::Gamma(shape, scale) => {
+                RuntimeValue::Float((param(shape)? - 1.0).max(0.0) * param(scale)?)
+            }
+            Dist::Beta(a, b) => {
+                let (a, b) = (param(a)?, param(b)?);
+                let m = match (a > 1.0, b > 1.0) {
+                    (true, true) => (a - 1.0) / (a + b - 2.0),
+                    _ if a <= 1.0 && b > 1.0 => 0.0,
+                    _ if a > 1.0 && b <= 1.0 => 1.0,
+                    _ => return Err(no_unique_mode()),
+                };
+                RuntimeValue::Float(m)
+            }
+            _ => return Err(no_unique_mode()),
+        })
+    }
 
-            _ => panic!("Unknown distribution method: '{}'", method),
+    /// Entropy in nats (differential entropy for continuous distributions).
+    fn dist_entropy(&self, dist: &Dist) -> Result<f64> {
+        let param = |e: &Expr| -> Result<f64> { self.eval_expr(e)?.as_f64() };
+        Ok(match dist {
+            Dist::UniformContinuous(a, b) => (param(b)? - param(a)?).ln(),
+            Dist::Normal(_, sigma) => {
+                0.5 * (2.0 * std::f64::consts::PI * std::f64::consts::E * param(sigma)?.powi(2)).ln()
+            }
+            Dist::Exponential(rate) => 1.0 - param(rate)?.ln(),
+            Dist::Gamma(shape, scale) => {
+                let (k, theta) = (param(shape)?, param(scale)?);
+                k + theta.ln() + ln_gamma(k) + (1.0 - k) * digamma(k)
+            }
+            Dist::Beta(a, b) => {
+                let (a, b) = (param(a)?, param(b)?);
+                ln_gamma(a) + ln_gamma(b) - ln_gamma(a + b) - (a - 1.0) * digamma(a)
+                    - (b - 1.0) * digamma(b)
+                    + (a + b - 2.0) * digamma(a + b)
+            }
+            _ if self.dist_is_discrete(dist) => {
+                let mut k = if matches!(dist, Dist::Geometric(_)) { 1 } else { 0 };
+                let (mut h, mut covered) = (0.0, 0.0);
+                while covered < 1.0 - 1e-12 && k < 1_000_000 {
+                    let p = self.log_prob_of(dist, &RuntimeValue::Int(k))?.exp();
+                    h -= xlny(p, p);
+                    covered += p;
+                    k += 1;
+                }
+                h
+            }
+            _ => return Err(runtime_err!("entropy() is not supported for {}", format_dist(dist))),
+        })
+    }
+
+    /// Call a user predicate for `builtin`, requiring a boolean result.
+    fn call_predicate(&self, builtin: &str, pred: &FuncValue, arg: &RuntimeValue) -> Result<bool> {
+        match self.call_func_value(pred, std::slice::from_ref(arg))? {
+            RuntimeValue::Bool(b) => Ok(b),
+            v => Err(type_err!("{}() predicate '{}' must return bool, got {}", builtin, pred.name, v)),
         }
     }
 
     /// Probability of getting exactly `target` from a distribution (exact rational).
-    fn prob_of_outcome(&self, dist: &Dist, target: i64) -> Fraction {
-        match dist {
+    fn prob_of_outcome(&self, dist: &Dist, target: i64) -> Result<RuntimeValue> {
+        let prob = match dist {
             Dist::Uniform(a_expr, b_expr) => {
-                let a = self.eval_expr(a_expr).as_f64() as i64;
-                let b = self.eval_expr(b_expr).as_f64() as i64;
+                let a = self.eval_expr(a_expr)?.as_f64()? as i64;
+                let b = self.eval_expr(b_expr)?.as_f64()? as i64;
                 if target < a || target > b {
                     Fraction::from(0u64)
                 } else {
@@ -788,31 +2597,31 @@ impl RuntimeEnv {
                 }
             }
             Dist::UniformContinuous(_, _) => {
-                panic!("expect() is not supported for continuous distributions");
+                return Err(runtime_err!("expect() is not supported for continuous distributions"));
             }
             Dist::Discrete(pairs) => {
                 for (val_expr, prob_expr) in pairs {
-                    if self.eval_expr(val_expr).as_f64() as i64 == target {
-                        return Self::float_to_frac(self.eval_expr(prob_expr).as_f64());
+                    if self.eval_expr(val_expr)?.as_f64()? as i64 == target {
+                        return self.eval_frac(prob_expr).map(RuntimeValue::Frac);
                     }
                 }
                 Fraction::from(0u64)
             }
             Dist::CombinedDist(d1, d2) => {
-                let outcomes1 = self.get_dist_outcomes(d1);
-                let outcomes2 = self.get_dist_outcomes(d2);
-                let mut prob = Fraction::from(0u64);
+                let outcomes1 = self.get_dist_outcomes(d1)?;
+                let outcomes2 = self.get_dist_outcomes(d2)?;
+                let mut prob = Prob::zero();
                 for (v1, p1) in &outcomes1 {
                     for (v2, p2) in &outcomes2 {
                         if v1 + v2 == target {
-                            prob = prob + p1.clone() * p2.clone();
+                            prob = prob + *p1 * *p2;
                         }
                     }
                 }
-                prob
+                return Ok(prob_value(prob));
             }
             Dist::Bernoulli(p_expr) => {
-                let p = Self::float_to_frac(self.eval_expr(p_expr).as_f64());
+                let p = self.eval_frac(p_expr)?;
                 match target {
                     1 => p,
                     0 => Fraction::from(1u64) - p,
@@ -820,77 +2629,99 @@ impl RuntimeEnv {
                 }
             }
             Dist::Binomial(n_expr, p_expr) => {
-                let n = self.eval_expr(n_expr).as_f64() as u64;
-                let p = Self::float_to_frac(self.eval_expr(p_expr).as_f64());
-                if target < 0 || target as u64 > n { return Fraction::from(0u64); }
-                let k = target as u64;
-                let binom = Fraction::from(binom_coeff(n, k));
-                let p_k = Self::pow_frac(p.clone(), k);
-                let q_nk = Self::pow_frac(Fraction::from(1u64) - p, n - k);
-                binom * p_k * q_nk
+                let n = self.eval_expr(n_expr)?.as_f64()? as u64;
+                let p = self.eval_frac(p_expr)?;
+                if target < 0 || target as u64 > n { return Ok(RuntimeValue::Frac(Fraction::from(0u64))); }
+                return Ok(prob_value(binomial_pmf(n, target as u64, Prob::Exact(p))));
             }
             Dist::Geometric(p_expr) => {
-                if target < 1 { return Fraction::from(0u64); }
+                if target < 1 { return Ok(RuntimeValue::Frac(Fraction::from(0u64))); }
                 // Use f64 to avoid u64 overflow; round to 4 sig figs for clean fractions.
-                let p_f64 = self.eval_expr(p_expr).as_f64();
+                let p_f64 = self.eval_expr(p_expr)?.as_f64()?;
                 let prob_f64 = (1.0 - p_f64).powi(target as i32 - 1) * p_f64;
                 Self::float_to_frac(Self::round_sig(prob_f64, 4))
             }
             Dist::Beta(_, _) => {
-                panic!("Beta distribution is continuous; expect() is not supported")
+                return Err(runtime_err!("Beta distribution is continuous; expect() is not supported"));
             }
-        }
+            Dist::Normal(..) | Dist::Exponential(_) | Dist::Gamma(..) => {
+                return Err(runtime_err!(
+                    "{} is continuous; expect() is not supported, use pdf() instead",
+                    format_dist(dist)
+                ));
+            }
+            // C(k + r - 1, k) · p^r · (1 - p)^k is exact for a whole number of
+            // successes, as long as the fraction does not overflow.
+            Dist::NegativeBinomial(r_expr, p_expr) => {
+                let r = check_positive("NegativeBinomial", "number of successes", self.eval_expr(r_expr)?.as_f64()?)?;
+                if target >= 0 && r.fract() == 0.0 {
+                    let (r, k) = (r as u64, target as u64);
+                    let p = Prob::Exact(self.eval_frac(p_expr)?);
+                    if let Some(c) = k.checked_add(r - 1).and_then(|n| binom_coeff(n, k))
+                        && let exact @ Prob::Exact(_) =
+                            Prob::Exact(Fraction::from(c)) * p.powi(r) * (Prob::one() - p).powi(k)
+                    {
+                        return Ok(prob_value(exact));
+                    }
+                }
+                return Ok(RuntimeValue::Float(self.log_prob_of(dist, &RuntimeValue::Int(target))?.exp()));
+            }
+            // The Poisson pmf involves e^-λ, so the probability is irrational
+            // (as for NegativeBinomial with a fractional r): give it as a float, like pdf().
+            Dist::Poisson(_) => {
+                return Ok(RuntimeValue::Float(self.log_prob_of(dist, &RuntimeValue::Int(target))?.exp()));
+            }
+        };
+        Ok(RuntimeValue::Frac(prob))
     }
 
-    /// Returns (outcome_value, probability) pairs for analytical computation (exact rationals).
-    fn get_dist_outcomes(&self, dist: &Dist) -> Vec<(i64, Fraction)> {
-        match dist {
+    /// Returns (outcome_value, probability) pairs for analytical computation,
+    /// exact rationals unless the arithmetic would overflow.
+    fn get_dist_outcomes(&self, dist: &Dist) -> Result<Vec<(i64, Prob)>> {
+        Ok(match dist {
             Dist::Uniform(a_expr, b_expr) => {
-                let a = self.eval_expr(a_expr).as_f64() as i64;
-                let b = self.eval_expr(b_expr).as_f64() as i64;
-                let prob = Fraction::new(1u64, (b - a + 1) as u64);
-                (a..=b).map(|v| (v, prob.clone())).collect()
+                let a = self.eval_expr(a_expr)?.as_f64()? as i64;
+                let b = self.eval_expr(b_expr)?.as_f64()? as i64;
+                if b < a {
+                    return Err(runtime_err!("uniform({}, {}) has an empty range", a, b));
+                }
+                let prob = Prob::Exact(Fraction::new(1u64, (b - a + 1) as u64));
+                (a..=b).map(|v| (v, prob)).collect()
             }
             Dist::UniformContinuous(_, _) => {
-                panic!("Continuous distributions cannot be combined analytically")
+                return Err(runtime_err!("continuous distributions cannot be enumerated analytically"));
             }
             Dist::Discrete(pairs) => pairs
                 .iter()
                 .map(|(v, p)| {
-                    (
-                        self.eval_expr(v).as_f64() as i64,
-                        Self::float_to_frac(self.eval_expr(p).as_f64()),
-                    )
+                    Ok((
+                        self.eval_expr(v)?.as_f64()? as i64,
+                        Prob::Exact(self.eval_frac(p)?),
+                    ))
                 })
-                .collect(),
+                .collect::<Result<_>>()?,
             Dist::CombinedDist(d1, d2) => {
-                let o1 = self.get_dist_outcomes(d1);
-                let o2 = self.get_dist_outcomes(d2);
+                let o1 = self.get_dist_outcomes(d1)?;
+                let o2 = self.get_dist_outcomes(d2)?;
                 let mut out = Vec::new();
                 for (v1, p1) in &o1 {
                     for (v2, p2) in &o2 {
-                        out.push((v1 + v2, p1.clone() * p2.clone()));
+                        out.push((v1 + v2, *p1 * *p2));
                     }
                 }
                 out
             }
-            Dist::Beta(_, _) => panic!("Beta distribution is continuous; cannot enumerate discrete outcomes"),
+            Dist::Beta(_, _) => {
+                return Err(runtime_err!("Beta distribution is continuous; cannot enumerate discrete outcomes"));
+            }
             Dist::Bernoulli(p_expr) => {
-                let p = Self::float_to_frac(self.eval_expr(p_expr).as_f64());
-                let q = Fraction::from(1u64) - p.clone();
-                vec![(1, p), (0, q)]
+                let p = Prob::Exact(self.eval_frac(p_expr)?);
+                vec![(1, p), (0, Prob::one() - p)]
             }
             Dist::Binomial(n_expr, p_expr) => {
-                let n = self.eval_expr(n_expr).as_f64() as u64;
-                let p = Self::float_to_frac(self.eval_expr(p_expr).as_f64());
-                (0..=n)
-                    .map(|k| {
-                        let binom = Fraction::from(binom_coeff(n, k));
-                        let p_k = Self::pow_frac(p.clone(), k);
-                        let q_nk = Self::pow_frac(Fraction::from(1u64) - p.clone(), n - k);
-                        (k as i64, binom * p_k * q_nk)
-                    })
-                    .collect()
+                let n = self.eval_expr(n_expr)?.as_f64()? as u64;
+                let p = Prob::Exact(self.eval_frac(p_expr)?);
+                (0..=n).map(|k| (k as i64, binomial_pmf(n, k, p))).collect()
             }
             Dist::Geometric(p_expr) => {
                 // Compute probabilities in f64 to avoid u64 overflow in the Fraction
@@ -898,7 +2729,7 @@ impl RuntimeEnv {
                 // for moderate k (e.g. p=0.3 → overflow at k=20).
                 // Round each probability to 4 significant figures before converting to a
                 // Fraction so that floating-point noise doesn't produce ugly denominators.
-                let p_f64 = self.eval_expr(p_expr).as_f64();
+                let p_f64 = self.eval_expr(p_expr)?.as_f64()?;
                 let q_f64 = 1.0 - p_f64;
                 let limit = if q_f64 <= 0.0 {
                     1i64
@@ -908,23 +2739,44 @@ impl RuntimeEnv {
                 let mut outcomes = Vec::new();
                 let mut prob_f64 = p_f64;
                 for k in 1..=limit {
-                    outcomes.push((k, Self::float_to_frac(Self::round_sig(prob_f64, 4))));
+                    outcomes.push((k, Prob::Exact(Self::float_to_frac(Self::round_sig(prob_f64, 4)))));
                     prob_f64 *= q_f64;
                 }
                 outcomes
             }
-        }
+            Dist::Poisson(_) | Dist::NegativeBinomial(..) => {
+                // Unbounded support: as for Geometric, stop once the outcomes
+                // listed cover all but 0.001 of the mass (past the mean).
+                let mean = self.dist_mean_f64(dist)?;
+                let mut outcomes = Vec::new();
+                let mut covered = 0.0;
+                let mut k = 0i64;
+                while covered < 0.999 || (k as f64) <= mean {
+                    if k > 1_000_000 {
+                        return Err(runtime_err!("{} has too many outcomes to enumerate", format_dist(dist)));
+                    }
+                    let p = self.log_prob_of(dist, &RuntimeValue::Int(k))?.exp();
+                    outcomes.push((k, Prob::Exact(Self::float_to_frac(Self::round_sig(p, 4)))));
+                    covered += p;
+                    k += 1;
+                }
+                outcomes
+            }
+            Dist::Normal(..) | Dist::Exponential(_) | Dist::Gamma(..) => {
+                return Err(runtime_err!("continuous distributions cannot be enumerated analytically"));
+            }
+        })
     }
 
     // Histogram Construction
 
     /// Build a `HistogramData` from any distribution for use by `:visualise()`.
-    fn build_histogram_data(&self, dist: &Dist) -> HistogramData {
+    fn build_histogram_data(&self, dist: &Dist) -> Result<HistogramData> {
         let label = format_dist(dist);
-        match dist {
+        Ok(match dist {
             Dist::Beta(alpha_expr, beta_expr) => {
-                let alpha = self.eval_expr(alpha_expr).as_f64();
-                let beta_val = self.eval_expr(beta_expr).as_f64();
+                let alpha = self.eval_expr(alpha_expr)?.as_f64()?;
+                let beta_val = self.eval_expr(beta_expr)?.as_f64()?;
                 // Discretize the Beta PDF into 20 bins at midpoints 0.025, 0.075, ..., 0.975
                 let n_bins = 20usize;
                 let mut raw: Vec<(f64, f64)> = (0..n_bins)
@@ -947,67 +2799,117 @@ impl RuntimeEnv {
                 HistogramData { label, kind: HistKind::Discrete, bars }
             }
             Dist::UniformContinuous(a_expr, b_expr) => {
-                let min = self.eval_expr(a_expr).as_f64();
-                let max = self.eval_expr(b_expr).as_f64();
+                let min = self.eval_expr(a_expr)?.as_f64()?;
+                let max = self.eval_expr(b_expr)?.as_f64()?;
                 HistogramData {
                     label,
                     kind: HistKind::Continuous { min, max, mean: (min + max) / 2.0 },
                     bars: vec![],
                 }
             }
+            Dist::Normal(..) | Dist::Exponential(_) | Dist::Gamma(..) => {
+                // Discretize the PDF into 20 bins covering mean ± 4 standard deviations
+                // (clipped at 0 for the distributions on the positive reals).
+                let mean = self.dist_mean_f64(dist)?;
+                let sd = self.dist_variance_f64(dist)?.sqrt();
+                let lo = if matches!(dist, Dist::Normal(..)) { mean - 4.0 * sd } else { 0.0 };
+                let hi = mean + 4.0 * sd;
+                let n_bins = 20usize;
+                let width = (hi - lo) / n_bins as f64;
+                let raw: Vec<(f64, f64)> = (0..n_bins)
+                    .map(|i| {
+                        let x = lo + (i as f64 + 0.5) * width;
+                        Ok((x, self.log_prob_of(dist, &RuntimeValue::Float(x))?.exp()))
+                    })
+                    .collect::<Result<_>>()?;
+                let total: f64 = raw.iter().map(|(_, v)| v).sum();
+                let bars = raw
+                    .iter()
+                    .map(|(x, pdf)| {
+                        let prob = if total > 0.0 { pdf / total } else { 1.0 / n_bins as f64 };
+                        (format!("{:.2}", x), prob, format!("{:.4}", prob))
+                    })
+                    .collect();
+                HistogramData { label, kind: HistKind::Discrete, bars }
+            }
+            Dist::Discrete(pairs) if self.is_enum_keyed(pairs)? => {
+                // Categorical over an enum: one bar per variant, in the order written.
+                let bars = self
+                    .get_dist_outcomes_dynamic(dist)?
+                    .into_iter()
+                    .map(|(v, p)| {
+                        let display = match p {
+                            Prob::Exact(f) => format!("{}", f),
+                            Prob::Approx(x) => format!("{:.4}", x),
+                        };
+                        (v.to_string(), p.to_f64(), display)
+                    })
+                    .collect();
+                HistogramData { label, kind: HistKind::Discrete, bars }
+            }
             _ => {
                 // Merge outcomes (CombinedDist can produce duplicate keys)
-                let outcomes = self.get_dist_outcomes(dist);
-                let mut merged: HashMap<i64, Fraction> = HashMap::new();
+                let outcomes = self.get_dist_outcomes(dist)?;
+                let mut merged: HashMap<i64, Prob> = HashMap::new();
                 for (v, p) in outcomes {
-                    let entry = merged.entry(v).or_insert_with(|| Fraction::from(0u64));
-                    *entry = entry.clone() + p;
+                    let entry = merged.entry(v).or_insert_with(Prob::zero);
+                    *entry = *entry + p;
                 }
                 let mut bars: Vec<(String, f64, String)> = merged
                     .into_iter()
                     .map(|(v, p)| {
-                        let f = p.to_f64().unwrap_or(0.0);
-                        let display = format!("{}", p);
-                        (v.to_string(), f, display)
+                        let display = match p {
+                            Prob::Exact(f) => format!("{}", f),
+                            Prob::Approx(x) => format!("{:.4}", x),
+                        };
+                        (v.to_string(), p.to_f64(), display)
                     })
                     .collect();
                 bars.sort_by_key(|(k, _, _)| k.parse::<i64>().unwrap_or(0));
                 HistogramData { label, kind: HistKind::Discrete, bars }
             }
-        }
+        })
     }
 
     // Sampling
 
-    fn sample_dist(&self, dist: &Dist) -> RuntimeValue {
-        let mut rng = rand::thread_rng();
-        self.sample_dist_with(&mut rng, dist)
+    fn sample_dist(&self, dist: &Dist) -> Result<RuntimeValue> {
+        self.sample_dist_with(&mut self.rng.clone(), dist)
     }
 
-    fn sample_dist_with<R: Rng>(&self, rng: &mut R, dist: &Dist) -> RuntimeValue {
-        match dist {
+    fn sample_dist_with<R: Rng>(&self, rng: &mut R, dist: &Dist) -> Result<RuntimeValue> {
+        Ok(match dist {
             Dist::Uniform(a_expr, b_expr) => {
-                let a = self.eval_expr(a_expr).as_f64() as i64;
-                let b = self.eval_expr(b_expr).as_f64() as i64;
+                let a = self.eval_expr(a_expr)?.as_f64()? as i64;
+                let b = self.eval_expr(b_expr)?.as_f64()? as i64;
+                if b < a {
+                    return Err(runtime_err!("uniform({}, {}) has an empty range", a, b));
+                }
                 RuntimeValue::Int(rng.gen_range(a..=b))
             }
             Dist::UniformContinuous(a_expr, b_expr) => {
-                let a = self.eval_expr(a_expr).as_f64();
-                let b = self.eval_expr(b_expr).as_f64();
+                let a = self.eval_expr(a_expr)?.as_f64()?;
+                let b = self.eval_expr(b_expr)?.as_f64()?;
+                if a >= b {
+                    return Err(runtime_err!("uniformContinuous({}, {}) has an empty range", a, b));
+                }
                 RuntimeValue::Float(rng.gen_range(a..b))
             }
             Dist::Bernoulli(p_expr) => {
-                let p = self.eval_expr(p_expr).as_f64();
+                let p = check_probability("Bernoulli", self.eval_expr(p_expr)?.as_f64()?)?;
                 RuntimeValue::Bool(rng.gen_bool(p))
             }
             Dist::Binomial(n_expr, p_expr) => {
-                let n = self.eval_expr(n_expr).as_f64() as u64;
-                let p = self.eval_expr(p_expr).as_f64();
+                let n = self.eval_expr(n_expr)?.as_f64()? as u64;
+                let p = check_probability("Binomial", self.eval_expr(p_expr)?.as_f64()?)?;
                 let count = (0..n).filter(|_| rng.gen_bool(p)).count() as i64;
                 RuntimeValue::Int(count)
             }
             Dist::Geometric(p_expr) => {
-                let p = self.eval_expr(p_expr).as_f64();
+                let p = check_probability("Geometric", self.eval_expr(p_expr)?.as_f64()?)?;
+                if p == 0.0 {
+                    return Err(runtime_err!("Geometric(0) never succeeds and cannot be sampled"));
+                }
                 let mut count = 1i64;
                 while !rng.gen_bool(p) {
                     count += 1;
@@ -1015,213 +2917,505 @@ impl RuntimeEnv {
                 RuntimeValue::Int(count)
             }
             Dist::Discrete(pairs) => {
-                let evaluated: Vec<(f64, f64)> = pairs
+                let evaluated: Vec<(RuntimeValue, f64)> = pairs
                     .iter()
-                    .map(|(v, p)| (self.eval_expr(v).as_f64(), self.eval_expr(p).as_f64()))
-                    .collect();
+                    .map(|(v, p)| Ok((self.eval_expr(v)?, self.eval_expr(p)?.as_f64()?)))
+                    .collect::<Result<_>>()?;
+                // Enum variants are returned as-is; numbers as Int when whole.
+                let outcome = |val: &RuntimeValue| -> Result<RuntimeValue> {
+                    if let RuntimeValue::EnumVariant(..) = val {
+                        return Ok(val.clone());
+                    }
+                    let val = val.as_f64()?;
+                    Ok(if val.fract() == 0.0 { RuntimeValue::Int(val as i64) } else { RuntimeValue::Float(val) })
+                };
                 let r: f64 = rng.r#gen();
                 let mut cumulative = 0.0;
                 for (val, prob) in &evaluated {
                     cumulative += prob;
                     if r < cumulative {
-                        if val.fract() == 0.0 {
-                            return RuntimeValue::Int(*val as i64);
-                        } else {
-                            return RuntimeValue::Float(*val);
-                        }
+                        return outcome(val);
                     }
                 }
-                let (val, _) = evaluated.last().unwrap();
-                if val.fract() == 0.0 { RuntimeValue::Int(*val as i64) } else { RuntimeValue::Float(*val) }
+                let (val, _) = evaluated
+                    .last()
+                    .ok_or_else(|| runtime_err!("Discrete distribution has no outcomes"))?;
+                outcome(val)?
             }
             Dist::CombinedDist(d1, d2) => {
-                let s1 = self.sample_dist_with(rng, d1);
-                let s2 = self.sample_dist_with(rng, d2);
+                let s1 = self.sample_dist_with(rng, d1)?;
+                let s2 = self.sample_dist_with(rng, d2)?;
                 match (s1, s2) {
                     (RuntimeValue::Int(a), RuntimeValue::Int(b)) => RuntimeValue::Int(a + b),
                     (RuntimeValue::Float(a), RuntimeValue::Float(b)) => RuntimeValue::Float(a + b),
                     (RuntimeValue::Int(a), RuntimeValue::Float(b)) => RuntimeValue::Float(a as f64 + b),
                     (RuntimeValue::Float(a), RuntimeValue::Int(b)) => RuntimeValue::Float(a + b as f64),
-                    (a, b) => panic!("Cannot combine distribution samples {:?} and {:?}", a, b),
+                    (a, b) => return Err(type_err!("cannot combine distribution samples {} and {}", a, b)),
                 }
             }
             Dist::Beta(alpha_expr, beta_expr) => {
-                let alpha = self.eval_expr(alpha_expr).as_f64();
-                let beta  = self.eval_expr(beta_expr).as_f64();
+                let alpha = check_positive("Beta", "alpha", self.eval_expr(alpha_expr)?.as_f64()?)?;
+                let beta  = check_positive("Beta", "beta", self.eval_expr(beta_expr)?.as_f64()?)?;
                 RuntimeValue::Float(sample_beta(rng, alpha, beta))
             }
+            Dist::Normal(mu_expr, sigma_expr) => {
+                let mu = self.eval_expr(mu_expr)?.as_f64()?;
+                let sigma = check_positive("Normal", "standard deviation", self.eval_expr(sigma_expr)?.as_f64()?)?;
+                RuntimeValue::Float(mu + sigma * sample_std_normal(rng))
+            }
+            Dist::Exponential(rate_expr) => {
+                let rate = check_positive("Exponential", "rate", self.eval_expr(rate_expr)?.as_f64()?)?;
+                RuntimeValue::Float(-(1.0 - rng.r#gen::<f64>()).ln() / rate)
+            }
+            Dist::Gamma(shape_expr, scale_expr) => {
+                let shape = check_positive("Gamma", "shape", self.eval_expr(shape_expr)?.as_f64()?)?;
+                let scale = check_positive("Gamma", "scale", self.eval_expr(scale_expr)?.as_f64()?)?;
+                RuntimeValue::Float(sample_gamma(rng, shape) * scale)
+            }
+            Dist::Poisson(lambda_expr) => {
+                let lambda = check_non_negative("Poisson", "rate", self.eval_expr(lambda_expr)?.as_f64()?)?;
+                RuntimeValue::Int(sample_poisson(rng, lambda))
+            }
+            Dist::NegativeBinomial(r_expr, p_expr) => {
+                let r = check_positive("NegativeBinomial", "number of successes", self.eval_expr(r_expr)?.as_f64()?)?;
+                let p = check_probability("NegativeBinomial", self.eval_expr(p_expr)?.as_f64()?)?;
+                if p == 0.0 {
+                    return Err(runtime_err!("NegativeBinomial with p = 0 never succeeds and cannot be sampled"));
+                }
+                // Gamma-Poisson mixture: λ ~ Gamma(r, (1 - p) / p), then Poisson(λ).
+                let lambda = sample_gamma(rng, r) * (1.0 - p) / p;
+                RuntimeValue::Int(sample_poisson(rng, lambda))
+            }
+        })
+    }
+
+    /// Whether a `Discrete` distribution is keyed by enum variants (a categorical).
+    fn is_enum_keyed(&self, pairs: &[(Box<Expr>, Box<Expr>)]) -> Result<bool> {
+        match pairs.first() {
+            Some((v, _)) => Ok(matches!(self.eval_expr(v)?, RuntimeValue::EnumVariant(..))),
+            None => Ok(false),
         }
     }
 
     // Built-in & User Function Calls
 
-    fn eval_func_call(&self, name: &str, args: &[Expr]) -> RuntimeValue {
+    fn eval_func_call(&self, name: &str, args: &[Expr]) -> Result<RuntimeValue> {
+        // A variable holding a function value.
+        if let Some(RuntimeValue::Func(func)) = self.vars.get(name) {
+            let func = Rc::clone(func);
+            let eval_args: Vec<RuntimeValue> = args.iter().map(|a| self.eval_expr(a)).collect::<Result<_>>()?;
+            return self.call_func_value(&func, &eval_args);
+        }
+
         // Markov chain special forms
         // These are handled before evaluating args because the second argument
         // is a function name (an identifier), not a value.
 
         if name == "bind" {
-            if args.len() != 2 {
-                panic!("bind() requires 2 arguments: bind(distribution, transition_function)");
-            }
-            let dist_val = self.eval_expr(&args[0]);
-            let func_name = match &args[1] {
-                Expr::Var(n) => n.clone(),
-                _ => panic!("bind() second argument must be a transition function name"),
-            };
-            return self.eval_bind(dist_val, &func_name);
+            check_arity("bind", 2, args.len())?;
+            let dist_val = self.eval_expr(&args[0])?;
+            let func = self.func_arg("bind", "second", &args[1])?;
+            return self.eval_bind(dist_val, &func);
         }
 
         if name == "step" {
-            if args.len() != 3 {
-                panic!("step() requires 3 arguments: step(initial_state, transition_function, n)");
+            check_arity("step", 3, args.len())?;
+            let initial = self.eval_expr(&args[0])?;
+            let func = self.func_arg("step", "second", &args[1])?;
+            let n = self.eval_expr(&args[2])?.as_f64()? as usize;
+            return self.eval_step(initial, &func, n);
+        }
+
+        if name == "posterior" {
+            if args.is_empty() {
+                return Err(YapplError::ArityError { name: "posterior".to_string(), expected: 1, got: 0 });
+            }
+            let func = self.lookup_func(&func_name_arg("posterior", "first", &args[0])?)?;
+            let model_args: Vec<RuntimeValue> =
+                args[1..].iter().map(|a| self.eval_expr(a)).collect::<Result<_>>()?;
+            return self.eval_posterior(&func, &model_args);
+        }
+
+        if name == "verify" {
+            if !(3..=4).contains(&args.len()) {
+                return Err(YapplError::ArityError { name: "verify".to_string(), expected: 3, got: args.len() });
+            }
+            let func_name = func_name_arg("verify", "first", &args[0])?;
+            let func = self.pb_funcs.get(&func_name).cloned().ok_or_else(|| {
+                contract_err!("verify: '{}' is not a probabilistic function", func_name)
+            })?;
+            let trials = self.eval_expr(&args[2])?.as_int()?;
+            if trials <= 0 {
+                return Err(runtime_err!("verify({}) needs at least one trial", func_name));
             }
-            let initial = self.eval_expr(&args[0]);
-            let func_name = match &args[1] {
-                Expr::Var(n) => n.clone(),
-                _ => panic!("step() second argument must be a transition function name"),
+            let reference = match args.get(3) {
+                Some(arg) => Some(self.lookup_func(&func_name_arg("verify", "fourth", arg)?)?),
+                None => None,
             };
-            let n = self.eval_expr(&args[2]).as_f64() as usize;
-            return self.eval_step(initial, &func_name, n);
+            // A function of several parameters takes each input as an array of arguments.
+            let inputs = self
+                .eval_array(&args[1])?
+                .into_iter()
+                .map(|input| match input {
+                    RuntimeValue::Array(elems) if func.params.len() != 1 => Ok(elems),
+                    _ if func.params.len() == 1 => Ok(vec![input]),
+                    v => Err(type_err!(
+                        "verify: each input to '{}' must be an array of {} arguments, got {}",
+                        func_name, func.params.len(), v
+                    )),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let report = self.verify_pb_func(&func, inputs, trials as u64, reference.as_ref())?;
+            return Ok(RuntimeValue::Verification(report));
+        }
+
+        if name == "hmm" {
+            check_arity("hmm", 3, args.len())?;
+            let transition = self.func_arg("hmm", "first", &args[0])?;
+            let emission = self.func_arg("hmm", "second", &args[1])?;
+            // The initial state may be a distribution or a single known state.
+            let initial = match self.eval_expr(&args[2])? {
+                RuntimeValue::Dist(d) => self.value_outcomes(&d)?,
+                RuntimeValue::DynDist(o) => o,
+                state => vec![(state, Prob::one())],
+            };
+            return Ok(RuntimeValue::Hmm(Box::new(HmmModel { transition, emission, initial })));
+        }
+
+        if matches!(name, "stationary" | "absorption_probs" | "hitting_time" | "is_ergodic") {
+            return self.eval_markov_analysis(name, args);
         }
 
-        let eval_args: Vec<RuntimeValue> = args.iter().map(|a| self.eval_expr(a)).collect();
+        // Higher-order array built-ins: the first argument is a function name.
 
-        match name {
+        if name == "filter" {
+            check_arity("filter", 2, args.len())?;
+            let func = self.func_arg("filter", "first", &args[0])?;
+            let mut kept = Vec::new();
+            for elem in self.eval_array(&args[1])? {
+                if self.call_predicate("filter", &func, &elem)? {
+                    kept.push(elem);
+                }
+            }
+            return Ok(RuntimeValue::Array(kept));
+        }
+
+        if name == "fold" {
+            check_arity("fold", 3, args.len())?;
+            let func = self.func_arg("fold", "first", &args[0])?;
+            let mut acc = self.eval_expr(&args[1])?;
+            for elem in self.eval_array(&args[2])? {
+                acc = self.call_func_value(&func, &[acc, elem])?;
+            }
+            return Ok(acc);
+        }
+
+        // `map` as an expression; `let x = map(f, xs) with confidence ...` is a statement.
+        if name == "map" {
+            check_arity("map", 2, args.len())?;
+            let func = self.func_arg("map", "first", &args[0])?;
+            let mut out = Vec::new();
+            for elem in self.eval_array(&args[1])? {
+                out.push(self.call_func_value(&func, &[elem])?);
+            }
+            return Ok(RuntimeValue::Array(out));
+        }
+
+        let eval_args: Vec<RuntimeValue> =
+            args.iter().map(|a| self.eval_expr(a)).collect::<Result<_>>()?;
+
+        Ok(match name {
             // Built-ins
             "jacobi" => {
-                if eval_args.len() != 2 {
-                    panic!("jacobi() requires 2 arguments");
+                check_arity("jacobi", 2, eval_args.len())?;
+                let n = eval_args[1].as_f64()? as i64;
+                if n <= 0 {
+                    return Err(runtime_err!("jacobi() requires a positive modulus, got {}", n));
                 }
-                RuntimeValue::Int(jacobi_symbol(
-                    eval_args[0].as_f64() as i64,
-                    eval_args[1].as_f64() as i64,
-                ))
+                RuntimeValue::Int(jacobi_symbol(eval_args[0].as_f64()? as i64, n))
             }
             "mod_exp" => {
-                if eval_args.len() != 3 {
-                    panic!("mod_exp() requires 3 arguments");
+                check_arity("mod_exp", 3, eval_args.len())?;
+                if eval_args[2].as_f64()? as i64 == 0 {
+                    return Err(YapplError::DivisionByZero);
                 }
                 RuntimeValue::Int(mod_exp(
-                    eval_args[0].as_f64() as i64,
-                    eval_args[1].as_f64() as i64,
-                    eval_args[2].as_f64() as i64,
+                    eval_args[0].as_f64()? as i64,
+                    eval_args[1].as_f64()? as i64,
+                    eval_args[2].as_f64()? as i64,
                 ))
             }
+            // Array built-ins
+            "len" => {
+                check_arity("len", 1, eval_args.len())?;
+                RuntimeValue::Int(as_array("len", &eval_args[0])?.len() as i64)
+            }
+            "push" => {
+                // Arrays are values: push returns a new, longer array.
+                check_arity("push", 2, eval_args.len())?;
+                let mut elems = as_array("push", &eval_args[0])?.to_vec();
+                elems.push(eval_args[1].clone());
+                RuntimeValue::Array(elems)
+            }
+            "sum" => {
+                check_arity("sum", 1, eval_args.len())?;
+                sum_values(as_array("sum", &eval_args[0])?)?
+            }
+            "range" => {
+                check_arity("range", 2, eval_args.len())?;
+                let (a, b) = (eval_args[0].as_int()?, eval_args[1].as_int()?);
+                RuntimeValue::Array((a..b).map(RuntimeValue::Int).collect())
+            }
+            "zip" => {
+                check_arity("zip", 2, eval_args.len())?;
+                let xs = as_array("zip", &eval_args[0])?;
+                let ys = as_array("zip", &eval_args[1])?;
+                RuntimeValue::Array(
+                    xs.iter()
+                        .zip(ys)
+                        .map(|(x, y)| RuntimeValue::Array(vec![x.clone(), y.clone()]))
+                        .collect(),
+                )
+            }
+            // pb functions called from the rounds of another pb function
+            _ if self.nested_budget.is_some() && self.pb_funcs.contains_key(name) => {
+                self.call_nested_pb_func(name, &eval_args)?
+            }
             // User-defined regular functions
             _ => {
-                if self.pb_funcs.contains_key(name) {
-                    panic!(
-                        "'{}' is a probabilistic function; call it with \
-                         `let x, info = {}(args) with confidence >= val`",
-                        name, name
-                    );
-                }
-                let func = self
-                    .funcs
-                    .get(name)
-                    .cloned()
-                    .unwrap_or_else(|| panic!("Undefined function: '{}'", name));
-                self.call_func(&func, &eval_args)
+                let func = self.lookup_func(name)?;
+                self.call_func(&func, &eval_args)?
             }
+        })
+    }
+
+    /// Find a regular function by name, rejecting pb functions (which must be
+    /// called with a confidence).
+    fn lookup_func(&self, name: &str) -> Result<FuncDef> {
+        if self.pb_funcs.contains_key(name) {
+            return Err(pb_call_err(name));
         }
+        self.funcs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| YapplError::UndefinedFunction(name.to_string()))
     }
 
-    fn call_func(&self, func: &FuncDef, args: &[RuntimeValue]) -> RuntimeValue {
-        if args.len() != func.params.len() {
-            panic!(
-                "Function '{}' expects {} arguments, got {}",
-                func.name,
-                func.params.len(),
-                args.len()
-            );
+    fn eval_array(&self, expr: &Expr) -> Result<Vec<RuntimeValue>> {
+        match self.eval_expr(expr)? {
+            RuntimeValue::Array(elems) => Ok(elems),
+            other => Err(type_err!("expected an array, got {}", other)),
         }
+    }
+
+    fn call_func(&self, func: &FuncDef, args: &[RuntimeValue]) -> Result<RuntimeValue> {
+        check_arity(&func.name, func.params.len(), args.len())?;
         let mut env = self.new_child();
         for (param, arg) in func.params.iter().zip(args.iter()) {
             env.vars.insert(param.name.clone(), arg.clone());
         }
-        for stmt in &func.body {
-            match env.exec_stmt(stmt) {
-                FlowControl::Return(val) => return val,
-                FlowControl::Continue => {}
-            }
+        match env.exec_block(&func.body)? {
+            FlowControl::Return(val) => Ok(val),
+            _ => Ok(RuntimeValue::Int(0)), // implicit return 0 if no return statement
         }
-        RuntimeValue::Int(0) // implicit return 0 if no return statement
     }
 
     // Probabilistic Function Execution
 
     /// Execute a pb function body once, returning Certain(v) or Uncertain(v).
-    fn call_pb_func_once(&self, func: &PbFuncDef, args: &[RuntimeValue]) -> RuntimeValue {
-        if args.len() != func.params.len() {
-            panic!(
-                "Pb function '{}' expects {} arguments, got {}",
-                func.name,
-                func.params.len(),
-                args.len()
-            );
-        }
+    /// A round run outside of a call gets its own budget for the pb functions
+    /// it calls.
+    fn call_pb_func_once(&self, func: &PbFuncDef, args: &[RuntimeValue]) -> Result<RuntimeValue> {
+        check_arity(&func.name, func.params.len(), args.len())?;
         let mut env = self.new_child();
+        if env.nested_budget.is_none() && self.calls_pb_funcs(&func.body, &mut HashSet::new()) {
+            env.nested_budget = Some(Rc::new(NestedBudget::new(SINGLE_ROUND_NESTED_ERROR)));
+        }
         for (param, arg) in func.params.iter().zip(args.iter()) {
             env.vars.insert(param.name.clone(), arg.clone());
         }
-        for stmt in &func.body {
-            match env.exec_stmt(stmt) {
-                FlowControl::Return(val) => return val,
-                FlowControl::Continue => {}
-            }
+        match env.exec_block(&func.body)? {
+            FlowControl::Return(val) => Ok(val),
+            _ => Err(contract_err!("pb function '{}' did not return a value", func.name)),
         }
-        panic!("Pb function '{}' did not return a value", func.name);
     }
 
     /// Execute a pb function for the required number of rounds to meet `target_confidence`.
+    /// With `adaptive`, a BPP vote stops as soon as its result is settled.
+    /// A function that calls pb functions (directly or through regular
+    /// functions) keeps half of its error budget for those calls, so by the
+    /// union bound the answer is right with probability at least
+    /// 1 - (own error + error reported by the nested calls).
     /// Returns `(result_value, info_value)`.
     fn call_pb_func(
         &self,
         func: &PbFuncDef,
         args: &[RuntimeValue],
         target_confidence: f64,
-    ) -> (RuntimeValue, RuntimeValue) {
-        let rounds_needed = compute_rounds_needed(&func.error_class, target_confidence);
+        adaptive: bool,
+    ) -> Result<(RuntimeValue, RuntimeValue)> {
+        let model = ErrorModel::of(func)?;
+        if let Some(calls) = &self.pb_calls {
+            calls.borrow_mut().push((func.name.clone(), args.to_vec()));
+        }
+        let mut env = self.new_child();
+        env.nested_budget = None;
+        let mut target_confidence = target_confidence;
+        if self.calls_pb_funcs(&func.body, &mut HashSet::new()) {
+            let half = (1.0 - target_confidence) / 2.0;
+            target_confidence = 1.0 - half;
+            env.nested_budget = Some(Rc::new(NestedBudget::new(half)));
+        }
+        let rounds_needed = model.rounds_needed(target_confidence);
+
+        let (result, mut info) = match func.error_class {
+            ErrorClass::RP => env.run_rp_rounds(func, args, &model, rounds_needed),
+            ErrorClass::CoRP => env.run_corp_rounds(func, args, &model, rounds_needed),
+            ErrorClass::BPP if adaptive => {
+                // Half of the error budget goes to stopping early on a wrong
+                // leader, half to the vote at the cap if no leader settles.
+                let half = (1.0 - target_confidence) / 2.0;
+                let cap = model.rounds_needed(1.0 - half);
+                let lead = model.lead_needed(half);
+                let confidence = 1.0 - model.lead_error(lead) - model.error_after(cap);
+                env.run_bpp_rounds(func, args, &model, cap, Some(lead), confidence)
+            }
+            ErrorClass::BPP => {
+                let confidence = 1.0 - model.error_after(rounds_needed);
+                env.run_bpp_rounds(func, args, &model, rounds_needed, None, confidence)
+            }
+            ErrorClass::ZPP => env.run_zpp_rounds(func, args, &model, rounds_needed),
+        }?;
+        if let (Some(budget), RuntimeValue::Info(info)) = (&env.nested_budget, &mut info) {
+            info.confidence = (info.confidence - budget.spent.get()).max(0.0);
+        }
+        Ok((result, info))
+    }
+
+    /// Call pb function `name` from inside the rounds of another pb function,
+    /// with the next share of the caller's nested-call budget.
+    fn call_nested_pb_func(&self, name: &str, args: &[RuntimeValue]) -> Result<RuntimeValue> {
+        let budget = self.nested_budget.as_ref().expect("only called with a nested budget");
+        let func = self.pb_funcs[name].clone();
+        let share = budget.next_share();
+        if 1.0 - share == 1.0 {
+            return Err(contract_err!(
+                "too many nested calls to pb function '{}' to share the error budget",
+                name
+            ));
+        }
+        let (result, info) = self.call_pb_func(&func, args, 1.0 - share, false)?;
+        if let RuntimeValue::Info(info) = info {
+            budget.spend((1.0 - info.confidence).clamp(0.0, share));
+        }
+        Ok(result)
+    }
+
+    /// Whether running `body` may call a pb function as an expression, either
+    /// directly, through a function value, or through the regular functions
+    /// it calls.
+    fn calls_pb_funcs(&self, body: &[Statement], seen: &mut HashSet<String>) -> bool {
+        let mut called = Vec::new();
+        for stmt in body {
+            stmt.walk_exprs(&mut |e| match &e.kind {
+                ExprKind::FuncCall(name, _) => called.push(name.clone()),
+                ExprKind::Var(name) if self.pb_funcs.contains_key(name) => called.push(name.clone()),
+                _ => {}
+            });
+        }
+        called.into_iter().any(|name| {
+            if self.pb_funcs.contains_key(&name) {
+                return true;
+            }
+            match self.funcs.get(&name) {
+                Some(func) if seen.insert(name.clone()) => self.calls_pb_funcs(&func.body, seen),
+                _ => false,
+            }
+        })
+    }
+
+    /// Call a pb function on every element of `arr` so that all results are
+    /// right together with probability at least `confidence`.
+    /// By the union bound, P(any element wrong) ≤ Σ per-element errors, so it
+    /// is enough for the per-element error budgets to sum to 1 - confidence:
+    ///   - `Even` gives each of the n elements (1 - confidence) / n up front.
+    ///   - `Adaptive` gives each element an even share of the budget still
+    ///     left, then takes back only the error it reported (0 for an answer
+    ///     settled by `Certain`), so later elements get a larger share.
+    ///
+    /// The returned Info sums rounds and element errors; its `trace` holds
+    /// each element's own Info.
+    fn map_pb_func(
+        &self,
+        func: &PbFuncDef,
+        arr: Vec<RuntimeValue>,
+        confidence: f64,
+        budget: MapBudget,
+    ) -> Result<(Vec<RuntimeValue>, PbInfo)> {
+        let model = ErrorModel::of(func)?;
+        let n = arr.len();
+        let mut remaining = 1.0 - confidence;
+        let mut results = Vec::with_capacity(n);
+        let mut total = PbInfo { error_bound: model.per_round, ..Default::default() };
+        let mut error = 0.0;
 
-        match func.error_class {
-            ErrorClass::RP => self.run_rp_rounds(func, args, rounds_needed),
-            ErrorClass::CoRP => self.run_corp_rounds(func, args, rounds_needed),
-            ErrorClass::BPP => self.run_bpp_rounds(func, args, rounds_needed),
+        for (i, elem) in arr.into_iter().enumerate() {
+            let share = match budget {
+                MapBudget::Even => (1.0 - confidence) / n as f64,
+                MapBudget::Adaptive => remaining / (n - i) as f64,
+            };
+            let (result, info) = self.call_pb_func(func, &[elem], 1.0 - share, false)?;
+            let RuntimeValue::Info(info) = info else {
+                unreachable!("call_pb_func always returns an Info");
+            };
+            let spent = (1.0 - info.confidence).clamp(0.0, share);
+            remaining -= spent;
+            error += 1.0 - info.confidence;
+            total.rounds += info.rounds;
+            total.stopped_early |= info.stopped_early;
+            if let Some(p) = info.fail_prob {
+                total.fail_prob = Some(total.fail_prob.unwrap_or(0.0) + p);
+            }
+            total.trace.push(RuntimeValue::Info(info));
+            results.push(result);
         }
+
+        total.confidence = (1.0 - error).max(0.0);
+        Ok((results, total))
     }
 
     /// RP: stop early on `Certain(v)` (definitive answer); accumulate `Uncertain(v)`.
-    /// If all k rounds return `Uncertain`, confidence = 1 - (1/2)^k.
+    /// If all k rounds return `Uncertain`, confidence = 1 - ε^k.
     fn run_rp_rounds(
         &self,
         func: &PbFuncDef,
         args: &[RuntimeValue],
+        model: &ErrorModel,
         max_rounds: u64,
-    ) -> (RuntimeValue, RuntimeValue) {
+    ) -> Result<(RuntimeValue, RuntimeValue)> {
+        let mut trace = Vec::new();
         let mut last_uncertain_val: Option<RuntimeValue> = None;
 
-        for round in 1..=max_rounds {
-            match self.call_pb_func_once(func, args) {
+        for _ in 0..max_rounds {
+            let outcome = self.call_pb_func_once(func, args)?;
+            trace.push(outcome.clone());
+            match outcome {
                 RuntimeValue::Certain(val) => {
-                    let info = RuntimeValue::Info { rounds: round, confidence: 1.0 };
-                    return (*val, info);
+                    let info = PbInfo::new(model, trace, max_rounds, 1.0);
+                    return Ok((*val, RuntimeValue::Info(info)));
                 }
                 RuntimeValue::Uncertain(val) => {
                     last_uncertain_val = Some(*val);
                 }
-                v => panic!(
-                    "Pb function '{}' must return Certain(v) or Uncertain(v), got {:?}",
-                    func.name, v
-                ),
+                v => return Err(not_certain_or_uncertain(&func.name, &v)),
             }
         }
 
         let val = last_uncertain_val
-            .unwrap_or_else(|| panic!("Pb function '{}' returned no value", func.name));
-        let confidence = 1.0 - 0.5_f64.powi(max_rounds as i32);
-        let info = RuntimeValue::Info { rounds: max_rounds, confidence };
-        (val, info)
+            .ok_or_else(|| contract_err!("pb function '{}' returned no value", func.name))?;
+        let confidence = 1.0 - model.error_after(max_rounds);
+        let info = PbInfo::new(model, trace, max_rounds, confidence);
+        Ok((val, RuntimeValue::Info(info)))
     }
 
     /// coRP: stop early on `Certain(v)` (definitive answer); accumulate `Uncertain(v)`.
@@ -1230,92 +3424,243 @@ impl RuntimeEnv {
         &self,
         func: &PbFuncDef,
         args: &[RuntimeValue],
+        model: &ErrorModel,
         max_rounds: u64,
-    ) -> (RuntimeValue, RuntimeValue) {
+    ) -> Result<(RuntimeValue, RuntimeValue)> {
+        let mut trace = Vec::new();
         let mut last_uncertain_val: Option<RuntimeValue> = None;
 
-        for round in 1..=max_rounds {
-            match self.call_pb_func_once(func, args) {
+        for _ in 0..max_rounds {
+            let outcome = self.call_pb_func_once(func, args)?;
+            trace.push(outcome.clone());
+            match outcome {
                 RuntimeValue::Certain(val) => {
-                    let info = RuntimeValue::Info { rounds: round, confidence: 1.0 };
-                    return (*val, info);
+                    let info = PbInfo::new(model, trace, max_rounds, 1.0);
+                    return Ok((*val, RuntimeValue::Info(info)));
                 }
                 RuntimeValue::Uncertain(val) => {
                     last_uncertain_val = Some(*val);
                 }
-                v => panic!(
-                    "Pb function '{}' must return Certain(v) or Uncertain(v), got {:?}",
-                    func.name, v
-                ),
+                v => return Err(not_certain_or_uncertain(&func.name, &v)),
             }
         }
 
         let val = last_uncertain_val
-            .unwrap_or_else(|| panic!("Pb function '{}' returned no value", func.name));
-        let confidence = 1.0 - 0.5_f64.powi(max_rounds as i32);
-        let info = RuntimeValue::Info { rounds: max_rounds, confidence };
-        (val, info)
+            .ok_or_else(|| contract_err!("pb function '{}' returned no value", func.name))?;
+        let confidence = 1.0 - model.error_after(max_rounds);
+        let info = PbInfo::new(model, trace, max_rounds, confidence);
+        Ok((val, RuntimeValue::Info(info)))
+    }
+
+    /// BPP: run all k rounds, take the plurality vote over the answers (any
+    /// type, compared like `DynDist` outcomes). Ties go to the answer whose
+    /// label sorts first, so a boolean tie gives `false`.
+    /// Confidence bound (Chernoff, per-round success prob p = 1 - ε > 1/2): the
+    /// right answer misses a strict majority with
+    ///   P(error) ≤ exp(-2k(p - 1/2)²), which is exp(-k/8) for the default p = 3/4
+    /// With `stop_lead`, voting stops once the leader has that many more votes
+    /// than all other answers together (see `ErrorModel::lead_needed`).
+    fn run_bpp_rounds(
+        &self,
+        func: &PbFuncDef,
+        args: &[RuntimeValue],
+        model: &ErrorModel,
+        max_rounds: u64,
+        stop_lead: Option<u64>,
+        confidence: f64,
+    ) -> Result<(RuntimeValue, RuntimeValue)> {
+        let mut trace = Vec::new();
+        let mut tally: HashMap<String, (RuntimeValue, u64)> = HashMap::new();
+        let mut rounds = 0;
+
+        while rounds < max_rounds {
+            let outcome = self.call_pb_func_once(func, args)?;
+            trace.push(outcome.clone());
+            let inner = match outcome {
+                RuntimeValue::Certain(v) | RuntimeValue::Uncertain(v) => *v,
+                v => return Err(not_certain_or_uncertain(&func.name, &v)),
+            };
+            let votes = &mut tally.entry(Self::dyn_key(&inner)).or_insert((inner, 0)).1;
+            *votes += 1;
+            rounds += 1;
+            if let Some(lead) = stop_lead
+                && (2 * *votes).saturating_sub(rounds) >= lead
+            {
+                break;
+            }
+        }
+
+        let mut votes: Vec<(String, (RuntimeValue, u64))> = tally.into_iter().collect();
+        votes.sort_by(|(ka, (_, a)), (kb, (_, b))| b.cmp(a).then_with(|| ka.cmp(kb)));
+        let votes: Vec<(RuntimeValue, u64)> = votes.into_iter().map(|(_, vote)| vote).collect();
+        let winner = votes
+            .first()
+            .map(|(v, _)| v.clone())
+            .ok_or_else(|| contract_err!("pb function '{}' returned no value", func.name))?;
+        let info = PbInfo { votes: Some(votes), ..PbInfo::new(model, trace, max_rounds, confidence) };
+        Ok((winner, RuntimeValue::Info(info)))
+    }
+
+    /// ZPP: retry until a round returns `Certain(v)`. Answers are never wrong,
+    /// so the cap of k rounds only bounds the running time; all k rounds fail
+    /// with probability ε^k. Gives `Fail` if the cap is reached.
+    fn run_zpp_rounds(
+        &self,
+        func: &PbFuncDef,
+        args: &[RuntimeValue],
+        model: &ErrorModel,
+        max_rounds: u64,
+    ) -> Result<(RuntimeValue, RuntimeValue)> {
+        let fail_prob = Some(model.error_after(max_rounds));
+        let mut trace = Vec::new();
+        for _ in 0..max_rounds {
+            let outcome = self.call_pb_func_once(func, args)?;
+            trace.push(outcome.clone());
+            match outcome {
+                RuntimeValue::Certain(val) => {
+                    let info = PbInfo { fail_prob, ..PbInfo::new(model, trace, max_rounds, 1.0) };
+                    return Ok((*val, RuntimeValue::Info(info)));
+                }
+                RuntimeValue::Fail => {}
+                v => {
+                    return Err(contract_err!(
+                        "ZPP function '{}' must return Certain(v) or Fail, got {}",
+                        func.name, v
+                    ));
+                }
+            }
+        }
+        let info = PbInfo { fail_prob, ..PbInfo::new(model, trace, max_rounds, 0.0) };
+        Ok((RuntimeValue::Fail, RuntimeValue::Info(info)))
     }
 
-    /// BPP: run all k rounds, take the majority vote.
-    /// Confidence bound (Chernoff, assuming per-round success prob p = 3/4):
-    ///   P(error) ≈ exp(-2k(p - 1/2)²) = exp(-k/8)
-    fn run_bpp_rounds(
+    // Verification
+
+    /// Run `trials` single rounds of `func` on each input and check them
+    /// against its declared error class:
+    ///   - RP / coRP / ZPP: a `Certain` answer must never be wrong.
+    ///   - every class: the per-round error rate must not be significantly
+    ///     above the declared bound ε, i.e. the lower end of its 95% Wilson
+    ///     interval must be at most ε. For ZPP the "error" is a `Fail`.
+    ///
+    /// The right answer comes from `reference` when given. Otherwise it is the
+    /// first `Certain` answer (RP / coRP / ZPP), or else the most common one.
+    fn verify_pb_func(
         &self,
         func: &PbFuncDef,
-        args: &[RuntimeValue],
-        rounds: u64,
-    ) -> (RuntimeValue, RuntimeValue) {
-        let mut true_votes = 0u64;
-        let mut false_votes = 0u64;
+        inputs: Vec<Vec<RuntimeValue>>,
+        trials: u64,
+        reference: Option<&FuncDef>,
+    ) -> Result<VerifyReport> {
+        let model = ErrorModel::of(func)?;
+        let one_sided = !matches!(func.error_class, ErrorClass::BPP);
+        let mut rows = Vec::with_capacity(inputs.len());
 
-        for _ in 0..rounds {
-            let inner = match self.call_pb_func_once(func, args) {
-                RuntimeValue::Certain(v) | RuntimeValue::Uncertain(v) => *v,
-                v => panic!(
-                    "Pb function '{}' must return Certain(v) or Uncertain(v), got {:?}",
-                    func.name, v
-                ),
+        for args in inputs {
+            // (answer, whether it was Certain) for each round; `None` for Fail.
+            let mut rounds: Vec<(Option<RuntimeValue>, bool)> = Vec::new();
+            for _ in 0..trials {
+                rounds.push(match (self.call_pb_func_once(func, &args)?, &func.error_class) {
+                    (RuntimeValue::Certain(v), _) => (Some(*v), true),
+                    (RuntimeValue::Fail, ErrorClass::ZPP) => (None, false),
+                    (v, ErrorClass::ZPP) => {
+                        return Err(contract_err!(
+                            "ZPP function '{}' must return Certain(v) or Fail, got {}",
+                            func.name, v
+                        ));
+                    }
+                    (RuntimeValue::Uncertain(v), _) => (Some(*v), false),
+                    (v, _) => return Err(not_certain_or_uncertain(&func.name, &v)),
+                });
+            }
+
+            let expected = match reference {
+                Some(reference) => self.call_func(reference, &args)?,
+                None => inferred_answer(&rounds, one_sided),
             };
-            match inner {
-                RuntimeValue::Bool(true) => true_votes += 1,
-                RuntimeValue::Bool(false) => false_votes += 1,
-                v => panic!("BPP function round returned non-boolean inner value: {:?}", v),
+            let key = Self::dyn_key(&expected);
+            let is_right = |v: &RuntimeValue| Self::dyn_key(v) == key;
+
+            let errors = rounds.iter().filter(|(v, _)| !v.as_ref().is_some_and(is_right)).count() as u64;
+            let interval = wilson_interval(errors, trials);
+            let mut violations = Vec::new();
+            let wrong_certain: Vec<&RuntimeValue> = rounds
+                .iter()
+                .filter_map(|(v, certain)| v.as_ref().filter(|v| *certain && !is_right(v)))
+                .collect();
+            if one_sided && let Some(first) = wrong_certain.first() {
+                violations.push(format!(
+                    "{} round(s) returned Certain({}), but the answer is {}",
+                    wrong_certain.len(), first, expected
+                ));
             }
+            if interval.0 > model.per_round {
+                violations.push(format!(
+                    "per-round error rate {:.4} is above the declared bound {}",
+                    errors as f64 / trials as f64, model.per_round
+                ));
+            }
+            rows.push(VerifyRow { args, expected, errors, interval, violations });
         }
 
-        let majority = RuntimeValue::Bool(true_votes > false_votes);
-        // Chernoff: exp(-k/8) with p = 3/4
-        let error_prob = (-(rounds as f64) / 8.0).exp();
-        let info = RuntimeValue::Info { rounds, confidence: 1.0 - error_prob };
-        (majority, info)
+        Ok(VerifyReport {
+            func: func.name.clone(),
+            error_class: func.error_class.clone(),
+            error_bound: model.per_round,
+            trials,
+            rows,
+        })
     }
 
     // Statement Execution
 
-    fn exec_stmt(&mut self, stmt: &Statement) -> FlowControl {
-        match stmt {
-            Statement::Decl(expr) => {
-                if let Expr::Var(name) = expr {
+    /// Run statements in order, stopping at the first `break`, `continue` or
+    /// `return` and handing it back to the caller.
+    fn exec_block(&mut self, stmts: &[Statement]) -> Result<FlowControl> {
+        for stmt in stmts {
+            match self.exec_stmt(stmt)? {
+                FlowControl::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(FlowControl::Normal)
+    }
+
+    /// Run one iteration of a loop body, with `break`/`continue` allowed.
+    fn exec_loop_body(&mut self, body: &[Statement]) -> Result<FlowControl> {
+        self.loop_depth += 1;
+        let flow = self.exec_block(body);
+        self.loop_depth -= 1;
+        flow
+    }
+
+    fn exec_stmt(&mut self, stmt: &Statement) -> Result<FlowControl> {
+        self.exec_stmt_kind(&stmt.kind).map_err(|e| e.with_span(stmt.span))
+    }
+
+    fn exec_stmt_kind(&mut self, stmt: &StmtKind) -> Result<FlowControl> {
+        Ok(match stmt {
+            StmtKind::Decl(expr) => {
+                if let ExprKind::Var(name) = &expr.kind {
                     self.vars.insert(name.clone(), RuntimeValue::Int(0));
                 } else {
-                    panic!("Declaration must be a variable name");
+                    return Err(runtime_err!("declaration must be a variable name"));
                 }
-                FlowControl::Continue
+                FlowControl::Normal
             }
 
-            Statement::Assign { name, value } | Statement::DeclAssign { name, value } => {
-                let var_name = match name {
-                    Expr::Var(n) => n.clone(),
-                    _ => panic!("Left-hand side of assignment must be a variable name"),
+            StmtKind::Assign { name, value } | StmtKind::DeclAssign { name, value } => {
+                let var_name = match &name.kind {
+                    ExprKind::Var(n) => n.clone(),
+                    _ => return Err(runtime_err!("left-hand side of assignment must be a variable name")),
                 };
-                let val = self.eval_expr(value);
+                let val = self.eval_expr(value)?;
                 self.vars.insert(var_name, val);
-                FlowControl::Continue
+                FlowControl::Normal
             }
 
-            Statement::HardcodedOutput(expr) => {
-                let val = self.eval_expr(expr);
+            StmtKind::HardcodedOutput(expr) => {
+                let val = self.eval_expr(expr)?;
                 match val {
                     RuntimeValue::Visualisation(data) => {
                         self.output.push(OutputLine::Hist(data));
@@ -1324,162 +3669,274 @@ impl RuntimeEnv {
                         self.output.push(OutputLine::Text(format!("{}", other)));
                     }
                 }
-                FlowControl::Continue
+                FlowControl::Normal
             }
 
-            Statement::Return(maybe_expr) => {
+            StmtKind::Return(maybe_expr) => {
                 let val = match maybe_expr {
-                    Some(e) => self.eval_expr(e),
+                    Some(e) => self.eval_expr(e)?,
                     None => RuntimeValue::Int(0),
                 };
                 FlowControl::Return(val)
             }
 
-            Statement::If { cond, then_block, else_block } => {
-                let cond_val = self.eval_expr(cond).as_bool();
+            StmtKind::If { cond, then_block, else_block } => {
+                let cond_val = self.eval_expr(cond)?.as_bool()?;
                 let block = if cond_val {
                     Some(then_block.as_slice())
                 } else {
                     else_block.as_deref()
                 };
-                if let Some(stmts) = block {
-                    for s in stmts {
-                        match self.exec_stmt(s) {
-                            FlowControl::Return(v) => return FlowControl::Return(v),
-                            FlowControl::Continue => {}
+                match block {
+                    Some(stmts) => self.exec_block(stmts)?,
+                    None => FlowControl::Normal,
+                }
+            }
+
+            StmtKind::While { cond, body } => {
+                while self.eval_expr(cond)?.as_bool()? {
+                    match self.exec_loop_body(body)? {
+                        FlowControl::Break => break,
+                        FlowControl::Return(v) => return Ok(FlowControl::Return(v)),
+                        FlowControl::Normal | FlowControl::Continue => {}
+                    }
+                }
+                FlowControl::Normal
+            }
+
+            StmtKind::For { var, iterable, body } => {
+                let items: Box<dyn Iterator<Item = RuntimeValue>> = match iterable {
+                    ForIterable::Range(a, b) => {
+                        let a = self.eval_expr(a)?.as_int()?;
+                        let b = self.eval_expr(b)?.as_int()?;
+                        Box::new((a..b).map(RuntimeValue::Int))
+                    }
+                    ForIterable::Array(arr) => match self.eval_expr(arr)? {
+                        RuntimeValue::Array(elems) => Box::new(elems.into_iter()),
+                        other => {
+                            return Err(type_err!("cannot iterate over {}; expected an array or a range", other));
                         }
+                    },
+                };
+                for item in items {
+                    self.vars.insert(var.clone(), item);
+                    match self.exec_loop_body(body)? {
+                        FlowControl::Break => break,
+                        FlowControl::Return(v) => return Ok(FlowControl::Return(v)),
+                        FlowControl::Normal | FlowControl::Continue => {}
                     }
                 }
-                FlowControl::Continue
+                FlowControl::Normal
+            }
+
+            StmtKind::Match { scrutinee, arms } => {
+                let value = self.eval_expr(scrutinee)?;
+                let (body, bindings) = select_arm(&value, arms)?;
+                // The arm's bindings only last for its body; other assignments
+                // in it stay visible afterwards, as in an `if` block.
+                let shadowed: Vec<(String, Option<RuntimeValue>)> = bindings
+                    .iter()
+                    .map(|(name, _)| (name.clone(), self.vars.get(name).cloned()))
+                    .collect();
+                self.vars.extend(bindings);
+                let flow = self.exec_block(body);
+                for (name, outer) in shadowed.into_iter().rev() {
+                    match outer {
+                        Some(value) => self.vars.insert(name, value),
+                        None => self.vars.remove(&name),
+                    };
+                }
+                flow?
+            }
+
+            StmtKind::Observe(cond) => {
+                if !self.eval_expr(cond)?.as_bool()? {
+                    return Err(YapplError::ObservationFailed);
+                }
+                FlowControl::Normal
+            }
+
+            StmtKind::ObserveFrom { dist, value } => {
+                let dist = self.eval_expr(dist)?;
+                let value = self.eval_expr(value)?;
+                self.observe_from(&dist, &value)?;
+                FlowControl::Normal
+            }
+
+            StmtKind::SetSeed(seed) => {
+                let seed = self.eval_expr(seed)?.as_int()?;
+                if seed < 0 {
+                    return Err(runtime_err!("set_seed() expects a non-negative seed, got {}", seed));
+                }
+                self.rng.reseed(seed as u64);
+                FlowControl::Normal
+            }
+
+            StmtKind::Break | StmtKind::Continue => {
+                let (keyword, flow) = match stmt {
+                    StmtKind::Break => ("break", FlowControl::Break),
+                    _ => ("continue", FlowControl::Continue),
+                };
+                if self.loop_depth == 0 {
+                    return Err(runtime_err!("'{}' used outside of a loop", keyword));
+                }
+                flow
             }
 
-            Statement::PbCallAssign { result_var, info_var, func_name, args, confidence } => {
+            StmtKind::PbCallAssign { result_var, info_var, func_name, args, confidence, adaptive } => {
                 let func = self
                     .pb_funcs
                     .get(func_name)
                     .cloned()
-                    .unwrap_or_else(|| panic!("Undefined probabilistic function: '{}'", func_name));
+                    .ok_or_else(|| YapplError::UndefinedFunction(func_name.clone()))?;
                 let eval_args: Vec<RuntimeValue> =
-                    args.iter().map(|a| self.eval_expr(a)).collect();
-                let (result, info) = self.call_pb_func(&func, &eval_args, *confidence);
+                    args.iter().map(|a| self.eval_expr(a)).collect::<Result<_>>()?;
+                if *adaptive && !matches!(func.error_class, ErrorClass::BPP) {
+                    return Err(contract_err!(
+                        "adaptive stopping only applies to BPP functions; '{}' is {}",
+                        func_name, func.error_class
+                    ));
+                }
+                let (result, info) = self.call_pb_func(&func, &eval_args, *confidence, *adaptive)?;
                 self.vars.insert(result_var.clone(), result);
                 self.vars.insert(info_var.clone(), info);
-                FlowControl::Continue
+                FlowControl::Normal
             }
 
-            Statement::MapCallAssign { var, func_name, array_expr, confidence } => {
-                let arr = match self.eval_expr(array_expr) {
+            StmtKind::MapCallAssign { var, info_var, func_name, array_expr, confidence, budget } => {
+                let arr = match self.eval_expr(array_expr)? {
                     RuntimeValue::Array(elems) => elems,
-                    other => panic!(
-                        "map() second argument must be an array, got {:?}",
-                        other
-                    ),
+                    other => {
+                        return Err(type_err!("map() second argument must be an array, got {}", other));
+                    }
                 };
                 let n = arr.len();
 
                 let results: Vec<RuntimeValue> = if let Some(conf) = confidence {
-                    // Union bound: split error budget evenly across all n elements.
-                    //   P(any element wrong) <= sum of per-element errors = n * (1-per_conf)
-                    //   Setting n * (1-per_conf) = 1-conf gives per_conf = 1 - (1-conf)/n.
-                    let per_conf = if n == 0 {
-                        *conf
-                    } else {
-                        1.0 - (1.0 - conf) / n as f64
-                    };
                     let pb_func = self
                         .pb_funcs
                         .get(func_name)
                         .cloned()
-                        .unwrap_or_else(|| {
+                        .ok_or_else(|| {
                             if self.funcs.contains_key(func_name) {
-                                panic!(
+                                contract_err!(
                                     "'{}' is a regular function; \
                                      'with confidence' only applies to pb functions",
                                     func_name
                                 )
                             } else {
-                                panic!("Undefined function: '{}'", func_name)
+                                YapplError::UndefinedFunction(func_name.clone())
                             }
-                        });
-                    let mut out = Vec::with_capacity(n);
-                    for elem in arr {
-                        let (result, _info) =
-                            self.call_pb_func(&pb_func, &[elem], per_conf);
-                        out.push(result);
+                        })?;
+                    let (out, info) = self.map_pb_func(&pb_func, arr, *conf, *budget)?;
+                    if let Some(info_var) = info_var {
+                        self.vars.insert(info_var.clone(), RuntimeValue::Info(info));
                     }
                     out
                 } else {
                     // Regular function: apply element-wise, no confidence needed.
-                    let func = self
-                        .funcs
-                        .get(func_name)
-                        .cloned()
-                        .unwrap_or_else(|| {
-                            if self.pb_funcs.contains_key(func_name) {
-                                panic!(
-                                    "'{}' is a probabilistic function; \
-                                     use 'with confidence >= ...' when mapping it",
-                                    func_name
-                                )
-                            } else {
-                                panic!("Undefined function: '{}'", func_name)
-                            }
-                        });
+                    if info_var.is_some() {
+                        return Err(contract_err!(
+                            "map({}, ...) only produces an Info with 'with confidence >= ...'",
+                            func_name
+                        ));
+                    }
+                    let func = match self.vars.get(func_name) {
+                        Some(RuntimeValue::Func(func)) => Rc::clone(func),
+                        _ if self.pb_funcs.contains_key(func_name) => {
+                            return Err(contract_err!(
+                                "'{}' is a probabilistic function; \
+                                 use 'with confidence >= ...' when mapping it",
+                                func_name
+                            ));
+                        }
+                        _ => self
+                            .named_func(func_name)
+                            .ok_or_else(|| YapplError::UndefinedFunction(func_name.clone()))?,
+                    };
                     let mut out = Vec::with_capacity(n);
                     for elem in arr {
-                        out.push(self.call_func(&func, &[elem]));
+                        out.push(self.call_func_value(&func, &[elem])?);
                     }
                     out
                 };
 
                 self.vars.insert(var.clone(), RuntimeValue::Array(results));
-                FlowControl::Continue
+                FlowControl::Normal
+            }
+
+            StmtKind::Infer { var, info_var, func_name, args, method, samples } => {
+                let func = self.lookup_func(func_name)?;
+                let eval_args: Vec<RuntimeValue> =
+                    args.iter().map(|a| self.eval_expr(a)).collect::<Result<_>>()?;
+                if *samples <= 0 {
+                    return Err(runtime_err!("infer({}) needs at least one sample", func_name));
+                }
+                let (dist, info) = self.eval_infer(&func, &eval_args, *method, *samples as usize)?;
+                self.vars.insert(var.clone(), dist);
+                if let Some(info_var) = info_var {
+                    self.vars.insert(info_var.clone(), info);
+                }
+                FlowControl::Normal
             }
 
-            Statement::DistributionOf { var, func_name, args, mode } => {
+            StmtKind::DistributionOf { var, func_name, args, mode } => {
                 let func = self
                     .pb_funcs
                     .get(func_name)
                     .cloned()
-                    .unwrap_or_else(|| panic!(
+                    .ok_or_else(|| contract_err!(
                         "distribution_of: '{}' is not a probabilistic function", func_name
-                    ));
+                    ))?;
                 let eval_args: Vec<RuntimeValue> =
-                    args.iter().map(|a| self.eval_expr(a)).collect();
+                    args.iter().map(|a| self.eval_expr(a)).collect::<Result<_>>()?;
 
                 let dist_val = match mode {
                     DistributionOfMode::Analytical => {
-                        // Derive the per-round distribution from the error class alone.
-                        // RP / coRP: per-round probability of Certain �� 1/2
-                        //   → worst-case rounds-until-Certain ~ Geometric(0.5)
-                        // BPP: per-round vote correctness ≥ 3/4
-                        //   → model each vote as Bernoulli(0.75)
-                        let dist = match func.error_class {
-                            ErrorClass::RP | ErrorClass::CoRP => {
-                                Dist::Geometric(Box::new(Expr::Float(0.5)))
-                            }
-                            ErrorClass::BPP => {
-                                Dist::Bernoulli(Box::new(Expr::Float(0.75)))
-                            }
+                        // If a round's randomness comes only from finite discrete
+                        // draws, enumerate them for the exact per-round
+                        // probability of Certain as Bernoulli(p).
+                        let exact = match func.error_class {
+                            ErrorClass::BPP => None,
+                            _ => self.exact_certain_prob(&func, &eval_args)?,
                         };
-                        RuntimeValue::Dist(dist)
+                        match exact {
+                            Some(Prob::Exact(p)) => RuntimeValue::Dist(Dist::Bernoulli(Box::new(
+                                Expr::synthetic(ExprKind::Frac(p)),
+                            ))),
+                            Some(Prob::Approx(p)) => RuntimeValue::Dist(Dist::Bernoulli(Box::new(
+                                Expr::synthetic(ExprKind::Float(p)),
+                            ))),
+                            None => {
+                                // Otherwise take the worst case the declared error bound ε
+                                // allows for the error class.
+                                // RP / coRP / ZPP: per-round probability of Certain ≥ 1 - ε
+                                //   → worst-case rounds-until-Certain ~ Geometric(1 - ε)
+                                // BPP: per-round vote correctness ≥ 1 - ε
+                                //   → model each vote as Bernoulli(1 - ε)
+                                let p = Box::new(Expr::synthetic(ExprKind::Float(
+                                    1.0 - ErrorModel::of(&func)?.per_round,
+                                )));
+                                RuntimeValue::Dist(match func.error_class {
+                                    ErrorClass::BPP => Dist::Bernoulli(p),
+                                    _ => Dist::Geometric(p),
+                                })
+                            }
+                        }
                     }
 
                     DistributionOfMode::Empirical(n) => {
                         // Run N single rounds and report empirical Certain frequency.
                         let mut n_certain = 0i64;
                         for _ in 0..*n {
-                            match self.call_pb_func_once(&func, &eval_args) {
+                            match self.call_pb_func_once(&func, &eval_args)? {
                                 RuntimeValue::Certain(_) => n_certain += 1,
-                                RuntimeValue::Uncertain(_) => {}
-                                v => panic!(
-                                    "distribution_of: pb function must return \
-                                     Certain(v) or Uncertain(v), got {:?}", v
-                                ),
+                                RuntimeValue::Uncertain(_) | RuntimeValue::Fail => {}
+                                v => return Err(not_certain_or_uncertain(func_name, &v)),
                             }
                         }
                         let p = if *n > 0 { n_certain as f64 / *n as f64 } else { 0.0 };
-                        RuntimeValue::Dist(Dist::Bernoulli(Box::new(Expr::Float(p))))
+                        RuntimeValue::Dist(Dist::Bernoulli(Box::new(Expr::synthetic(ExprKind::Float(p)))))
                     }
 
                     DistributionOfMode::Bayesian(n) => {
@@ -1488,39 +3945,363 @@ impl RuntimeEnv {
                         let mut n_certain = 0i64;
                         let mut n_uncertain = 0i64;
                         for _ in 0..*n {
-                            match self.call_pb_func_once(&func, &eval_args) {
+                            match self.call_pb_func_once(&func, &eval_args)? {
                                 RuntimeValue::Certain(_) => n_certain += 1,
-                                RuntimeValue::Uncertain(_) => n_uncertain += 1,
-                                v => panic!(
-                                    "distribution_of: pb function must return \
-                                     Certain(v) or Uncertain(v), got {:?}", v
-                                ),
+                                RuntimeValue::Uncertain(_) | RuntimeValue::Fail => n_uncertain += 1,
+                                v => return Err(not_certain_or_uncertain(func_name, &v)),
                             }
                         }
                         let alpha = 1.0 + n_certain as f64;
                         let beta_val = 1.0 + n_uncertain as f64;
                         RuntimeValue::Dist(Dist::Beta(
-                            Box::new(Expr::Float(alpha)),
-                            Box::new(Expr::Float(beta_val)),
+                            Box::new(Expr::synthetic(ExprKind::Float(alpha))),
+                            Box::new(Expr::synthetic(ExprKind::Float(beta_val))),
                         ))
                     }
+
+                    DistributionOfMode::Outputs(n) => {
+                        // Run N single rounds and tally the values they return.
+                        let mut values = Vec::new();
+                        for _ in 0..*n {
+                            values.push(match self.call_pb_func_once(&func, &eval_args)? {
+                                RuntimeValue::Certain(v) | RuntimeValue::Uncertain(v) => *v,
+                                RuntimeValue::Fail => RuntimeValue::Fail,
+                                v => return Err(not_certain_or_uncertain(func_name, &v)),
+                            });
+                        }
+                        empirical_dist(func_name, values)?
+                    }
+
+                    DistributionOfMode::Rounds(n) => {
+                        // Run N trials of rounds-until-Certain; compare with the
+                        // analytical Geometric(1 - ε).
+                        let mut values = Vec::new();
+                        for _ in 0..*n {
+                            let mut rounds = 1;
+                            loop {
+                                match self.call_pb_func_once(&func, &eval_args)? {
+                                    RuntimeValue::Certain(_) => break,
+                                    RuntimeValue::Uncertain(_) | RuntimeValue::Fail => {}
+                                    v => return Err(not_certain_or_uncertain(func_name, &v)),
+                                }
+                                if rounds == MAX_ROUNDS_UNTIL_CERTAIN {
+                                    return Err(runtime_err!(
+                                        "distribution_of: '{}' did not return Certain within {} rounds",
+                                        func_name, MAX_ROUNDS_UNTIL_CERTAIN
+                                    ));
+                                }
+                                rounds += 1;
+                            }
+                            values.push(RuntimeValue::Int(rounds));
+                        }
+                        empirical_dist(func_name, values)?
+                    }
                 };
 
                 self.vars.insert(var.clone(), dist_val);
-                FlowControl::Continue
+                FlowControl::Normal
+            }
+        })
+    }
+}
+
+// Error Helpers
+
+fn check_arity(name: &str, expected: usize, got: usize) -> Result<()> {
+    if expected != got {
+        return Err(YapplError::ArityError { name: name.to_string(), expected, got });
+    }
+    Ok(())
+}
+
+fn check_probability(dist: &str, p: f64) -> Result<f64> {
+    if !(0.0..=1.0).contains(&p) {
+        return Err(runtime_err!("{} probability must be in [0, 1], got {}", dist, p));
+    }
+    Ok(p)
+}
+
+/// Reject a distribution parameter that must be strictly positive.
+fn check_positive(dist: &str, param: &str, v: f64) -> Result<f64> {
+    if v.is_nan() || v <= 0.0 {
+        return Err(runtime_err!("{} {} must be positive, got {}", dist, param, v));
+    }
+    Ok(v)
+}
+
+/// Reject a distribution parameter that must not be negative.
+fn check_non_negative(dist: &str, param: &str, v: f64) -> Result<f64> {
+    if v.is_nan() || v < 0.0 {
+        return Err(runtime_err!("{} {} must not be negative, got {}", dist, param, v));
+    }
+    Ok(v)
+}
+
+/// The answer `verify` checks rounds against when there is no reference
+/// function: for a one-sided class the first `Certain` answer, otherwise the
+/// most common answer (ties go to the first label). `Fail` if there is none.
+fn inferred_answer(rounds: &[(Option<RuntimeValue>, bool)], one_sided: bool) -> RuntimeValue {
+    if one_sided && let Some((Some(v), _)) = rounds.iter().find(|(_, certain)| *certain) {
+        return v.clone();
+    }
+    let mut tally: HashMap<String, (RuntimeValue, u64)> = HashMap::new();
+    for v in rounds.iter().filter_map(|(v, _)| v.as_ref()) {
+        tally.entry(RuntimeEnv::dyn_key(v)).or_insert((v.clone(), 0)).1 += 1;
+    }
+    tally
+        .into_iter()
+        .max_by(|(ka, (_, a)), (kb, (_, b))| a.cmp(b).then_with(|| kb.cmp(ka)))
+        .map(|(_, (v, _))| v)
+        .unwrap_or(RuntimeValue::Fail)
+}
+
+/// 95% Wilson score interval for a rate of `errors` out of `n`.
+fn wilson_interval(errors: u64, n: u64) -> (f64, f64) {
+    let (k, n) = (errors as f64, n as f64);
+    let z2 = VERIFY_Z * VERIFY_Z;
+    let rate = k / n;
+    let centre = (rate + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half = VERIFY_Z / (1.0 + z2 / n) * (rate * (1.0 - rate) / n + z2 / (4.0 * n * n)).sqrt();
+    ((centre - half).max(0.0), (centre + half).min(1.0))
+}
+
+/// The empirical distribution of `values` (each weighted 1/n) as a `DynDist`.
+fn empirical_dist(func_name: &str, values: Vec<RuntimeValue>) -> Result<RuntimeValue> {
+    if values.is_empty() {
+        return Err(runtime_err!("distribution_of: '{}' needs at least one sample", func_name));
+    }
+    let n = values.len() as u64;
+    let mut counts: HashMap<String, (RuntimeValue, u64)> = HashMap::new();
+    for value in values {
+        counts.entry(RuntimeEnv::dyn_key(&value)).or_insert((value, 0)).1 += 1;
+    }
+    let merged = counts
+        .into_iter()
+        .map(|(key, (value, count))| (key, (value, Prob::from(Fraction::new(count, n)))))
+        .collect();
+    Ok(RuntimeValue::DynDist(sorted_outcomes(merged)))
+}
+
+/// Merged `(value, probability)` pairs, sorted for deterministic output:
+/// numbers in numeric order, anything else by display label.
+fn sorted_outcomes(merged: HashMap<String, (RuntimeValue, Prob)>) -> Vec<(RuntimeValue, Prob)> {
+    let mut result: Vec<(RuntimeValue, Prob)> = merged.into_values().collect();
+    result.sort_by(|(a, _), (b, _)| match (exact_number(a), exact_number(b)) {
+        (Some(x), Some(y)) => x.to_f64().total_cmp(&y.to_f64()),
+        _ => format!("{}", a).cmp(&format!("{}", b)),
+    });
+    result
+}
+
+/// An expression that evaluates to `value`, if it can be written as a literal.
+fn literal_expr(value: &RuntimeValue, span: Span) -> Option<Expr> {
+    let kind = match value {
+        RuntimeValue::Int(n) => ExprKind::Int(*n),
+        RuntimeValue::Float(x) => ExprKind::Float(*x),
+        RuntimeValue::Frac(f) => ExprKind::Frac(*f),
+        RuntimeValue::Bool(b) => ExprKind::Bool(*b),
+        RuntimeValue::EnumVariant(_, variant) => ExprKind::Var(variant.clone()),
+        RuntimeValue::Array(elems) => {
+            ExprKind::Array(elems.iter().map(|e| literal_expr(e, span)).collect::<Option<_>>()?)
+        }
+        _ => return None,
+    };
+    Some(Expr::new(kind, span))
+}
+
+/// Total probability of the outcomes equal to `value`.
+fn outcome_prob(outcomes: &[(RuntimeValue, Prob)], value: &RuntimeValue) -> Prob {
+    outcomes
+        .iter()
+        .filter(|(v, _)| same_outcome(v, value))
+        .fold(Prob::zero(), |acc, (_, p)| acc + *p)
+}
+
+/// Whether two outcomes are the same value. Numbers compare by value (with
+/// booleans as 0 and 1, matching Bernoulli); anything else by label.
+fn same_outcome(a: &RuntimeValue, b: &RuntimeValue) -> bool {
+    match (exact_number(a), exact_number(b)) {
+        (Some(x), Some(y)) => x.to_f64() == y.to_f64(),
+        _ => RuntimeEnv::dyn_key(a) == RuntimeEnv::dyn_key(b),
+    }
+}
+
+/// A numeric outcome as an exact number where possible.
+fn exact_number(v: &RuntimeValue) -> Option<Prob> {
+    match v {
+        RuntimeValue::Int(n) => Some(Prob::Exact(RuntimeEnv::int_to_frac(*n))),
+        RuntimeValue::Bool(b) => Some(Prob::Exact(RuntimeEnv::int_to_frac(*b as i64))),
+        RuntimeValue::Frac(f) => Some(Prob::Exact(*f)),
+        RuntimeValue::Float(x) => Some(Prob::Approx(*x)),
+        _ => None,
+    }
+}
+
+/// Total variation distance 0.5 · Σ|p1(x) - p2(x)| between two outcome maps.
+fn total_variation(map1: &HashMap<String, f64>, map2: &HashMap<String, f64>) -> f64 {
+    let mut all_keys: Vec<&String> = map1.keys().chain(map2.keys()).collect();
+    all_keys.sort_unstable();
+    all_keys.dedup();
+    all_keys.iter()
+        .map(|k| {
+            let p1 = map1.get(*k).cloned().unwrap_or(0.0);
+            let p2 = map2.get(*k).cloned().unwrap_or(0.0);
+            (p1 - p2).abs()
+        })
+        .sum::<f64>() * 0.5
+}
+
+/// A probability as a runtime value: `Frac` when exact, `Float` otherwise.
+fn prob_value(p: Prob) -> RuntimeValue {
+    match p {
+        Prob::Exact(f) => RuntimeValue::Frac(f),
+        Prob::Approx(x) => RuntimeValue::Float(x),
+    }
+}
+
+/// Number of arguments each distribution query takes.
+fn query_arity(method: &str) -> usize {
+    match method {
+        "cdf" | "pdf" | "quantile" | "prob" => 1,
+        _ => 0,
+    }
+}
+
+fn check_quantile(q: f64) -> Result<f64> {
+    if !(0.0..=1.0).contains(&q) {
+        return Err(runtime_err!("quantile must be in [0, 1], got {}", q));
+    }
+    Ok(q)
+}
+
+/// Draw one value from a list of weighted outcomes.
+fn sample_outcomes<R: Rng>(rng: &mut R, outcomes: &[(RuntimeValue, Prob)]) -> RuntimeValue {
+    let r: f64 = rng.r#gen();
+    let mut cumulative = 0.0;
+    for (state, prob) in outcomes {
+        cumulative += prob.to_f64();
+        if r < cumulative {
+            return state.clone();
+        }
+    }
+    outcomes
+        .last()
+        .map(|(s, _)| s.clone())
+        .unwrap_or(RuntimeValue::Int(0))
+}
+
+/// Validate `index` against an array of length `len`, returning it as a `usize`.
+fn check_index(index: i64, len: usize) -> Result<usize> {
+    if index < 0 || index as usize >= len {
+        return Err(YapplError::IndexOutOfBounds { index, len });
+    }
+    Ok(index as usize)
+}
+
+fn as_array<'a>(builtin: &str, value: &'a RuntimeValue) -> Result<&'a [RuntimeValue]> {
+    match value {
+        RuntimeValue::Array(elems) => Ok(elems),
+        other => Err(type_err!("{}() expects an array, got {}", builtin, other)),
+    }
+}
+
+/// The error for calling a pb function like a regular function.
+fn pb_call_err(name: &str) -> YapplError {
+    contract_err!(
+        "'{}' is a probabilistic function; outside another pb function, call it \
+         with `let x, info = {}(args) with confidence >= val`",
+        name, name
+    )
+}
+
+/// Extract the function name passed to a built-in such as `posterior` or `verify`.
+fn func_name_arg(builtin: &str, position: &str, expr: &Expr) -> Result<String> {
+    match &expr.kind {
+        ExprKind::Var(n) => Ok(n.clone()),
+        _ => Err(type_err!("{}() {} argument must be a function name", builtin, position)),
+    }
+}
+
+/// Sum an array of numbers. Booleans count as 0/1 so that `sum` can tally the
+/// `true` results of a `map`. The result is a float if any element is a float.
+fn sum_values(elems: &[RuntimeValue]) -> Result<RuntimeValue> {
+    let mut int_total = 0i64;
+    let mut float_total = 0.0f64;
+    let mut any_float = false;
+    for elem in elems {
+        match elem {
+            RuntimeValue::Int(n) => int_total = int_total.checked_add(*n).ok_or_else(int_overflow)?,
+            RuntimeValue::Bool(b) => int_total = int_total.checked_add(*b as i64).ok_or_else(int_overflow)?,
+            RuntimeValue::Float(f) => {
+                float_total += f;
+                any_float = true;
+            }
+            other => return Err(type_err!("sum() expects numbers or bools, got {}", other)),
+        }
+    }
+    Ok(if any_float {
+        RuntimeValue::Float(float_total + int_total as f64)
+    } else {
+        RuntimeValue::Int(int_total)
+    })
+}
+
+/// The body of the first arm with a pattern matching `value`, and the names
+/// that pattern binds.
+fn select_arm<'a, B>(
+    value: &RuntimeValue,
+    arms: &'a [MatchArm<B>],
+) -> Result<(&'a B, Vec<(String, RuntimeValue)>)> {
+    for arm in arms {
+        for pattern in &arm.patterns {
+            let mut bindings = Vec::new();
+            if match_pattern(pattern, value, &mut bindings) {
+                return Ok((&arm.body, bindings));
             }
         }
     }
+    Err(runtime_err!("no match arm for {}", value))
+}
+
+/// Whether `pattern` matches `value`, collecting the names it binds. An int
+/// pattern also matches a whole-number float, such as the quotient `4 / 2`.
+fn match_pattern(pattern: &Pattern, value: &RuntimeValue, bindings: &mut Vec<(String, RuntimeValue)>) -> bool {
+    match (&pattern.kind, value) {
+        (PatternKind::Wildcard, _) => true,
+        (PatternKind::Binding(name), v) => {
+            bindings.push((name.clone(), v.clone()));
+            true
+        }
+        (PatternKind::Int(n), RuntimeValue::Int(m)) => n == m,
+        (PatternKind::Int(n), RuntimeValue::Float(x)) => *n as f64 == *x,
+        (PatternKind::Bool(b), RuntimeValue::Bool(c)) => b == c,
+        (PatternKind::Variant(v), RuntimeValue::EnumVariant(_, w)) => v == w,
+        (PatternKind::Certain(p), RuntimeValue::Certain(v))
+        | (PatternKind::Uncertain(p), RuntimeValue::Uncertain(v)) => match_pattern(p, v, bindings),
+        (PatternKind::Fail, RuntimeValue::Fail) => true,
+        _ => false,
+    }
+}
+
+/// Integer arithmetic whose result does not fit in an `int`.
+fn int_overflow() -> YapplError {
+    runtime_err!("integer overflow")
+}
+
+fn not_certain_or_uncertain(func_name: &str, v: &RuntimeValue) -> YapplError {
+    contract_err!(
+        "pb function '{}' must return Certain(v) or Uncertain(v), got {}",
+        func_name, v
+    )
 }
 
 // Pure Maths Helpers
 
 /// Iterative Jacobi symbol (a/n). Returns -1, 0, or 1.
-/// n must be a positive integer. If n is even (or 1) the symbol is not
+/// n must be a positive integer (checked by the caller). If n is even (or 1) the symbol is not
 /// classically defined, but we return 0 — this signals compositeness to
 /// the Solovay-Strassen algorithm, producing the correct `Certain(false)`.
 fn jacobi_symbol(mut a: i64, mut n: i64) -> i64 {
-    assert!(n > 0, "jacobi: n must be positive (got n={})", n);
     // Even n is composite; return 0 so the caller detects a non-prime.
     if n % 2 == 0 {
         return 0;
@@ -1545,12 +4326,14 @@ fn jacobi_symbol(mut a: i64, mut n: i64) -> i64 {
 }
 
 /// Modular exponentiation: base^exp mod modulus (all must be non-negative).
-fn mod_exp(mut base: i64, mut exp: i64, modulus: i64) -> i64 {
+fn mod_exp(base: i64, mut exp: i64, modulus: i64) -> i64 {
     if modulus == 1 {
         return 0;
     }
-    let mut result = 1i64;
-    base = base.rem_euclid(modulus);
+    // Products of two residues can exceed i64, so multiply in i128.
+    let modulus = modulus as i128;
+    let mut result = 1i128;
+    let mut base = (base as i128).rem_euclid(modulus);
     while exp > 0 {
         if exp % 2 == 1 {
             result = result * base % modulus;
@@ -1558,81 +4341,446 @@ fn mod_exp(mut base: i64, mut exp: i64, modulus: i64) -> i64 {
         exp /= 2;
         base = base * base % modulus;
     }
-    result
+    result as i64
 }
 
-/// Minimum rounds needed to achieve `target_confidence`.
-fn compute_rounds_needed(error_class: &ErrorClass, target_confidence: f64) -> u64 {
-    let error = 1.0 - target_confidence;
-    let k = match error_class {
-        // RP / coRP: per-round error ≤ 1/2 (Geometric decay).
-        // Need (1/2)^k ≤ error  →  k ≥ −log₂(error)
-        ErrorClass::RP | ErrorClass::CoRP => (-error.log2()).ceil(),
-        // BPP: majority-vote + Chernoff bound (p = 3/4 assumed).
-        // Need exp(−k/8) ≤ error  →  k ≥ −8·ln(error)
-        ErrorClass::BPP => (-8.0 * error.ln()).ceil(),
-    };
-    (k as u64).max(1)
+/// How a pb function's error shrinks as rounds are added, built from its
+/// `error_class`, `error_distribution` and optional declared error bound.
+struct ErrorModel {
+    /// Probability that a single round's answer is wrong.
+    per_round: f64,
+    decay: ErrorDecay,
+}
+
+/// The decay laws behind the `error_distribution` families.
+enum ErrorDecay {
+    /// `Geometric` (RP / coRP / ZPP): a wrong answer (or, for ZPP, no answer)
+    /// needs every round to miss, ε^k.
+    Geometric,
+    /// `Binomial` (BPP): the correct votes are Binomial(k, 1 - ε), so a
+    /// majority vote errs with probability ≤ exp(-2k(1/2 - ε)²) (Chernoff).
+    Binomial,
+}
+
+impl ErrorModel {
+    /// Validate a pb function's metadata and build its error model. Errors
+    /// point at the metadata entry at fault.
+    fn of(func: &PbFuncDef) -> Result<Self> {
+        let decay = match (func.error_distribution.as_str(), &func.error_class) {
+            ("Geometric", ErrorClass::RP | ErrorClass::CoRP | ErrorClass::ZPP) => ErrorDecay::Geometric,
+            ("Binomial", ErrorClass::BPP) => ErrorDecay::Binomial,
+            ("Geometric" | "Binomial", class) => {
+                return Err(contract_err!(
+                    "pb function '{}': error_distribution {} does not describe {} error; \
+                     use Geometric for RP/coRP/ZPP and Binomial for BPP",
+                    func.name, func.error_distribution, class
+                ).with_span(func.metadata_span));
+            }
+            (other, _) => {
+                return Err(contract_err!(
+                    "pb function '{}': unknown error_distribution '{}'; expected Geometric or Binomial",
+                    func.name, other
+                ).with_span(func.metadata_span));
+            }
+        };
+        let (per_round, limit) = match decay {
+            ErrorDecay::Geometric => (func.error_bound.unwrap_or(0.5), 1.0),
+            ErrorDecay::Binomial => (func.error_bound.unwrap_or(0.25), 0.5),
+        };
+        // Only a ratio with a zero denominator parses to a non-finite bound.
+        if !per_round.is_finite() {
+            return Err(contract_err!(
+                "pb function '{}': the declared error bound divides by zero",
+                func.name
+            ).with_span(func.error_bound_span));
+        }
+        // A per-round error of `limit` or more cannot be amplified, and a
+        // declared error of 0 would need no rounds at all.
+        if per_round <= 0.0 || per_round >= limit {
+            return Err(contract_err!(
+                "pb function '{}': {} per-round error must be in (0, {}), got {}",
+                func.name, func.error_class, limit, per_round
+            ).with_span(func.error_bound_span));
+        }
+        Ok(ErrorModel { per_round, decay })
+    }
+
+    /// Probability that the combined answer is still wrong after `rounds` rounds.
+    fn error_after(&self, rounds: u64) -> f64 {
+        let k = rounds as f64;
+        match self.decay {
+            ErrorDecay::Geometric => self.per_round.powf(k),
+            ErrorDecay::Binomial => (-2.0 * k * (0.5 - self.per_round).powi(2)).exp(),
+        }
+    }
+
+    /// Smallest lead (votes for the leader minus all other votes) at which a
+    /// BPP vote may stop with error at most `error`. Counting votes for the
+    /// right answer minus the rest is a walk that steps up with probability
+    /// 1 - ε; a wrong leader means it fell `lead` below zero, which happens at
+    /// any time with probability (ε / (1 - ε))^lead (gambler's ruin).
+    fn lead_needed(&self, error: f64) -> u64 {
+        let ratio = self.per_round / (1.0 - self.per_round);
+        ((error.ln() / ratio.ln()).ceil() as u64).max(1)
+    }
+
+    /// Chance that a vote stopped at `lead` picked the wrong answer.
+    fn lead_error(&self, lead: u64) -> f64 {
+        (self.per_round / (1.0 - self.per_round)).powf(lead as f64)
+    }
+
+    /// Minimum rounds needed to achieve `target_confidence`.
+    fn rounds_needed(&self, target_confidence: f64) -> u64 {
+        let error = 1.0 - target_confidence;
+        let k = match self.decay {
+            // Need ε^k ≤ error  →  k ≥ log₂(error) / log₂(ε)
+            ErrorDecay::Geometric => (error.log2() / self.per_round.log2()).ceil(),
+            // Need exp(−2k(1/2 − ε)²) ≤ error  →  k ≥ −ln(error) / (2(1/2 − ε)²)
+            ErrorDecay::Binomial => (-error.ln() / (2.0 * (0.5 - self.per_round).powi(2))).ceil(),
+        };
+        (k as u64).max(1)
+    }
+}
+
+/// Sample a standard normal N(0, 1) with the Box-Muller transform.
+fn sample_std_normal<R: rand::Rng>(rng: &mut R) -> f64 {
+    // 1 - U lies in (0, 1], so the log is finite.
+    let u1: f64 = 1.0 - rng.r#gen::<f64>();
+    let u2: f64 = rng.r#gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Sample Gamma(shape, 1) for any positive shape (Marsaglia-Tsang).
+/// Shapes below 1 are boosted to shape + 1 and scaled back by U^(1/shape).
+fn sample_gamma<R: rand::Rng>(rng: &mut R, shape: f64) -> f64 {
+    if shape < 1.0 {
+        let u: f64 = 1.0 - rng.r#gen::<f64>();
+        return sample_gamma(rng, shape + 1.0) * u.powf(1.0 / shape);
+    }
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = sample_std_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f64 = 1.0 - rng.r#gen::<f64>();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
 }
 
-/// Sample Gamma(n, 1) for integer n using the sum-of-exponentials identity:
-///   Gamma(n, 1) = -ln(U₁ · U₂ · … · Uₙ) = sum of n i.i.d. Exp(1) values.
-fn sample_gamma_int<R: rand::Rng>(rng: &mut R, n: u64) -> f64 {
-    (0..n.max(1)).map(|_| -rng.r#gen::<f64>().ln()).sum()
+/// Sample Poisson(lambda) by counting Exp(1) arrivals before time `lambda`.
+fn sample_poisson<R: rand::Rng>(rng: &mut R, lambda: f64) -> i64 {
+    let mut count = 0;
+    let mut t = -(1.0 - rng.r#gen::<f64>()).ln();
+    while t < lambda {
+        count += 1;
+        t -= (1.0 - rng.r#gen::<f64>()).ln();
+    }
+    count
 }
 
 /// Sample from Beta(alpha, beta) using the Gamma relationship:
 ///   X = Ga / (Ga + Gb)  where Ga ~ Gamma(alpha, 1), Gb ~ Gamma(beta, 1).
-/// Works exactly for integer alpha/beta (as produced by the Bayesian update).
 fn sample_beta<R: rand::Rng>(rng: &mut R, alpha: f64, beta: f64) -> f64 {
-    let a = alpha.round().max(1.0) as u64;
-    let b = beta.round().max(1.0) as u64;
-    let ga = sample_gamma_int(rng, a);
-    let gb = sample_gamma_int(rng, b);
+    let ga = sample_gamma(rng, alpha);
+    let gb = sample_gamma(rng, beta);
     let total = ga + gb;
     if total == 0.0 { 0.5 } else { (ga / total).clamp(0.0, 1.0) }
 }
 
-/// Binomial coefficient C(n, k).
-fn binom_coeff(n: u64, k: u64) -> u64 {
+/// Natural log of the Gamma function (Lanczos approximation, g = 7).
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection formula: Γ(x)·Γ(1 − x) = π / sin(πx).
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let series = COEFFS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFS[0], |acc, (i, c)| acc + c / (x + i as f64 + 1.0));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+/// ln C(n, k), valid for non-integer arguments and large n.
+fn ln_choose(n: f64, k: f64) -> f64 {
+    ln_gamma(n + 1.0) - ln_gamma(k + 1.0) - ln_gamma(n - k + 1.0)
+}
+
+/// `x · ln(y)`, taking 0 · ln(0) as 0.
+fn xlny(x: f64, y: f64) -> f64 {
+    if x == 0.0 { 0.0 } else { x * y.ln() }
+}
+
+/// Digamma function ψ(x) = d/dx ln Γ(x) for x > 0: shift x up with
+/// ψ(x) = ψ(x + 1) − 1/x, then use the asymptotic series.
+fn digamma(mut x: f64) -> f64 {
+    let mut result = 0.0;
+    while x < 6.0 {
+        result -= 1.0 / x;
+        x += 1.0;
+    }
+    let inv2 = 1.0 / (x * x);
+    result + x.ln() - 0.5 / x - inv2 * (1.0 / 12.0 - inv2 * (1.0 / 120.0 - inv2 / 252.0))
+}
+
+/// Lower regularized incomplete gamma function P(a, x): the series for
+/// x < a + 1, otherwise the continued fraction for Q = 1 − P.
+fn gamma_p(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefactor = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let (mut ap, mut term) = (a, 1.0 / a);
+        let mut sum = term;
+        for _ in 0..1000 {
+            ap += 1.0;
+            term *= x / ap;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        (sum * prefactor).min(1.0)
+    } else {
+        let b = x + 1.0 - a;
+        let q = lentz(b, |i| -i * (i - a), |i| b + 2.0 * i);
+        (1.0 - prefactor / q).max(0.0)
+    }
+}
+
+/// Regularized incomplete beta function I_x(a, b).
+fn inc_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // The continued fraction converges quickly below the mean; use
+    // I_x(a, b) = 1 − I_{1−x}(b, a) above it.
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front / (a * beta_fraction(a, b, x))
+    } else {
+        1.0 - front / (b * beta_fraction(b, a, 1.0 - x))
+    }
+}
+
+/// Continued fraction for the incomplete beta function; its odd and even
+/// steps have different numerators.
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    let numerator = |i: f64| {
+        let m = (i / 2.0).floor();
+        if i % 2.0 == 0.0 {
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m))
+        } else {
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0))
+        }
+    };
+    lentz(1.0, numerator, |_| 1.0)
+}
+
+/// Evaluate b0 + a1/(b1 + a2/(b2 + ...)) with the modified Lentz method.
+fn lentz(b0: f64, a: impl Fn(f64) -> f64, b: impl Fn(f64) -> f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut f = if b0 == 0.0 { TINY } else { b0 };
+    let (mut c, mut d) = (f, 0.0);
+    for i in 1..1000 {
+        let (ai, bi) = (a(i as f64), b(i as f64));
+        d = bi + ai * d;
+        d = if d == 0.0 { TINY } else { d };
+        c = bi + ai / c;
+        c = if c == 0.0 { TINY } else { c };
+        d = 1.0 / d;
+        let delta = c * d;
+        f *= delta;
+        if (delta - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    f
+}
+
+/// Log of the MH acceptance ratio for moving from run `old` to the proposal
+/// `new`, which resampled one choice of `old` and replayed the others where it
+/// could. Fresh draws in `new` and the draws of `old` that were not reused are
+/// the proposal probabilities in each direction.
+fn mh_log_acceptance(old: &SampleRun, new: &SampleRun) -> f64 {
+    let fresh: f64 = new.trace.iter().filter(|d| d.fresh).map(|d| d.log_prob).sum();
+    let stale: f64 = old
+        .trace
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| new.trace.get(*i).is_none_or(|d| d.fresh))
+        .map(|(_, d)| d.log_prob)
+        .sum();
+    new.log_score() - old.log_score() + (old.trace.len() as f64).ln() - (new.trace.len() as f64).ln()
+        + stale
+        - fresh
+}
+
+/// Effective sample size of an MCMC chain. Numeric chains use the values
+/// themselves; other chains take the smallest ESS over the indicator of each
+/// distinct value.
+fn chain_ess(chain: &[RuntimeValue]) -> f64 {
+    let numeric: Option<Vec<f64>> = chain
+        .iter()
+        .map(|v| match v {
+            RuntimeValue::Bool(b) => Some(*b as u8 as f64),
+            other => other.as_f64().ok(),
+        })
+        .collect();
+    if let Some(xs) = numeric {
+        return series_ess(&xs);
+    }
+    let keys: Vec<String> = chain.iter().map(RuntimeEnv::dyn_key).collect();
+    let distinct: HashSet<&String> = keys.iter().collect();
+    distinct
+        .into_iter()
+        .map(|k| series_ess(&keys.iter().map(|c| (c == k) as u8 as f64).collect::<Vec<_>>()))
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// n / (1 + 2·Σρₖ), summing autocorrelations ρₖ until the first non-positive one.
+fn series_ess(xs: &[f64]) -> f64 {
+    let n = xs.len() as f64;
+    let mean = xs.iter().sum::<f64>() / n;
+    let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    if var == 0.0 {
+        return n;
+    }
+    let mut rho_sum = 0.0;
+    for lag in 1..xs.len() {
+        let cov = xs.iter().zip(&xs[lag..]).map(|(a, b)| (a - mean) * (b - mean)).sum::<f64>() / n;
+        let rho = cov / var;
+        if rho <= 0.0 {
+            break;
+        }
+        rho_sum += rho;
+    }
+    n / (1.0 + 2.0 * rho_sum)
+}
+
+/// P(X = k) for X ~ Binomial(n, p): exact while C(n, k) fits in a `u64` and
+/// the powers of p stay representable, otherwise computed in log space.
+fn binomial_pmf(n: u64, k: u64, p: Prob) -> Prob {
+    match binom_coeff(n, k) {
+        Some(c) => Prob::Exact(Fraction::from(c)) * p.powi(k) * (Prob::one() - p).powi(n - k),
+        None => {
+            let (n, k, p) = (n as f64, k as f64, p.to_f64());
+            Prob::Approx((ln_choose(n, k) + xlny(k, p) + xlny(n - k, 1.0 - p)).exp())
+        }
+    }
+}
+
+/// Binomial coefficient C(n, k), or `None` if it does not fit in a `u64`.
+fn binom_coeff(n: u64, k: u64) -> Option<u64> {
     if k > n {
-        return 0;
+        return Some(0);
     }
     let k = k.min(n - k); // use symmetry
     let mut result = 1u64;
     for i in 0..k {
-        result = result * (n - i) / (i + 1);
+        // result · (n - i) is divisible by i + 1, since it is (i + 1) · C(n, i + 1).
+        result = result.checked_mul(n - i)? / (i + 1);
     }
-    result
+    Some(result)
 }
 
 // Public API
 
-/// Execute all program items and return the output lines.
-/// Shared by `run`, `run_to_string`, and `run_to_html`.
-fn collect_output(items: &[ProgramItem]) -> Vec<OutputLine> {
-    let mut env = RuntimeEnv::new();
+/// Execute all program items and return the output lines. The items should
+/// already have passed `typecheck::check`.
+/// Shared by `run`, `run_to_string`, and `run_to_html`. With a `seed`, every
+/// random draw (and so the output) is the same from run to run.
+pub fn collect_output(items: &[ProgramItem], seed: Option<u64>) -> Result<Vec<OutputLine>> {
+    let mut env = RuntimeEnv::new(seed);
+    exec_program(&mut env, items)?;
+    Ok(env.output)
+}
+
+fn exec_program(env: &mut RuntimeEnv, items: &[ProgramItem]) -> Result<()> {
     // Two-pass: register all definitions before executing statements,
     // so call-before-definition works.
     for item in items {
         match item {
             ProgramItem::FuncDef(f) => { env.funcs.insert(f.name.clone(), f.clone()); }
-            ProgramItem::PbFuncDef(f) => { env.pb_funcs.insert(f.name.clone(), f.clone()); }
+            ProgramItem::PbFuncDef(f) => {
+                ErrorModel::of(f)?;
+                env.pb_funcs.insert(f.name.clone(), f.clone());
+            }
             ProgramItem::EnumDef(e) => { env.register_enum(e); }
             ProgramItem::Statement(_) => {}
         }
     }
     for item in items {
         if let ProgramItem::Statement(stmt) = item {
-            env.exec_stmt(stmt);
+            env.exec_stmt(stmt)?;
+        }
+    }
+    Ok(())
+}
+
+/// `--verify` mode: run the program, then verify every pb function on the
+/// distinct inputs it was called with. Returns whether every check passed.
+pub fn run_verify(items: &[ProgramItem], seed: Option<u64>) -> Result<bool> {
+    let mut env = RuntimeEnv::new(seed);
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    env.pb_calls = Some(Rc::clone(&calls));
+    exec_program(&mut env, items)?;
+    env.pb_calls = None;
+    print_output(std::mem::take(&mut env.output));
+
+    // Group the distinct inputs by function, in order of first call.
+    let mut inputs: Vec<(String, Vec<Vec<RuntimeValue>>)> = Vec::new();
+    let mut seen = HashSet::new();
+    for (name, args) in calls.take() {
+        let keys: Vec<String> = args.iter().map(RuntimeEnv::dyn_key).collect();
+        if !seen.insert((name.clone(), keys)) {
+            continue;
+        }
+        match inputs.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, list)) => list.push(args),
+            None => inputs.push((name, vec![args])),
         }
     }
-    env.output
+
+    let mut passed = true;
+    for (name, args) in inputs {
+        let func = env.pb_funcs[&name].clone();
+        let report = env.verify_pb_func(&func, args, VERIFY_CLI_TRIALS, None)?;
+        passed &= report.passed();
+        println!("{}", report);
+    }
+    Ok(passed)
 }
 
 /// CLI mode: print output to stdout, rendering histograms as ASCII art.
-pub fn run(items: &[ProgramItem]) {
-    for line in collect_output(items) {
+pub fn run(items: &[ProgramItem], seed: Option<u64>) -> Result<()> {
+    print_output(collect_output(items, seed)?);
+    Ok(())
+}
+
+fn print_output(lines: Vec<OutputLine>) {
+    for line in lines {
         match line {
             OutputLine::Text(s) => println!("{}", s),
             OutputLine::Hist(data) => print!("{}", visualiser::render_cli(&data)),
@@ -1642,9 +4790,9 @@ pub fn run(items: &[ProgramItem]) {
 
 /// Return output as a plain string (for tests and `try_run_program`).
 /// Histograms are rendered as ASCII art.
-pub fn run_to_string(items: &[ProgramItem]) -> String {
+pub fn run_to_string(items: &[ProgramItem], seed: Option<u64>) -> Result<String> {
     let mut parts: Vec<String> = Vec::new();
-    for line in collect_output(items) {
+    for line in collect_output(items, seed)? {
         match line {
             OutputLine::Text(s) => parts.push(s),
             OutputLine::Hist(data) => {
@@ -1653,17 +4801,17 @@ pub fn run_to_string(items: &[ProgramItem]) -> String {
             }
         }
     }
-    parts.join("\n")
+    Ok(parts.join("\n"))
 }
 
 /// Return output as an HTML string for the web playground.
 /// Histograms are rendered as inline SVG; text is HTML-escaped inside `<pre>`.
-pub fn run_to_html(items: &[ProgramItem]) -> String {
+pub fn run_to_html(items: &[ProgramItem], seed: Option<u64>) -> Result<String> {
     let mut html = String::new();
     let mut text_buf: Vec<String> = Vec::new();
     let mut vis_idx: usize = 0;
 
-    for line in collect_output(items) {
+    for line in collect_output(items, seed)? {
         match line {
             OutputLine::Text(s) => text_buf.push(s),
             OutputLine::Hist(data) => {
@@ -1685,26 +4833,13 @@ pub fn run_to_html(items: &[ProgramItem]) -> String {
         html.push_str(&visualiser::html_esc(&text_buf.join("\n")));
         html.push_str("</pre>");
     }
-    html
+    Ok(html)
 }
 
-/// Run a YAPPL source string, returning Ok(output) or Err(error message).
-/// Catches both parse errors and runtime panics.
-pub fn try_run_program(source: &str) -> Result<String, String> {
-    use std::panic::{self, AssertUnwindSafe};
-    let result = panic::catch_unwind(AssertUnwindSafe(|| {
-        let items = crate::parser::parse(source);
-        run_to_string(&items)
-    }));
-    match result {
-        Ok(output) => Ok(output),
-        Err(e) => {
-            let msg = e
-                .downcast_ref::<String>()
-                .cloned()
-                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
-                .unwrap_or_else(|| "Unknown error".to_string());
-            Err(msg)
-        }
-    }
+/// Run a YAPPL source string end to end, returning its output or the first
+/// parse, type or runtime error encountered.
+pub fn try_run_program(source: &str) -> Result<String> {
+    let items = crate::parser::parse(source)?;
+    crate::typecheck::check(&items)?;
+    run_to_string(&items, None)
 }
@@ -3,24 +3,51 @@ use std::fs;
 use std::path::Path;
 
 mod ast;
+mod error;
 mod interpreter;
+mod markov;
 mod parser;
+mod prob;
+mod typecheck;
 mod visualiser;
 mod web;
 
 #[cfg(test)]
 mod tests;
 
-use interpreter::{run, try_run_program};
+use interpreter::{run, run_verify, try_run_program};
 use parser::parse;
+use typecheck::check;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    // `--seed N` can go anywhere on the command line.
+    let seed = match args.iter().position(|a| a == "--seed") {
+        Some(i) => {
+            let Some(seed) = args.get(i + 1).and_then(|s| s.parse::<u64>().ok()) else {
+                eprintln!("--seed expects a non-negative integer");
+                std::process::exit(1);
+            };
+            args.drain(i..i + 2);
+            Some(seed)
+        }
+        None => None,
+    };
+
+    // `--verify` can also go anywhere.
+    let verify = match args.iter().position(|a| a == "--verify") {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    };
 
     if args.len() < 2 {
         eprintln!(
-            "Usage:\n  {} <filename>      Run a program file\n  {} --web [port]   Start the web playground (default port: 8080)\n  {} --test         Run the deterministic test suite",
-            args[0], args[0], args[0]
+            "Usage:\n  {} <filename> [--seed N]   Run a program file (with a fixed random seed)\n  {} <filename> --verify     Run a program, then check each pb function it called against its error class\n  {} --web [port]   Start the web playground (default port: 8080)\n  {} --test         Run the deterministic test suite",
+            args[0], args[0], args[0], args[0]
         );
         return;
     }
@@ -35,9 +62,19 @@ fn main() {
         }
         filename => {
             match fs::read_to_string(filename) {
+                Ok(text) if verify => match parse_checked(&text).and_then(|items| run_verify(&items, seed)) {
+                    Ok(true) => {}
+                    Ok(false) => std::process::exit(1),
+                    Err(e) => {
+                        eprintln!("{}", e.render(&text, filename));
+                        std::process::exit(1);
+                    }
+                },
                 Ok(text) => {
-                    let items = parse(&text);
-                    run(&items);
+                    if let Err(e) = parse_checked(&text).and_then(|items| run(&items, seed)) {
+                        eprintln!("{}", e.render(&text, filename));
+                        std::process::exit(1);
+                    }
                 }
                 Err(e) => eprintln!("Error reading file '{}': {}", filename, e),
             }
@@ -45,12 +82,17 @@ fn main() {
     }
 }
 
+/// Parse `source` and type-check it, so no statement runs in a program with
+/// a type error.
+fn parse_checked(source: &str) -> error::Result<Vec<ast::ProgramItem>> {
+    let items = parse(source)?;
+    check(&items)?;
+    Ok(items)
+}
+
 // CLI Test Runner
 
 fn run_test_suite() {
-    // Silence the default panic hook so error-test panics don't spam stderr.
-    std::panic::set_hook(Box::new(|_| {}));
-
     let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("Sample/Deterministic");
     let mut passed = 0usize;
     let mut failed = 0usize;
@@ -86,10 +128,10 @@ fn run_test_suite() {
             let result = try_run_program(&source);
             let (ok, detail) = if expect_failure {
                 match result {
-                    Err(msg) if msg.contains(expected) => (true, String::new()),
-                    Err(msg) => (
+                    Err(e) if e.to_string().contains(expected) => (true, String::new()),
+                    Err(e) => (
                         false,
-                        format!("expected error containing {:?}, got: {:?}", expected, msg),
+                        format!("expected error containing {:?}, got: {:?}", expected, e.to_string()),
                     ),
                     Ok(out) => (
                         false,
@@ -103,7 +145,7 @@ fn run_test_suite() {
                         false,
                         format!("expected {:?}, got {:?}", expected, output.trim()),
                     ),
-                    Err(msg) => (false, format!("unexpected error: {}", msg)),
+                    Err(e) => (false, format!("unexpected error: {}", e)),
                 }
             };
 
@@ -0,0 +1,210 @@
+/// Exact analysis of finite Markov chains.
+///
+/// A chain is a square matrix of transition probabilities between states
+/// `0..n`. Linear systems are solved by Gauss-Jordan elimination over `Prob`,
+/// so answers stay exact fractions unless the arithmetic overflows.
+use std::collections::VecDeque;
+
+use crate::prob::Prob;
+
+/// Pivots smaller than this are treated as zero once values are approximate.
+const APPROX_ZERO: f64 = 1e-12;
+
+pub struct Chain {
+    /// `p[i][j]`: probability of moving from state `i` to state `j` in one step.
+    pub p: Vec<Vec<Prob>>,
+}
+
+/// Why an absorption or hitting-time question has no finite answer.
+pub enum Unreachable {
+    /// No state is absorbing.
+    NoAbsorbingStates,
+    /// From the start the chain can reach this state, from which the goal
+    /// can never be reached.
+    Escapes(usize),
+    /// The linear system lost its pivots to rounding once the probabilities
+    /// became approximate, so it has no reliable solution.
+    Singular,
+}
+
+impl Chain {
+    fn len(&self) -> usize {
+        self.p.len()
+    }
+
+    /// The unique distribution π with πP = π, or `None` if there is more than
+    /// one (the chain has several closed classes).
+    pub fn stationary(&self) -> Option<Vec<Prob>> {
+        let n = self.len();
+        // (Pᵀ - I)π = 0, with the last equation replaced by Σπ = 1.
+        let mut a: Vec<Vec<Prob>> = (0..n)
+            .map(|i| {
+                (0..n)
+                    .map(|j| if i == j { self.p[j][i] - Prob::one() } else { self.p[j][i] })
+                    .collect()
+            })
+            .collect();
+        let mut b = vec![Prob::zero(); n];
+        a[n - 1] = vec![Prob::one(); n];
+        b[n - 1] = Prob::one();
+        solve(a, b)
+    }
+
+    /// For each absorbing state, the probability that the chain started at
+    /// `start` is eventually absorbed there.
+    pub fn absorption(&self, start: usize) -> Result<Vec<(usize, Prob)>, Unreachable> {
+        let absorbing: Vec<usize> = (0..self.len()).filter(|&i| self.is_absorbing(i)).collect();
+        if absorbing.is_empty() {
+            return Err(Unreachable::NoAbsorbingStates);
+        }
+        if self.is_absorbing(start) {
+            return Ok(absorbing.iter().map(|&a| (a, prob_of(a == start))).collect());
+        }
+        let transient = self.transient_states(start, &absorbing)?;
+
+        // For each absorbing a: b_i = P(i, a) + Σ_{j transient} P(i, j) b_j.
+        let mut result = Vec::with_capacity(absorbing.len());
+        for &a in &absorbing {
+            let rhs = transient.iter().map(|&i| self.p[i][a]).collect();
+            let b = solve(self.step_system(&transient), rhs).ok_or(Unreachable::Singular)?;
+            result.push((a, b[0]));
+        }
+        Ok(result)
+    }
+
+    /// Expected number of steps for the chain started at `start` to first
+    /// reach `target` (0 if it starts there).
+    pub fn hitting_time(&self, start: usize, target: usize) -> Result<Prob, Unreachable> {
+        if start == target {
+            return Ok(Prob::zero());
+        }
+        let transient = self.transient_states(start, &[target])?;
+        // h_i = 1 + Σ_{j transient} P(i, j) h_j.
+        let rhs = vec![Prob::one(); transient.len()];
+        let h = solve(self.step_system(&transient), rhs).ok_or(Unreachable::Singular)?;
+        Ok(h[0])
+    }
+
+    /// Irreducible (every state reaches every other) and aperiodic.
+    pub fn is_ergodic(&self) -> bool {
+        let n = self.len();
+        if (0..n).any(|i| self.reachable_from(i, &[]).iter().any(|r| !r)) {
+            return false;
+        }
+        self.period() == 1
+    }
+
+    /// The gcd of all cycle lengths of an irreducible chain: with BFS depths
+    /// d from state 0, the gcd of d(i) + 1 - d(j) over every edge i → j.
+    fn period(&self) -> usize {
+        let n = self.len();
+        let mut depth = vec![usize::MAX; n];
+        depth[0] = 0;
+        let mut queue = VecDeque::from([0]);
+        while let Some(i) = queue.pop_front() {
+            for j in self.successors(i) {
+                if depth[j] == usize::MAX {
+                    depth[j] = depth[i] + 1;
+                    queue.push_back(j);
+                }
+            }
+        }
+        let mut period = 0;
+        for i in 0..n {
+            for j in self.successors(i) {
+                period = gcd(period, (depth[i] + 1).abs_diff(depth[j]));
+            }
+        }
+        period
+    }
+
+    fn is_absorbing(&self, i: usize) -> bool {
+        self.p[i][i] == Prob::one() || (self.p[i][i].to_f64() - 1.0).abs() < APPROX_ZERO
+    }
+
+    fn successors(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).filter(move |&j| !self.p[i][j].is_zero())
+    }
+
+    /// `reachable[j]`: whether `j` can be reached from `start` without
+    /// passing through any state in `stop` (which are reachable but not expanded).
+    fn reachable_from(&self, start: usize, stop: &[usize]) -> Vec<bool> {
+        let mut reachable = vec![false; self.len()];
+        reachable[start] = true;
+        let mut queue = VecDeque::from([start]);
+        while let Some(i) = queue.pop_front() {
+            if stop.contains(&i) {
+                continue;
+            }
+            for j in self.successors(i) {
+                if !reachable[j] {
+                    reachable[j] = true;
+                    queue.push_back(j);
+                }
+            }
+        }
+        reachable
+    }
+
+    /// The states visited from `start` before reaching a state in `goals`,
+    /// with `start` first. Fails if any of them cannot reach `goals`.
+    fn transient_states(&self, start: usize, goals: &[usize]) -> Result<Vec<usize>, Unreachable> {
+        let visited = self.reachable_from(start, goals);
+        let mut transient = vec![start];
+        transient.extend((0..self.len()).filter(|&i| i != start && visited[i] && !goals.contains(&i)));
+        for &i in &transient {
+            let reaches = self.reachable_from(i, goals);
+            if !goals.iter().any(|&g| reaches[g]) {
+                return Err(Unreachable::Escapes(i));
+            }
+        }
+        Ok(transient)
+    }
+
+    /// The matrix I - Q, where Q is P restricted to `states`.
+    fn step_system(&self, states: &[usize]) -> Vec<Vec<Prob>> {
+        states
+            .iter()
+            .map(|&i| {
+                states
+                    .iter()
+                    .map(|&j| if i == j { Prob::one() - self.p[i][j] } else { Prob::zero() - self.p[i][j] })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Solve `a x = b` by Gauss-Jordan elimination, or `None` if `a` is singular.
+fn solve(mut a: Vec<Vec<Prob>>, mut b: Vec<Prob>) -> Option<Vec<Prob>> {
+    let n = b.len();
+    for col in 0..n {
+        // Prefer the largest pivot, which only matters once values are approximate.
+        let pivot = (col..n).max_by(|&r, &s| a[r][col].to_f64().abs().total_cmp(&a[s][col].to_f64().abs()))?;
+        if a[pivot][col].to_f64().abs() < APPROX_ZERO {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in 0..n {
+            if row == col || a[row][col].is_zero() {
+                continue;
+            }
+            let factor = a[row][col] / a[col][col];
+            let pivot_row = a[col].clone();
+            for (x, &y) in a[row].iter_mut().zip(&pivot_row).skip(col) {
+                *x = *x - factor * y;
+            }
+            b[row] = b[row] - factor * b[col];
+        }
+    }
+    Some((0..n).map(|i| b[i] / a[i][i]).collect())
+}
+
+fn prob_of(certain: bool) -> Prob {
+    if certain { Prob::one() } else { Prob::zero() }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
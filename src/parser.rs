@@ -1,17 +1,54 @@
+use std::cell::Cell;
 use std::str::FromStr;
 
 use nom::{
-    IResult,
     Parser,
     branch::alt,
     bytes::complete::{tag, take_while, take_while1},
     character::complete::{multispace0, satisfy},
     combinator::{map, opt, peek, recognize},
-    multi::many0,
-    sequence::{delimited, pair, preceded, terminated},
+    error::{ErrorKind, ParseError},
+    multi::{many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
 };
 
 use crate::ast::*;
+use crate::error::{Result, YapplError, line_col};
+
+// Parse Failures
+
+thread_local! {
+    /// Length of the shortest input any parser has failed on since `parse`
+    /// began, i.e. the furthest point in the source that parsing reached.
+    static FURTHEST_FAILURE: Cell<usize> = const { Cell::new(usize::MAX) };
+}
+
+/// nom's default error, except that every failure records how far into the
+/// source it happened. `many0` drops the error of the item it gives up on, so
+/// without this a parse error could only be reported at that item's start.
+#[derive(Debug)]
+struct ParseFailure<I> {
+    input: I,
+}
+
+impl<'a> ParseFailure<&'a str> {
+    fn new(input: &'a str, _kind: ErrorKind) -> Self {
+        FURTHEST_FAILURE.with(|len| len.set(len.get().min(input.len())));
+        ParseFailure { input }
+    }
+}
+
+impl<'a> ParseError<&'a str> for ParseFailure<&'a str> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        ParseFailure::new(input, kind)
+    }
+
+    fn append(_input: &'a str, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+type IResult<I, O> = nom::IResult<I, O, ParseFailure<I>>;
 
 // Whitespace & Comment Handling
 
@@ -31,13 +68,84 @@ fn ws0(input: &str) -> IResult<&str, ()> {
     Ok((i, ()))
 }
 
-fn eat_ws<'a, F: 'a, O>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
+fn eat_ws<'a, F, O>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O>,
+    F: FnMut(&'a str) -> IResult<&'a str, O> + 'a,
 {
     delimited(ws0, inner, ws0)
 }
 
+// Span Tracking
+
+thread_local! {
+    /// Length of the source currently being parsed. Every `&str` the parsers see
+    /// is a suffix of that source, so a slice's byte offset is `len - slice.len()`.
+    static SOURCE_LEN: Cell<usize> = const { Cell::new(0) };
+}
+
+fn offset(input: &str) -> usize {
+    SOURCE_LEN.with(|len| len.get()).saturating_sub(input.len())
+}
+
+/// Byte offset just past the last meaningful character consumed between
+/// `before` and `after`, ignoring trailing whitespace and `//` comments.
+fn end_offset(before: &str, after: &str) -> usize {
+    let mut consumed = &before[..before.len() - after.len()];
+    loop {
+        consumed = consumed.trim_end();
+        let line_start = consumed.rfind('\n').map(|n| n + 1).unwrap_or(0);
+        match consumed[line_start..].find("//") {
+            Some(c) => consumed = &consumed[..line_start + c],
+            None => break,
+        }
+    }
+    offset(before) + consumed.len()
+}
+
+/// Run `inner` (after skipping leading whitespace) and attach the span of
+/// the text it consumed to the resulting expression.
+fn spanned<'a, F>(mut inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, Expr>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, ExprKind>,
+{
+    move |input| {
+        let (input, _) = ws0(input)?;
+        let (rest, kind) = inner(input)?;
+        Ok((rest, Expr::new(kind, Span::new(offset(input), end_offset(input, rest)))))
+    }
+}
+
+/// Like `spanned`, for parsers whose output is not an expression: pairs the
+/// output with the span of the text it consumed.
+fn located<'a, F, O>(mut inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, (O, Span)>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, O>,
+{
+    move |input| {
+        let (input, _) = ws0(input)?;
+        let (rest, out) = inner(input)?;
+        Ok((rest, (out, Span::new(offset(input), end_offset(input, rest)))))
+    }
+}
+
+/// Statement counterpart of `spanned`.
+fn spanned_stmt<'a, F>(mut inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, Statement>
+where
+    F: FnMut(&'a str) -> IResult<&'a str, StmtKind>,
+{
+    move |input| {
+        let (input, _) = ws0(input)?;
+        let (rest, kind) = inner(input)?;
+        Ok((rest, Statement { kind, span: Span::new(offset(input), end_offset(input, rest)) }))
+    }
+}
+
+/// Build a binary expression spanning both operands.
+fn binary(op: fn(Box<Expr>, Box<Expr>) -> ExprKind, lhs: Expr, rhs: Expr) -> Expr {
+    let span = lhs.span.to(rhs.span);
+    Expr::new(op(Box::new(lhs), Box::new(rhs)), span)
+}
+
 // Keyword Helper
 
 /// Matches `kw` only when NOT followed by an alphanumeric character or `_`,
@@ -47,7 +155,7 @@ fn keyword<'a>(kw: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a
         let (rest, matched) = tag(kw)(input)?;
         match rest.chars().next() {
             Some(c) if c.is_alphanumeric() || c == '_' => Err(nom::Err::Error(
-                nom::error::Error::new(input, nom::error::ErrorKind::Tag),
+                ParseFailure::new(input, ErrorKind::Tag),
             )),
             _ => Ok((rest, matched)),
         }
@@ -86,16 +194,24 @@ fn parse_statement_list(input: &str) -> IResult<&str, Vec<Statement>> {
 }
 
 fn parse_statement(input: &str) -> IResult<&str, Statement> {
-    alt((
-        parse_pb_call_assign,      // must precede parse_map_call_assign and parse_var_declaration
-        parse_distribution_of,     // must precede parse_map_call_assign (both start with `let`)
-        parse_map_call_assign,     // must precede parse_var_declaration (both start with `let`)
+    spanned_stmt(alt((
+        parse_infer_assign,        // must precede parse_pb_call_assign (both start with `let a, b =`)
+        parse_map_call_assign,     // must precede parse_pb_call_assign (`let a, b = map(...)`)
+        parse_pb_call_assign,      // must precede parse_var_declaration
+        parse_distribution_of,     // must precede parse_var_declaration (both start with `let`)
         parse_return_stmt,
         parse_if_stmt,
+        parse_while_stmt,
+        parse_for_stmt,
+        parse_match_stmt,
+        map(eat_ws(keyword("break")), |_| StmtKind::Break),
+        map(eat_ws(keyword("continue")), |_| StmtKind::Continue),
+        parse_observe_stmt,
+        parse_set_seed_stmt,
         parse_var_declaration,
         hardcoded_output,
         parse_statement_assignment,
-    ))
+    )))
     .parse(input)
 }
 
@@ -103,44 +219,142 @@ fn parse_block(input: &str) -> IResult<&str, Vec<Statement>> {
     delimited(eat_ws(tag("{")), parse_statement_list, eat_ws(tag("}")))(input)
 }
 
-fn hardcoded_output(input: &str) -> IResult<&str, Statement> {
+fn hardcoded_output(input: &str) -> IResult<&str, StmtKind> {
     let (input, expr) = delimited(eat_ws(tag("output(")), parse_expr, eat_ws(tag(")")))(input)?;
-    Ok((input, Statement::HardcodedOutput(expr)))
+    Ok((input, StmtKind::HardcodedOutput(expr)))
 }
 
-fn parse_return_stmt(input: &str) -> IResult<&str, Statement> {
+fn parse_return_stmt(input: &str) -> IResult<&str, StmtKind> {
     let (input, _) = eat_ws(keyword("return"))(input)?;
     // The expression is optional (bare `return;`)
     let (input, expr) = opt(eat_ws(parse_expr))(input)?;
-    Ok((input, Statement::Return(expr)))
+    Ok((input, StmtKind::Return(expr)))
 }
 
-fn parse_if_stmt(input: &str) -> IResult<&str, Statement> {
+fn parse_if_stmt(input: &str) -> IResult<&str, StmtKind> {
     let (input, _) = eat_ws(keyword("if"))(input)?;
     let (input, cond) = eat_ws(parse_expr)(input)?;
     let (input, then_block) = parse_block(input)?;
     let (input, else_block) = opt(preceded(eat_ws(keyword("else")), parse_block))(input)?;
-    Ok((input, Statement::If { cond, then_block, else_block }))
+    Ok((input, StmtKind::If { cond, then_block, else_block }))
 }
 
-fn parse_statement_assignment(input: &str) -> IResult<&str, Statement> {
+/// `observe(cond)` or `observe(dist, value)`
+fn parse_observe_stmt(input: &str) -> IResult<&str, StmtKind> {
+    let (input, _) = eat_ws(keyword("observe"))(input)?;
+    let (input, _) = eat_ws(tag("("))(input)?;
+    let (input, first) = eat_ws(parse_expr)(input)?;
+    let (input, value) = opt(preceded(eat_ws(tag(",")), eat_ws(parse_expr)))(input)?;
+    let (input, _) = eat_ws(tag(")"))(input)?;
+    let stmt = match value {
+        Some(value) => StmtKind::ObserveFrom { dist: first, value },
+        None => StmtKind::Observe(first),
+    };
+    Ok((input, stmt))
+}
+
+/// `set_seed(n)`
+fn parse_set_seed_stmt(input: &str) -> IResult<&str, StmtKind> {
+    let (input, _) = eat_ws(keyword("set_seed"))(input)?;
+    let (input, seed) = delimited(eat_ws(tag("(")), eat_ws(parse_expr), eat_ws(tag(")")))(input)?;
+    Ok((input, StmtKind::SetSeed(seed)))
+}
+
+fn parse_while_stmt(input: &str) -> IResult<&str, StmtKind> {
+    let (input, _) = eat_ws(keyword("while"))(input)?;
+    let (input, cond) = eat_ws(parse_expr)(input)?;
+    let (input, body) = parse_block(input)?;
+    Ok((input, StmtKind::While { cond, body }))
+}
+
+/// `for var in a..b { ... }` (half-open integer range) or `for var in array { ... }`
+fn parse_for_stmt(input: &str) -> IResult<&str, StmtKind> {
+    let (input, _) = eat_ws(keyword("for"))(input)?;
+    let (input, var) = eat_ws(parse_identifier)(input)?;
+    let (input, _) = eat_ws(keyword("in"))(input)?;
+    let (input, start) = eat_ws(parse_expr)(input)?;
+    let (input, end) = opt(preceded(eat_ws(tag("..")), eat_ws(parse_expr)))(input)?;
+    let (input, body) = parse_block(input)?;
+    let iterable = match end {
+        Some(end) => ForIterable::Range(start, end),
+        None => ForIterable::Array(start),
+    };
+    Ok((input, StmtKind::For { var: var.to_string(), iterable, body }))
+}
+
+/// `match scrutinee { pattern | pattern => { ... }, ... }`
+fn parse_match_stmt(input: &str) -> IResult<&str, StmtKind> {
+    let (input, (scrutinee, arms)) = parse_match(input, parse_block)?;
+    Ok((input, StmtKind::Match { scrutinee, arms }))
+}
+
+/// `match scrutinee { pattern | pattern => expr, ... }`
+fn parse_match_expr(input: &str) -> IResult<&str, ExprKind> {
+    let (input, (scrutinee, arms)) = parse_match(input, parse_expr)?;
+    Ok((input, ExprKind::Match { scrutinee: Box::new(scrutinee), arms }))
+}
+
+/// The scrutinee and arms of either form of `match`; `body` parses each arm's
+/// body. Arms are separated by commas, and a trailing comma is allowed.
+fn parse_match<B>(
+    input: &str,
+    body: fn(&str) -> IResult<&str, B>,
+) -> IResult<&str, (Expr, Vec<MatchArm<B>>)> {
+    let (input, _) = eat_ws(keyword("match"))(input)?;
+    let (input, scrutinee) = eat_ws(parse_expr)(input)?;
+    let (input, _) = eat_ws(tag("{"))(input)?;
+    let arm = |input| {
+        let (input, patterns) = separated_list1(eat_ws(tag("|")), parse_pattern)(input)?;
+        let (input, _) = eat_ws(tag("=>"))(input)?;
+        let (input, _) = ws0(input)?;
+        let (input, body) = body(input)?;
+        Ok((input, MatchArm { patterns, body }))
+    };
+    let (input, arms) = separated_list1(eat_ws(tag(",")), arm)(input)?;
+    let (input, _) = opt(eat_ws(tag(",")))(input)?;
+    let (input, _) = eat_ws(tag("}"))(input)?;
+    Ok((input, (scrutinee, arms)))
+}
+
+/// `_`, an int or bool literal, an enum variant, `Certain(p)`, `Uncertain(p)`,
+/// `Fail`, or a lowercase name that binds the matched value.
+fn parse_pattern(input: &str) -> IResult<&str, Pattern> {
+    let inner = |input| delimited(eat_ws(tag("(")), parse_pattern, eat_ws(tag(")")))(input);
+    let kind = alt((
+        map(preceded(keyword("Certain"), inner), |p| PatternKind::Certain(Box::new(p))),
+        map(preceded(keyword("Uncertain"), inner), |p| PatternKind::Uncertain(Box::new(p))),
+        map(keyword("Fail"), |_| PatternKind::Fail),
+        map(keyword("true"), |_| PatternKind::Bool(true)),
+        map(keyword("false"), |_| PatternKind::Bool(false)),
+        map(pair(opt(tag("-")), parse_i64), |(sign, n)| PatternKind::Int(if sign.is_some() { -n } else { n })),
+        map(keyword("_"), |_| PatternKind::Wildcard),
+        map(parse_uppercase_identifier, |v| PatternKind::Variant(v.to_string())),
+        map(parse_var_name, |kind| match kind {
+            ExprKind::Var(name) => PatternKind::Binding(name),
+            _ => unreachable!("parse_var_name only produces variables"),
+        }),
+    ));
+    map(eat_ws(located(kind)), |(kind, span)| Pattern::new(kind, span))(input)
+}
+
+fn parse_statement_assignment(input: &str) -> IResult<&str, StmtKind> {
     let (input, var_expr) = eat_ws(parse_var)(input)?;
     let (input, rhs) = eat_ws(parse_assignment_rhs)(input)?;
-    Ok((input, Statement::Assign { name: var_expr, value: rhs }))
+    Ok((input, StmtKind::Assign { name: var_expr, value: rhs }))
 }
 
-fn parse_var_declaration(input: &str) -> IResult<&str, Statement> {
+fn parse_var_declaration(input: &str) -> IResult<&str, StmtKind> {
     let (input, _) = eat_ws(keyword("let"))(input)?;
     let (input, var_expr) = eat_ws(parse_var)(input)?;
     let (input, maybe_rhs) = opt(eat_ws(parse_assignment_rhs))(input)?;
     match maybe_rhs {
-        Some(rhs) => Ok((input, Statement::DeclAssign { name: var_expr, value: rhs })),
-        None => Ok((input, Statement::Decl(var_expr))),
+        Some(rhs) => Ok((input, StmtKind::DeclAssign { name: var_expr, value: rhs })),
+        None => Ok((input, StmtKind::Decl(var_expr))),
     }
 }
 
-/// `let result_var, info_var = func_name(args) with confidence >= <float>`
-fn parse_pb_call_assign(input: &str) -> IResult<&str, Statement> {
+/// `let result_var, info_var = func_name(args) with confidence >= <float> [adaptive]`
+fn parse_pb_call_assign(input: &str) -> IResult<&str, StmtKind> {
     let (input, _) = eat_ws(keyword("let"))(input)?;
     let (input, result_var) = eat_ws(parse_identifier)(input)?;
     let (input, _) = eat_ws(tag(","))(input)?;
@@ -153,19 +367,22 @@ fn parse_pb_call_assign(input: &str) -> IResult<&str, Statement> {
     let (input, _) = eat_ws(keyword("confidence"))(input)?;
     let (input, _) = eat_ws(tag(">="))(input)?;
     let (input, confidence) = eat_ws(parse_float_value)(input)?;
-    Ok((input, Statement::PbCallAssign {
+    let (input, adaptive) = opt(eat_ws(keyword("adaptive")))(input)?;
+    Ok((input, StmtKind::PbCallAssign {
         result_var: result_var.to_string(),
         info_var: info_var.to_string(),
         func_name: func_name.to_string(),
         args,
         confidence,
+        adaptive: adaptive.is_some(),
     }))
 }
 
-/// `let var = map(func_name, array_expr) [with confidence >= float]`
-fn parse_map_call_assign(input: &str) -> IResult<&str, Statement> {
+/// `let var[, info_var] = map(func_name, array_expr) [with confidence >= float [budget even|adaptive]]`
+fn parse_map_call_assign(input: &str) -> IResult<&str, StmtKind> {
     let (input, _) = eat_ws(keyword("let"))(input)?;
     let (input, var) = eat_ws(parse_identifier)(input)?;
+    let (input, info_var) = opt(preceded(eat_ws(tag(",")), eat_ws(parse_identifier)))(input)?;
     let (input, _) = eat_ws(tag("="))(input)?;
     let (input, _) = eat_ws(keyword("map"))(input)?;
     let (input, _) = eat_ws(tag("("))(input)?;
@@ -174,14 +391,29 @@ fn parse_map_call_assign(input: &str) -> IResult<&str, Statement> {
     let (input, array_expr) = eat_ws(parse_expr)(input)?;
     let (input, _) = eat_ws(tag(")"))(input)?;
     let (input, confidence) = opt(parse_with_confidence)(input)?;
-    Ok((input, Statement::MapCallAssign {
+    let (input, budget) = match confidence {
+        Some(_) => opt(parse_map_budget)(input)?,
+        None => (input, None),
+    };
+    Ok((input, StmtKind::MapCallAssign {
         var: var.to_string(),
+        info_var: info_var.map(str::to_string),
         func_name: func_name.to_string(),
         array_expr,
         confidence,
+        budget: budget.unwrap_or(MapBudget::Even),
     }))
 }
 
+/// `budget even` or `budget adaptive`
+fn parse_map_budget(input: &str) -> IResult<&str, MapBudget> {
+    let (input, _) = eat_ws(keyword("budget"))(input)?;
+    eat_ws(alt((
+        map(keyword("even"), |_| MapBudget::Even),
+        map(keyword("adaptive"), |_| MapBudget::Adaptive),
+    )))(input)
+}
+
 fn parse_with_confidence(input: &str) -> IResult<&str, f64> {
     let (input, _) = eat_ws(keyword("with"))(input)?;
     let (input, _) = eat_ws(keyword("confidence"))(input)?;
@@ -197,8 +429,43 @@ fn parse_i64(input: &str) -> IResult<&str, i64> {
     Ok((input, n))
 }
 
+/// `let var[, info_var] = infer(func_name(args), method: rejection|importance|mh[, samples: N])`
+fn parse_infer_assign(input: &str) -> IResult<&str, StmtKind> {
+    let (input, _) = eat_ws(keyword("let"))(input)?;
+    let (input, var) = eat_ws(parse_identifier)(input)?;
+    let (input, info_var) = opt(preceded(eat_ws(tag(",")), eat_ws(parse_identifier)))(input)?;
+    let (input, _) = eat_ws(tag("="))(input)?;
+    let (input, _) = eat_ws(keyword("infer"))(input)?;
+    let (input, _) = eat_ws(tag("("))(input)?;
+    let (input, func_name) = eat_ws(parse_identifier)(input)?;
+    let (input, args) =
+        delimited(eat_ws(tag("(")), parse_arg_list_optional, eat_ws(tag(")")))(input)?;
+    let (input, _) = eat_ws(tag(","))(input)?;
+    let (input, _) = eat_ws(keyword("method"))(input)?;
+    let (input, _) = eat_ws(tag(":"))(input)?;
+    let (input, method) = eat_ws(alt((
+        map(keyword("rejection"), |_| InferMethod::Rejection),
+        map(keyword("importance"), |_| InferMethod::Importance),
+        map(keyword("mh"), |_| InferMethod::MH),
+    )))(input)?;
+    // Optional ", samples: N" (default 1000)
+    let (input, samples) = opt(preceded(
+        tuple((eat_ws(tag(",")), eat_ws(keyword("samples")), eat_ws(tag(":")))),
+        eat_ws(parse_i64),
+    ))(input)?;
+    let (input, _) = eat_ws(tag(")"))(input)?;
+    Ok((input, StmtKind::Infer {
+        var: var.to_string(),
+        info_var: info_var.map(str::to_string),
+        func_name: func_name.to_string(),
+        args,
+        method,
+        samples: samples.unwrap_or(1000),
+    }))
+}
+
 /// `let var = distribution_of(func_name(args), analytical|empirical[, N]|bayesian[, N])`
-fn parse_distribution_of(input: &str) -> IResult<&str, Statement> {
+fn parse_distribution_of(input: &str) -> IResult<&str, StmtKind> {
     let (input, _) = eat_ws(keyword("let"))(input)?;
     let (input, var) = eat_ws(parse_identifier)(input)?;
     let (input, _) = eat_ws(tag("="))(input)?;
@@ -214,8 +481,10 @@ fn parse_distribution_of(input: &str) -> IResult<&str, Statement> {
         keyword("analytical"),
         keyword("empirical"),
         keyword("bayesian"),
+        keyword("outputs"),
+        keyword("rounds"),
     )))(input)?;
-    // Optional ", N" for empirical and bayesian (default 100)
+    // Optional ", N" for the sampling modes (default 100)
     let (input, n_opt) = if mode_str != "analytical" {
         opt(preceded(eat_ws(tag(",")), eat_ws(parse_i64)))(input)?
     } else {
@@ -227,9 +496,11 @@ fn parse_distribution_of(input: &str) -> IResult<&str, Statement> {
         "analytical" => DistributionOfMode::Analytical,
         "empirical"  => DistributionOfMode::Empirical(n_opt.unwrap_or(default_n)),
         "bayesian"   => DistributionOfMode::Bayesian(n_opt.unwrap_or(default_n)),
+        "outputs"    => DistributionOfMode::Outputs(n_opt.unwrap_or(default_n)),
+        "rounds"     => DistributionOfMode::Rounds(n_opt.unwrap_or(default_n)),
         _            => unreachable!(),
     };
-    Ok((input, Statement::DistributionOf {
+    Ok((input, StmtKind::DistributionOf {
         var: var.to_string(),
         func_name: func_name.to_string(),
         args,
@@ -249,9 +520,9 @@ fn parse_fn_def(input: &str) -> IResult<&str, FuncDef> {
     let (input, params) =
         delimited(eat_ws(tag("(")), parse_param_list, eat_ws(tag(")")))(input)?;
     let (input, _) = eat_ws(tag("->"))(input)?;
-    let (input, return_type) = eat_ws(parse_type)(input)?;
+    let (input, (return_type, return_type_span)) = eat_ws(located(parse_type))(input)?;
     let (input, body) = parse_block(input)?;
-    Ok((input, FuncDef { name: name.to_string(), params, return_type, body }))
+    Ok((input, FuncDef { name: name.to_string(), params, return_type, return_type_span, body }))
 }
 
 fn parse_pb_func_def(input: &str) -> IResult<&str, PbFuncDef> {
@@ -261,21 +532,34 @@ fn parse_pb_func_def(input: &str) -> IResult<&str, PbFuncDef> {
     let (input, params) =
         delimited(eat_ws(tag("(")), parse_param_list, eat_ws(tag(")")))(input)?;
     let (input, _) = eat_ws(tag("->"))(input)?;
-    let (input, return_type) = eat_ws(parse_type)(input)?;
-    let (input, (error_class, error_distribution)) =
-        delimited(eat_ws(tag("{")), parse_pb_metadata, eat_ws(tag("}")))(input)?;
+    let (input, (return_type, return_type_span)) = eat_ws(located(parse_type))(input)?;
+    let (input, ((error_class, error_distribution, bound), metadata_span)) =
+        delimited(eat_ws(tag("{")), located(parse_pb_metadata), eat_ws(tag("}")))(input)?;
+    let (error_bound, error_bound_span) = match bound {
+        Some((bound, span)) => (Some(bound), span),
+        None => (None, metadata_span),
+    };
     let (input, body) = parse_block(input)?;
     Ok((input, PbFuncDef {
         name: name.to_string(),
         params,
         return_type,
+        return_type_span,
         error_class,
         error_distribution,
+        error_bound,
+        error_bound_span,
+        metadata_span,
         body,
     }))
 }
 
-fn parse_pb_metadata(input: &str) -> IResult<&str, (ErrorClass, String)> {
+/// Error class, error distribution name, and the declared per-round error
+/// with the span of its entry.
+type PbMetadata = (ErrorClass, String, Option<(f64, Span)>);
+
+/// `error_class: C, error_distribution: D[, error_bound: e | success_prob: p]`
+fn parse_pb_metadata(input: &str) -> IResult<&str, PbMetadata> {
     let (input, _) = eat_ws(tag("error_class"))(input)?;
     let (input, _) = eat_ws(tag(":"))(input)?;
     let (input, ec) = eat_ws(parse_error_class)(input)?;
@@ -283,7 +567,23 @@ fn parse_pb_metadata(input: &str) -> IResult<&str, (ErrorClass, String)> {
     let (input, _) = eat_ws(tag("error_distribution"))(input)?;
     let (input, _) = eat_ws(tag(":"))(input)?;
     let (input, dist_name) = eat_ws(parse_identifier)(input)?;
-    Ok((input, (ec, dist_name.to_string())))
+    let (input, bound) = opt(preceded(eat_ws(tag(",")), located(parse_error_bound)))(input)?;
+    Ok((input, (ec, dist_name.to_string(), bound)))
+}
+
+/// Per-round error probability: `error_bound: e` or `success_prob: p` (as `1 - p`).
+fn parse_error_bound(input: &str) -> IResult<&str, f64> {
+    let (input, key) = eat_ws(alt((keyword("error_bound"), keyword("success_prob"))))(input)?;
+    let (input, _) = eat_ws(tag(":"))(input)?;
+    let (input, value) = eat_ws(parse_ratio_value)(input)?;
+    Ok((input, if key == "success_prob" { 1.0 - value } else { value }))
+}
+
+/// A float or a ratio of two floats, e.g. `0.25` or `2/3`.
+fn parse_ratio_value(input: &str) -> IResult<&str, f64> {
+    let (input, num) = parse_float_value(input)?;
+    let (input, den) = opt(preceded(eat_ws(tag("/")), eat_ws(parse_float_value)))(input)?;
+    Ok((input, den.map_or(num, |d| num / d)))
 }
 
 fn parse_error_class(input: &str) -> IResult<&str, ErrorClass> {
@@ -291,6 +591,7 @@ fn parse_error_class(input: &str) -> IResult<&str, ErrorClass> {
         map(keyword("RP"), |_| ErrorClass::RP),
         map(keyword("coRP"), |_| ErrorClass::CoRP),
         map(keyword("BPP"), |_| ErrorClass::BPP),
+        map(keyword("ZPP"), |_| ErrorClass::ZPP),
     ))
     .parse(input)
 }
@@ -302,18 +603,35 @@ fn parse_type(input: &str) -> IResult<&str, Type> {
         map(keyword("bool"), |_| Type::Bool),
         parse_type_dist_of,
         parse_type_named,
+        map(delimited(eat_ws(tag("[")), parse_type, eat_ws(tag("]"))), |elem| Type::Array(Box::new(elem))),
+        parse_type_fn,
     ))
     .parse(input)
 }
 
-/// Parses `Discrete<TypeName>` as `Type::DistOf("TypeName")`.
+/// Parses a function type, e.g. `fn(int, int) -> bool`.
+fn parse_type_fn(input: &str) -> IResult<&str, Type> {
+    let (input, _) = eat_ws(keyword("fn"))(input)?;
+    let (input, params) = delimited(
+        eat_ws(tag("(")),
+        separated_list0(eat_ws(tag(",")), eat_ws(parse_type)),
+        eat_ws(tag(")")),
+    )(input)?;
+    let (input, _) = eat_ws(tag("->"))(input)?;
+    let (input, ret) = eat_ws(parse_type)(input)?;
+    Ok((input, Type::Fn(params, Box::new(ret))))
+}
+
+/// Parses `Discrete<TypeName>` as `Type::DistOf("TypeName")`, where the type is
+/// an enum or one of `int`, `float` and `bool`.
 fn parse_type_dist_of(input: &str) -> IResult<&str, Type> {
     let (input, _) = eat_ws(tag("Discrete"))(input)?;
     let (input, _) = eat_ws(tag("<"))(input)?;
     let (input, type_name) = eat_ws(parse_identifier)(input)?;
-    if !type_name.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            input, nom::error::ErrorKind::Tag,
+    let primitive = matches!(type_name, "int" | "float" | "bool");
+    if !primitive && !type_name.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
+        return Err(nom::Err::Error(ParseFailure::new(
+            input, ErrorKind::Tag,
         )));
     }
     let (input, _) = eat_ws(tag(">"))(input)?;
@@ -324,8 +642,8 @@ fn parse_type_dist_of(input: &str) -> IResult<&str, Type> {
 fn parse_type_named(input: &str) -> IResult<&str, Type> {
     let (rest, name) = eat_ws(parse_identifier)(input)?;
     if !name.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            input, nom::error::ErrorKind::Tag,
+        return Err(nom::Err::Error(ParseFailure::new(
+            input, ErrorKind::Tag,
         )));
     }
     Ok((rest, Type::Named(name.to_string())))
@@ -339,8 +657,8 @@ fn parse_enum_def(input: &str) -> IResult<&str, EnumDef> {
     let (input, _) = eat_ws(keyword("enum"))(input)?;
     let (input, name) = eat_ws(parse_identifier)(input)?;
     if !name.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            input, nom::error::ErrorKind::Tag,
+        return Err(nom::Err::Error(ParseFailure::new(
+            input, ErrorKind::Tag,
         )));
     }
     let (input, variants) =
@@ -361,8 +679,8 @@ fn parse_variant_list(input: &str) -> IResult<&str, Vec<String>> {
 fn parse_uppercase_identifier(input: &str) -> IResult<&str, &str> {
     let (rest, ident) = parse_identifier(input)?;
     if !ident.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            input, nom::error::ErrorKind::Tag,
+        return Err(nom::Err::Error(ParseFailure::new(
+            input, ErrorKind::Tag,
         )));
     }
     Ok((rest, ident))
@@ -387,8 +705,8 @@ fn parse_param_list(input: &str) -> IResult<&str, Vec<FuncParam>> {
 fn parse_param(input: &str) -> IResult<&str, FuncParam> {
     let (input, name) = eat_ws(parse_identifier)(input)?;
     let (input, _) = eat_ws(tag(":"))(input)?;
-    let (input, ty) = eat_ws(parse_type)(input)?;
-    Ok((input, FuncParam { name: name.to_string(), ty }))
+    let (input, (ty, ty_span)) = eat_ws(located(parse_type))(input)?;
+    Ok((input, FuncParam { name: name.to_string(), ty, ty_span }))
 }
 
 // Argument / Pair Lists
@@ -406,14 +724,15 @@ fn parse_arg_list(input: &str) -> IResult<&str, Vec<Expr>> {
     Ok((input, args))
 }
 
-fn parse_discrete_pair_list_optional(
-    input: &str,
-) -> IResult<&str, Vec<(Box<Expr>, Box<Expr>)>> {
+/// `(value, probability)` pairs of a `Discrete(...)` literal, still unevaluated.
+type DiscretePairs = Vec<(Box<Expr>, Box<Expr>)>;
+
+fn parse_discrete_pair_list_optional(input: &str) -> IResult<&str, DiscretePairs> {
     opt(parse_discrete_pair_list)(input)
         .map(|(input, maybe)| (input, maybe.unwrap_or_default()))
 }
 
-fn parse_discrete_pair_list(input: &str) -> IResult<&str, Vec<(Box<Expr>, Box<Expr>)>> {
+fn parse_discrete_pair_list(input: &str) -> IResult<&str, DiscretePairs> {
     let (input, first) = eat_ws(parse_discrete_pair)(input)?;
     let (input, rest) =
         many0(preceded(eat_ws(tag(",")), eat_ws(parse_discrete_pair)))(input)?;
@@ -443,7 +762,7 @@ fn parse_or_expr(input: &str) -> IResult<&str, Expr> {
         many0(preceded(eat_ws(tag("||")), eat_ws(parse_and_expr)))(input)?;
     let mut result = first;
     for rhs in rest {
-        result = Expr::Or(Box::new(result), Box::new(rhs));
+        result = binary(ExprKind::Or, result, rhs);
     }
     Ok((input, result))
 }
@@ -454,7 +773,7 @@ fn parse_and_expr(input: &str) -> IResult<&str, Expr> {
         many0(preceded(eat_ws(tag("&&")), eat_ws(parse_cmp_expr)))(input)?;
     let mut result = first;
     for rhs in rest {
-        result = Expr::And(Box::new(result), Box::new(rhs));
+        result = binary(ExprKind::And, result, rhs);
     }
     Ok((input, result))
 }
@@ -464,13 +783,15 @@ fn parse_cmp_expr(input: &str) -> IResult<&str, Expr> {
 
     // Try `~=` (approximate equality) before regular operators.
     // Syntax: lhs ~= rhs (within tolerance)?
-    if let Ok((rest, _)) = eat_ws(tag::<&str, &str, nom::error::Error<&str>>("~="))(input) {
+    if let Ok((rest, _)) = eat_ws(tag::<&str, &str, ParseFailure<&str>>("~="))(input) {
         let (rest, rhs) = eat_ws(parse_add_expr)(rest)?;
         let (rest, tolerance) = opt(preceded(
             eat_ws(keyword("within")),
             eat_ws(parse_add_expr),
         ))(rest)?;
-        return Ok((rest, Expr::ApproxEq(Box::new(lhs), Box::new(rhs), tolerance.map(Box::new))));
+        let span = lhs.span.to(tolerance.as_ref().map_or(rhs.span, |t| t.span));
+        let kind = ExprKind::ApproxEq(Box::new(lhs), Box::new(rhs), tolerance.map(Box::new));
+        return Ok((rest, Expr::new(kind, span)));
     }
 
     // Multi-char operators must be tried before single-char prefixes.
@@ -488,12 +809,12 @@ fn parse_cmp_expr(input: &str) -> IResult<&str, Expr> {
     match maybe_op {
         Some((op, rhs)) => {
             let expr = match op {
-                "==" => Expr::Eq(Box::new(lhs), Box::new(rhs)),
-                "!=" => Expr::Neq(Box::new(lhs), Box::new(rhs)),
-                "<=" => Expr::Lte(Box::new(lhs), Box::new(rhs)),
-                ">=" => Expr::Gte(Box::new(lhs), Box::new(rhs)),
-                "<" => Expr::Lt(Box::new(lhs), Box::new(rhs)),
-                ">" => Expr::Gt(Box::new(lhs), Box::new(rhs)),
+                "==" => binary(ExprKind::Eq, lhs, rhs),
+                "!=" => binary(ExprKind::Neq, lhs, rhs),
+                "<=" => binary(ExprKind::Lte, lhs, rhs),
+                ">=" => binary(ExprKind::Gte, lhs, rhs),
+                "<" => binary(ExprKind::Lt, lhs, rhs),
+                ">" => binary(ExprKind::Gt, lhs, rhs),
                 _ => unreachable!(),
             };
             Ok((input, expr))
@@ -511,8 +832,8 @@ fn parse_add_expr(input: &str) -> IResult<&str, Expr> {
     let mut result = first;
     for (op, rhs) in rest {
         result = match op {
-            "+" => Expr::Add(Box::new(result), Box::new(rhs)),
-            "-" => Expr::Sub(Box::new(result), Box::new(rhs)),
+            "+" => binary(ExprKind::Add, result, rhs),
+            "-" => binary(ExprKind::Sub, result, rhs),
             _ => unreachable!(),
         };
     }
@@ -530,9 +851,9 @@ fn parse_mul_term(input: &str) -> IResult<&str, Expr> {
     let mut result = first;
     for (op, rhs) in rest {
         result = match op {
-            "*" => Expr::Mul(Box::new(result), Box::new(rhs)),
-            "/" => Expr::Div(Box::new(result), Box::new(rhs)),
-            "%" | "mod" => Expr::Mod(Box::new(result), Box::new(rhs)),
+            "*" => binary(ExprKind::Mul, result, rhs),
+            "/" => binary(ExprKind::Div, result, rhs),
+            "%" | "mod" => binary(ExprKind::Mod, result, rhs),
             _ => unreachable!(),
         };
     }
@@ -541,22 +862,74 @@ fn parse_mul_term(input: &str) -> IResult<&str, Expr> {
 
 fn parse_unary_term(input: &str) -> IResult<&str, Expr> {
     alt((
-        map(preceded(eat_ws(tag("-")), parse_unary_term), |e| Expr::Neg(Box::new(e))),
-        map(preceded(eat_ws(tag("!")), parse_unary_term), |e| Expr::Not(Box::new(e))),
+        spanned(map(preceded(tag("-"), parse_unary_term), |e| ExprKind::Neg(Box::new(e)))),
+        spanned(map(preceded(tag("!"), parse_unary_term), |e| ExprKind::Not(Box::new(e)))),
         parse_primary_with_postfix,
     ))
     .parse(input)
 }
 
-/// After a primary expression, optionally consume chained `.method(args)` or `:method(args)` calls.
+/// A suffix that can follow a primary expression.
+enum Postfix {
+    Method(String, Vec<Expr>),
+    Field(String),
+    Index(Expr),
+    Slice(Option<Expr>, Option<Expr>),
+}
+
+/// After a primary expression, optionally consume chained `.method(args)` / `:method(args)`
+/// calls, `.field` accesses and `[i]` / `[a..b]` index suffixes.
 fn parse_primary_with_postfix(input: &str) -> IResult<&str, Expr> {
-    let (input, base) = parse_primary_term(input)?;
-    let (input, methods) = many0(alt((parse_dot_method, parse_colon_method)))(input)?;
-    let mut result = base;
-    for (method, args) in methods {
-        result = Expr::ExprMethodCall { expr: Box::new(result), method, args };
+    let (mut input, mut result) = parse_primary_term(input)?;
+    loop {
+        match alt((parse_method_suffix, parse_field_suffix, parse_index_suffix))(input) {
+            Ok((rest, suffix)) => {
+                let span = Span::new(result.span.start, end_offset(input, rest));
+                let base = Box::new(result);
+                let kind = match suffix {
+                    Postfix::Method(method, args) => ExprKind::ExprMethodCall { expr: base, method, args },
+                    Postfix::Field(field) => ExprKind::Field { expr: base, field },
+                    Postfix::Index(idx) => ExprKind::Index(base, Box::new(idx)),
+                    Postfix::Slice(start, end) => ExprKind::Slice {
+                        expr: base,
+                        start: start.map(Box::new),
+                        end: end.map(Box::new),
+                    },
+                };
+                result = Expr::new(kind, span);
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => return Ok((input, result)),
+            Err(e) => return Err(e),
+        }
     }
-    Ok((input, result))
+}
+
+fn parse_method_suffix(input: &str) -> IResult<&str, Postfix> {
+    map(alt((parse_dot_method, parse_colon_method)), |(m, args)| Postfix::Method(m, args))(input)
+}
+
+/// `.field` with no argument list (tried after `.method(args)`).
+fn parse_field_suffix(input: &str) -> IResult<&str, Postfix> {
+    let (input, _) = eat_ws(tag("."))(input)?;
+    let (input, field) = eat_ws(parse_identifier)(input)?;
+    Ok((input, Postfix::Field(field.to_string())))
+}
+
+/// `[i]`, or a slice `[a..b]` where either bound may be omitted.
+fn parse_index_suffix(input: &str) -> IResult<&str, Postfix> {
+    let (input, _) = eat_ws(tag("["))(input)?;
+    let (input, start) = opt(eat_ws(parse_expr))(input)?;
+    let (input, range) = opt(preceded(eat_ws(tag("..")), opt(eat_ws(parse_expr))))(input)?;
+    let (input, _) = eat_ws(tag("]"))(input)?;
+    let suffix = match (start, range) {
+        (start, Some(end)) => Postfix::Slice(start, end),
+        (Some(idx), None) => Postfix::Index(idx),
+        (None, None) => {
+            return Err(nom::Err::Error(ParseFailure::new(input, ErrorKind::Tag)));
+        }
+    };
+    Ok((input, suffix))
 }
 
 fn parse_dot_method(input: &str) -> IResult<&str, (String, Vec<Expr>)> {
@@ -572,34 +945,52 @@ fn parse_primary_term(input: &str) -> IResult<&str, Expr> {
         delimited(eat_ws(tag("(")), parse_expr, eat_ws(tag(")"))),
 
         // Array literals: [expr, expr, ...]
-        parse_array_literal,
+        spanned(parse_array_literal),
+
+        // Lambdas: |x: int| expr
+        spanned(parse_lambda),
 
         // Boolean literals must come before generic identifier/call parsing.
-        map(eat_ws(keyword("true")), |_| Expr::Bool(true)),
-        map(eat_ws(keyword("false")), |_| Expr::Bool(false)),
+        spanned(map(keyword("true"), |_| ExprKind::Bool(true))),
+        spanned(map(keyword("false"), |_| ExprKind::Bool(false))),
+        spanned(map(keyword("Fail"), |_| ExprKind::Fail)),
+
+        spanned(parse_match_expr),
 
         // Function/constructor calls (includes Certain, Uncertain, distribution ctors).
-        parse_func_call,
+        spanned(parse_func_call),
 
         // Variable reference (with optional legacy `:method()` suffix).
         parse_var,
 
         // Numeric literals.
-        parse_number_literal,
+        spanned(parse_number_literal),
     ))
     .parse(input)
 }
 
-fn parse_array_literal(input: &str) -> IResult<&str, Expr> {
+fn parse_array_literal(input: &str) -> IResult<&str, ExprKind> {
     let (input, _) = eat_ws(tag("["))(input)?;
     let (input, elements) = parse_arg_list_optional(input)?;
     let (input, _) = eat_ws(tag("]"))(input)?;
-    Ok((input, Expr::Array(elements)))
+    Ok((input, ExprKind::Array(elements)))
+}
+
+/// `|params| expr` or `|params| { statements }`.
+fn parse_lambda(input: &str) -> IResult<&str, ExprKind> {
+    let (input, _) = eat_ws(tag("|"))(input)?;
+    let (input, params) = parse_param_list(input)?;
+    let (input, _) = eat_ws(tag("|"))(input)?;
+    let (input, body) = alt((
+        parse_block,
+        map(spanned_stmt(map(parse_expr, |e| StmtKind::Return(Some(e)))), |stmt| vec![stmt]),
+    ))(input)?;
+    Ok((input, ExprKind::Lambda { params, body }))
 }
 
 /// Parses any call of the form `name(args)`.
 /// Peeks ahead so that if no `(` follows the identifier, parsing backtracks cleanly.
-fn parse_func_call(input: &str) -> IResult<&str, Expr> {
+fn parse_func_call(input: &str) -> IResult<&str, ExprKind> {
     // Guard: only proceed if we see `identifier(` without consuming.
     let _ = peek(pair(eat_ws(parse_identifier), eat_ws(tag("("))))(input)?;
 
@@ -611,112 +1002,198 @@ fn parse_func_call(input: &str) -> IResult<&str, Expr> {
         "Certain" => {
             let (input, inner) = parse_expr(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
-            Ok((input, Expr::Certain(Box::new(inner))))
+            Ok((input, ExprKind::Certain(Box::new(inner))))
         }
         "Uncertain" => {
             let (input, inner) = parse_expr(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
-            Ok((input, Expr::Uncertain(Box::new(inner))))
+            Ok((input, ExprKind::Uncertain(Box::new(inner))))
         }
         // Distribution constructors
         "uniform" => {
             let (input, args) = parse_arg_list_optional(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
             if args.len() != 2 {
-                return Err(nom::Err::Error(nom::error::Error::new(
+                return Err(nom::Err::Error(ParseFailure::new(
                     input,
-                    nom::error::ErrorKind::Count,
+                    ErrorKind::Count,
                 )));
             }
             Ok((
                 input,
-                Expr::Dist(Dist::Uniform(Box::new(args[0].clone()), Box::new(args[1].clone()))),
+                ExprKind::Dist(Dist::Uniform(Box::new(args[0].clone()), Box::new(args[1].clone()))),
             ))
         }
         "uniformContinuous" => {
             let (input, args) = parse_arg_list_optional(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
             if args.len() != 2 {
-                return Err(nom::Err::Error(nom::error::Error::new(
+                return Err(nom::Err::Error(ParseFailure::new(
                     input,
-                    nom::error::ErrorKind::Count,
+                    ErrorKind::Count,
                 )));
             }
             Ok((
                 input,
-                Expr::Dist(Dist::UniformContinuous(
+                ExprKind::Dist(Dist::UniformContinuous(
                     Box::new(args[0].clone()),
                     Box::new(args[1].clone()),
                 )),
             ))
         }
-        "Discrete" => {
+        // `Categorical(...)` is another name for `Discrete(...)`, usually keyed by enum variants.
+        "Discrete" | "Categorical" => {
             let (input, pairs) = parse_discrete_pair_list_optional(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
             if pairs.is_empty() {
-                return Err(nom::Err::Error(nom::error::Error::new(
+                return Err(nom::Err::Error(ParseFailure::new(
                     input,
-                    nom::error::ErrorKind::Count,
+                    ErrorKind::Count,
                 )));
             }
-            Ok((input, Expr::Dist(Dist::Discrete(pairs))))
+            Ok((input, ExprKind::Dist(Dist::Discrete(pairs))))
         }
         "Bernoulli" => {
             let (input, args) = parse_arg_list_optional(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
             if args.len() != 1 {
-                return Err(nom::Err::Error(nom::error::Error::new(
+                return Err(nom::Err::Error(ParseFailure::new(
                     input,
-                    nom::error::ErrorKind::Count,
+                    ErrorKind::Count,
                 )));
             }
-            Ok((input, Expr::Dist(Dist::Bernoulli(Box::new(args[0].clone())))))
+            Ok((input, ExprKind::Dist(Dist::Bernoulli(Box::new(args[0].clone())))))
         }
         "Binomial" => {
             let (input, args) = parse_arg_list_optional(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
             if args.len() != 2 {
-                return Err(nom::Err::Error(nom::error::Error::new(
+                return Err(nom::Err::Error(ParseFailure::new(
                     input,
-                    nom::error::ErrorKind::Count,
+                    ErrorKind::Count,
                 )));
             }
             Ok((
                 input,
-                Expr::Dist(Dist::Binomial(Box::new(args[0].clone()), Box::new(args[1].clone()))),
+                ExprKind::Dist(Dist::Binomial(Box::new(args[0].clone()), Box::new(args[1].clone()))),
             ))
         }
         "Geometric" => {
             let (input, args) = parse_arg_list_optional(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
             if args.len() != 1 {
-                return Err(nom::Err::Error(nom::error::Error::new(
+                return Err(nom::Err::Error(ParseFailure::new(
                     input,
-                    nom::error::ErrorKind::Count,
+                    ErrorKind::Count,
                 )));
             }
-            Ok((input, Expr::Dist(Dist::Geometric(Box::new(args[0].clone())))))
+            Ok((input, ExprKind::Dist(Dist::Geometric(Box::new(args[0].clone())))))
+        }
+        "Beta" => {
+            let (input, args) = parse_arg_list_optional(input)?;
+            let (input, _) = eat_ws(tag(")"))(input)?;
+            if args.len() != 2 {
+                return Err(nom::Err::Error(ParseFailure::new(
+                    input,
+                    ErrorKind::Count,
+                )));
+            }
+            Ok((
+                input,
+                ExprKind::Dist(Dist::Beta(Box::new(args[0].clone()), Box::new(args[1].clone()))),
+            ))
+        }
+        "Normal" => {
+            let (input, args) = parse_arg_list_optional(input)?;
+            let (input, _) = eat_ws(tag(")"))(input)?;
+            if args.len() != 2 {
+                return Err(nom::Err::Error(ParseFailure::new(
+                    input,
+                    ErrorKind::Count,
+                )));
+            }
+            Ok((
+                input,
+                ExprKind::Dist(Dist::Normal(Box::new(args[0].clone()), Box::new(args[1].clone()))),
+            ))
+        }
+        "Poisson" => {
+            let (input, args) = parse_arg_list_optional(input)?;
+            let (input, _) = eat_ws(tag(")"))(input)?;
+            if args.len() != 1 {
+                return Err(nom::Err::Error(ParseFailure::new(
+                    input,
+                    ErrorKind::Count,
+                )));
+            }
+            Ok((input, ExprKind::Dist(Dist::Poisson(Box::new(args[0].clone())))))
+        }
+        "Exponential" => {
+            let (input, args) = parse_arg_list_optional(input)?;
+            let (input, _) = eat_ws(tag(")"))(input)?;
+            if args.len() != 1 {
+                return Err(nom::Err::Error(ParseFailure::new(
+                    input,
+                    ErrorKind::Count,
+                )));
+            }
+            Ok((input, ExprKind::Dist(Dist::Exponential(Box::new(args[0].clone())))))
+        }
+        "Gamma" => {
+            let (input, args) = parse_arg_list_optional(input)?;
+            let (input, _) = eat_ws(tag(")"))(input)?;
+            if args.len() != 2 {
+                return Err(nom::Err::Error(ParseFailure::new(
+                    input,
+                    ErrorKind::Count,
+                )));
+            }
+            Ok((
+                input,
+                ExprKind::Dist(Dist::Gamma(Box::new(args[0].clone()), Box::new(args[1].clone()))),
+            ))
+        }
+        "NegativeBinomial" => {
+            let (input, args) = parse_arg_list_optional(input)?;
+            let (input, _) = eat_ws(tag(")"))(input)?;
+            if args.len() != 2 {
+                return Err(nom::Err::Error(ParseFailure::new(
+                    input,
+                    ErrorKind::Count,
+                )));
+            }
+            Ok((
+                input,
+                ExprKind::Dist(Dist::NegativeBinomial(
+                    Box::new(args[0].clone()),
+                    Box::new(args[1].clone()),
+                )),
+            ))
         }
         // Generic function call
         _ => {
             let (input, args) = parse_arg_list_optional(input)?;
             let (input, _) = eat_ws(tag(")"))(input)?;
-            Ok((input, Expr::FuncCall(func_name.to_string(), args)))
+            Ok((input, ExprKind::FuncCall(func_name.to_string(), args)))
         }
     }
 }
 
 /// Variable reference. Colon and dot method calls are handled by `parse_primary_with_postfix`.
 fn parse_var(input: &str) -> IResult<&str, Expr> {
-    let (input, identifier) = eat_ws(parse_identifier)(input)?;
+    spanned(parse_var_name)(input)
+}
+
+fn parse_var_name(input: &str) -> IResult<&str, ExprKind> {
+    let (input, identifier) = parse_identifier(input)?;
     // Block reserved keywords from appearing as variable names.
     if is_reserved_keyword(identifier) {
-        return Err(nom::Err::Error(nom::error::Error::new(
+        return Err(nom::Err::Error(ParseFailure::new(
             input,
-            nom::error::ErrorKind::Tag,
+            ErrorKind::Tag,
         )));
     }
-    Ok((input, Expr::Var(identifier.to_string())))
+    Ok((input, ExprKind::Var(identifier.to_string())))
 }
 
 fn parse_colon_method(input: &str) -> IResult<&str, (String, Vec<Expr>)> {
@@ -733,15 +1210,17 @@ fn is_reserved_keyword(s: &str) -> bool {
         "let" | "if" | "else" | "return" | "fn" | "pb" | "function"
             | "output" | "mod" | "int" | "float" | "bool"
             | "with" | "confidence" | "true" | "false"
-            | "Certain" | "Uncertain" | "and" | "or" | "not"
-            | "map" | "distribution_of" | "within"
+            | "Certain" | "Uncertain" | "Fail" | "and" | "or" | "not"
+            | "map" | "distribution_of" | "infer" | "within"
             | "enum" | "bind" | "step"
+            | "while" | "for" | "in" | "break" | "continue" | "observe"
+            | "set_seed" | "adaptive" | "budget" | "match"
     )
 }
 
 // Literals
 
-fn parse_number_literal(input: &str) -> IResult<&str, Expr> {
+fn parse_number_literal(input: &str) -> IResult<&str, ExprKind> {
     let (input, int_part) = take_while1(|c: char| c.is_ascii_digit())(input)?;
     let (input, maybe_frac) = opt(pair(
         tag("."),
@@ -750,9 +1229,16 @@ fn parse_number_literal(input: &str) -> IResult<&str, Expr> {
     match maybe_frac {
         Some((_, frac)) => {
             let s = format!("{}.{}", int_part, frac);
-            Ok((input, Expr::Float(f64::from_str(&s).unwrap())))
+            Ok((input, ExprKind::Float(f64::from_str(&s).unwrap())))
         }
-        None => Ok((input, Expr::Int(i64::from_str(int_part).unwrap()))),
+        None => match i64::from_str(int_part) {
+            Ok(n) => Ok((input, ExprKind::Int(n))),
+            // Out-of-range integer literal: report it at the literal itself.
+            Err(_) => Err(nom::Err::Failure(ParseFailure::new(
+                int_part,
+                ErrorKind::Digit,
+            ))),
+        },
     }
 }
 
@@ -775,33 +1261,52 @@ fn parse_float_value(input: &str) -> IResult<&str, f64> {
 
 // Public Entry Point
 
-pub fn parse(input: &str) -> Vec<ProgramItem> {
+pub fn parse(input: &str) -> Result<Vec<ProgramItem>> {
+    SOURCE_LEN.with(|len| len.set(input.len()));
+    FURTHEST_FAILURE.with(|len| len.set(usize::MAX));
     match parse_program(input) {
         Ok((remaining, items)) => {
             if !remaining.trim().is_empty() {
-                // Compute line number of the first unparsed character.
-                let consumed_len = input.len().saturating_sub(remaining.len());
-                let consumed = &input[..consumed_len];
-                let line = consumed.lines().count().max(1);
-                // Show the first non-whitespace problem token.
-                let trimmed = remaining.trim_start();
-                let snippet: String = trimmed.chars().take(30).collect();
-                let snippet = snippet.lines().next().unwrap_or(&snippet);
-                panic!("Parse error on line {}: could not parse '{}'", line, snippet);
+                return Err(parse_error(input, deepest_failure(input, remaining)));
             }
-            items
+            Ok(items)
         }
         Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
-            let consumed_len = input.len().saturating_sub(e.input.len());
-            let consumed = &input[..consumed_len];
-            let line = consumed.lines().count().max(1);
-            let trimmed = e.input.trim_start();
-            let snippet: String = trimmed.chars().take(30).collect();
-            let snippet = snippet.lines().next().unwrap_or(&snippet);
-            panic!("Parse error on line {}: unexpected '{}'", line, snippet);
+            Err(parse_error(input, deepest_failure(input, e.input)))
         }
-        Err(nom::Err::Incomplete(_)) => {
-            panic!("Parse error: unexpected end of input");
+        Err(nom::Err::Incomplete(_)) => Err(YapplError::ParseError {
+            line: input.lines().count().max(1),
+            message: "unexpected end of input".to_string(),
         }
+        .with_span(Span::new(input.len(), input.len()))),
     }
 }
+
+/// The source from the furthest point any parser failed at, or `remaining`
+/// if parsing stopped further along than that.
+fn deepest_failure<'a>(input: &'a str, remaining: &'a str) -> &'a str {
+    let furthest = FURTHEST_FAILURE.with(|len| len.get());
+    if furthest < remaining.len() { &input[input.len() - furthest..] } else { remaining }
+}
+
+/// A parse error at the first token of `remaining`.
+fn parse_error(input: &str, remaining: &str) -> YapplError {
+    let (line, snippet, span) = error_location(input, remaining);
+    let message = if snippet.is_empty() {
+        "unexpected end of input".to_string()
+    } else {
+        format!("unexpected '{}'", snippet)
+    };
+    YapplError::ParseError { line, message }.with_span(span)
+}
+
+/// Line number of the first non-whitespace unparsed character, the problem token (truncated to a single line), and the span of that token.
+fn error_location(input: &str, remaining: &str) -> (usize, String, Span) {
+    let trimmed = remaining.trim_start();
+    let snippet: String = trimmed.chars().take(30).collect();
+    let snippet = snippet.lines().next().unwrap_or("").to_string();
+    let start = input.len() - trimmed.len();
+    let (line, _) = line_col(input, start);
+    let token_len = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    (line, snippet, Span::new(start, start + token_len))
+}
@@ -0,0 +1,98 @@
+/// Probabilities for dynamic distributions.
+///
+/// Probabilities stay exact (`Fraction`) for as long as possible, so that
+/// e.g. enumerating two fair dice gives exactly 1/36 per outcome. If exact
+/// arithmetic would overflow the fraction's `u64` parts, the value degrades to
+/// an `f64` approximation and stays approximate from then on.
+use std::ops::{Add, Div, Mul, Sub};
+
+use fraction::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Fraction, ToPrimitive};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prob {
+    Exact(Fraction),
+    Approx(f64),
+}
+
+impl Prob {
+    pub fn zero() -> Self {
+        Prob::Exact(Fraction::from(0u64))
+    }
+
+    pub fn one() -> Self {
+        Prob::Exact(Fraction::from(1u64))
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Prob::Exact(f) => f.to_f64().unwrap_or(f64::NAN),
+            Prob::Approx(p) => p,
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.to_f64() == 0.0
+    }
+
+    /// `self` raised to the power `n`, by repeated squaring.
+    pub fn powi(self, mut n: u64) -> Prob {
+        let mut base = self;
+        let mut result = Prob::one();
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n >>= 1;
+        }
+        result
+    }
+
+    fn combine(
+        self,
+        other: Prob,
+        exact: fn(&Fraction, &Fraction) -> Option<Fraction>,
+        approx: fn(f64, f64) -> f64,
+    ) -> Prob {
+        if let (Prob::Exact(a), Prob::Exact(b)) = (self, other)
+            && let Some(r) = exact(&a, &b)
+        {
+            return Prob::Exact(r);
+        }
+        Prob::Approx(approx(self.to_f64(), other.to_f64()))
+    }
+}
+
+impl Add for Prob {
+    type Output = Prob;
+    fn add(self, other: Prob) -> Prob {
+        self.combine(other, Fraction::checked_add, |a, b| a + b)
+    }
+}
+
+impl Sub for Prob {
+    type Output = Prob;
+    fn sub(self, other: Prob) -> Prob {
+        self.combine(other, Fraction::checked_sub, |a, b| a - b)
+    }
+}
+
+impl Mul for Prob {
+    type Output = Prob;
+    fn mul(self, other: Prob) -> Prob {
+        self.combine(other, Fraction::checked_mul, |a, b| a * b)
+    }
+}
+
+impl Div for Prob {
+    type Output = Prob;
+    fn div(self, other: Prob) -> Prob {
+        self.combine(other, Fraction::checked_div, |a, b| a / b)
+    }
+}
+
+impl From<Fraction> for Prob {
+    fn from(f: Fraction) -> Self {
+        Prob::Exact(f)
+    }
+}
@@ -8,7 +8,8 @@
 /// Run with `cargo test` or with the `--test` CLI flag.
 use std::path::Path;
 
-use crate::interpreter::try_run_program;
+use crate::error::YapplError;
+use crate::interpreter::{run_to_string, try_run_program};
 
 // Helpers
 
@@ -41,7 +42,8 @@ pub fn run_passing_test(source_path: &str) {
 }
 
 /// Run a program that should fail and check the error message contains the expected substring.
-pub fn run_failing_test(source_path: &str) {
+/// Returns the error so callers can also check its kind.
+pub fn run_failing_test(source_path: &str) -> YapplError {
     let expected_path = source_path.replace(".txt", ".expected");
 
     let source = std::fs::read_to_string(source_path)
@@ -57,12 +59,14 @@ pub fn run_failing_test(source_path: &str) {
                 source_path, output
             );
         }
-        Err(msg) => {
+        Err(e) => {
+            let msg = e.to_string();
             assert!(
                 msg.contains(expected_substr),
                 "\nTest FAILED (wrong error message): {}\n  expected to contain: {:?}\n  got: {:?}",
                 source_path, expected_substr, msg
             );
+            e
         }
     }
 }
@@ -105,6 +109,29 @@ passing_test!(array_literal,             "ArrayLiteral.txt");
 passing_test!(map_deterministic,         "MapDeterministic.txt");
 passing_test!(distribution_equality,     "DistributionEquality.txt");
 passing_test!(markov_chain,              "MarkovChain.txt");
+passing_test!(loops,                     "Loops.txt");
+passing_test!(array_ops,                 "ArrayOps.txt");
+passing_test!(posterior,                 "Posterior.txt");
+passing_test!(infer,                     "Infer.txt");
+passing_test!(more_distributions,        "MoreDistributions.txt");
+passing_test!(dist_queries,              "DistQueries.txt");
+passing_test!(set_seed,                  "SetSeed.txt");
+passing_test!(pb_error_bound,            "PbErrorBound.txt");
+passing_test!(zpp_las_vegas,             "ZppLasVegas.txt");
+passing_test!(bpp_plurality,             "BppPlurality.txt");
+passing_test!(bpp_adaptive,              "BppAdaptive.txt");
+passing_test!(pb_info_fields,            "PbInfoFields.txt");
+passing_test!(map_budget,                "MapBudget.txt");
+passing_test!(pb_composition,            "PbComposition.txt");
+passing_test!(distribution_of_outputs,   "DistributionOfOutputs.txt");
+passing_test!(exact_distribution_of,     "ExactDistributionOf.txt");
+passing_test!(composed_distribution_of,  "ComposedDistributionOf.txt");
+passing_test!(verify,                    "Verify.txt");
+passing_test!(markov_analysis,           "MarkovAnalysis.txt");
+passing_test!(hidden_markov,             "HiddenMarkov.txt");
+passing_test!(bind_extensions,           "BindExtensions.txt");
+passing_test!(first_class_functions,     "FirstClassFunctions.txt");
+passing_test!(pattern_matching,          "PatternMatching.txt");
 
 // Failing tests
 
@@ -118,15 +145,161 @@ macro_rules! failing_test {
             run_failing_test(path.to_str().unwrap());
         }
     };
+    ($test_name:ident, $file:expr, $kind:pat) => {
+        #[test]
+        fn $test_name() {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("Sample/Deterministic/Failing")
+                .join($file);
+            let err = run_failing_test(path.to_str().unwrap());
+            assert!(
+                matches!(err.kind(), $kind),
+                "\nTest FAILED (wrong error kind): {}\n  expected: {}\n  got: {:?}",
+                $file, stringify!($kind), err
+            );
+        }
+    };
+}
+
+failing_test!(failed_add,                   "FailedAdd.txt",                     YapplError::ParseError { .. });
+failing_test!(no_semicolons,                "NoSemicolons.txt",                  YapplError::ParseError { .. });
+failing_test!(missing_semicolon,            "MissingSemicolon.txt",              YapplError::ParseError { .. });
+failing_test!(bad_expression,               "BadExpression.txt",                 YapplError::ParseError { .. });
+failing_test!(undefined_variable,           "UndefinedVariable.txt",             YapplError::UndefinedVariable(_));
+failing_test!(type_mismatch,                "TypeMismatch.txt",                  YapplError::TypeError(_));
+failing_test!(wrong_arg_count,              "WrongArgCount.txt",                 YapplError::ArityError { .. });
+failing_test!(unknown_function,             "UnknownFunction.txt",               YapplError::UndefinedFunction(_));
+failing_test!(division_by_zero,             "DivisionByZero.txt",                YapplError::DivisionByZero);
+failing_test!(integer_overflow,             "IntegerOverflow.txt",               YapplError::RuntimeError(_));
+failing_test!(pb_function_without_conf,     "PbFunctionWithoutConfidence.txt",   YapplError::ProbabilisticContractViolation(_));
+failing_test!(break_outside_loop,           "BreakOutsideLoop.txt",              YapplError::RuntimeError(_));
+failing_test!(index_out_of_bounds,          "IndexOutOfBounds.txt",              YapplError::IndexOutOfBounds { index: 3, len: 3 });
+failing_test!(observe_outside_posterior,    "ObserveOutsidePosterior.txt",       YapplError::ObservationFailed);
+failing_test!(all_paths_rejected,           "AllPathsRejected.txt",              YapplError::RuntimeError(_));
+failing_test!(rejection_continuous_observe, "RejectionContinuousObserve.txt",    YapplError::RuntimeError(_));
+failing_test!(negative_std_dev,             "NegativeStdDev.txt",                YapplError::RuntimeError(_));
+failing_test!(quantile_out_of_range,        "QuantileOutOfRange.txt",            YapplError::RuntimeError(_));
+failing_test!(unbounded_support,            "UnboundedSupport.txt",              YapplError::RuntimeError(_));
+failing_test!(neg_bin_zero_successes,       "NegBinZeroSuccesses.txt",           YapplError::RuntimeError(_));
+failing_test!(unknown_error_distribution,   "UnknownErrorDistribution.txt",      YapplError::ProbabilisticContractViolation(_));
+failing_test!(error_bound_too_large,        "ErrorBoundTooLarge.txt",            YapplError::ProbabilisticContractViolation(_));
+failing_test!(error_bound_zero,             "ErrorBoundZero.txt",                YapplError::ProbabilisticContractViolation(_));
+failing_test!(bpp_success_half,             "BppSuccessHalf.txt",                YapplError::ProbabilisticContractViolation(_));
+failing_test!(error_bound_zero_denominator, "ErrorBoundZeroDenominator.txt",     YapplError::ProbabilisticContractViolation(_));
+failing_test!(analytical_round_error,       "AnalyticalRoundError.txt",          YapplError::DivisionByZero);
+failing_test!(zpp_uncertain,                "ZppUncertain.txt",                  YapplError::ProbabilisticContractViolation(_));
+failing_test!(adaptive_not_bpp,             "AdaptiveNotBpp.txt",                YapplError::ProbabilisticContractViolation(_));
+failing_test!(info_votes_not_bpp,           "InfoVotesNotBpp.txt",               YapplError::RuntimeError(_));
+failing_test!(map_info_without_conf,        "MapInfoWithoutConfidence.txt",      YapplError::ProbabilisticContractViolation(_));
+failing_test!(rounds_never_certain,         "RoundsNeverCertain.txt",            YapplError::RuntimeError(_));
+failing_test!(verify_not_pb,                "VerifyNotPb.txt",                   YapplError::ProbabilisticContractViolation(_));
+failing_test!(hitting_time_never_reached,  "HittingTimeNeverReached.txt",       YapplError::RuntimeError(_));
+failing_test!(hmm_impossible_observations, "HmmImpossibleObservations.txt",     YapplError::RuntimeError(_));
+failing_test!(transition_arity,             "TransitionArity.txt",               YapplError::ArityError { .. });
+failing_test!(pb_transition,                "PbTransition.txt",                  YapplError::ProbabilisticContractViolation(_));
+failing_test!(not_a_function,               "NotAFunction.txt",                  YapplError::TypeError(_));
+failing_test!(static_type_errors,           "StaticTypeErrors.txt",              YapplError::TypeErrors(_));
+failing_test!(pb_return_type,               "PbReturnType.txt",                  YapplError::TypeError(_));
+failing_test!(unknown_param_type,           "UnknownParamType.txt",              YapplError::TypeError(_));
+failing_test!(non_exhaustive_match,         "NonExhaustiveMatch.txt",            YapplError::TypeError(_));
+failing_test!(pattern_type_mismatch,        "PatternTypeMismatch.txt",           YapplError::TypeError(_));
+
+// Error locations
+
+#[test]
+fn runtime_error_span_points_at_expression() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("Sample/Deterministic/Failing/DivisionByZero.txt");
+    let source = std::fs::read_to_string(&path).unwrap();
+    let err = try_run_program(&source).unwrap_err();
+    let span = err.span().expect("runtime error should carry a span");
+    assert_eq!(&source[span.start..span.end], "x / y");
+    assert_eq!(
+        err.render(&source, "DivisionByZero.txt"),
+        "Runtime error: division by zero\n \
+         --> DivisionByZero.txt:4:8\n  \
+         |\n\
+         4 | output(x / y);\n  \
+         |        ^^^^^"
+    );
+}
+
+#[test]
+fn error_bound_span_points_at_metadata() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("Sample/Deterministic/Failing/BppSuccessHalf.txt");
+    let source = std::fs::read_to_string(&path).unwrap();
+    let err = try_run_program(&source).unwrap_err();
+    let span = err.span().expect("metadata error should carry a span");
+    assert_eq!(&source[span.start..span.end], "success_prob: 1/2");
+}
+
+#[test]
+fn parse_error_span_points_at_token() {
+    let source = "let x = 3;\nlet y = ;\n";
+    let err = try_run_program(source).unwrap_err();
+    assert!(matches!(err.kind(), YapplError::ParseError { line: 2, .. }));
+    let span = err.span().expect("parse error should carry a span");
+    assert_eq!(&source[span.start..span.end], ";");
+}
+
+#[test]
+fn parse_error_inside_function_points_at_token() {
+    let source = "fn f(a: int) -> int {\n    let b = 1;\n    return a +;\n}\n";
+    let err = try_run_program(source).unwrap_err();
+    assert!(matches!(err.kind(), YapplError::ParseError { line: 3, .. }));
+    let span = err.span().expect("parse error should carry a span");
+    assert_eq!(span.start, source.find("+;").unwrap() + 1);
+}
+
+#[test]
+fn type_errors_are_reported_together() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("Sample/Deterministic/Failing/StaticTypeErrors.txt");
+    let source = std::fs::read_to_string(&path).unwrap();
+    let err = try_run_program(&source).unwrap_err();
+    let YapplError::TypeErrors(errors) = &err else {
+        panic!("expected several type errors");
+    };
+    let lines: Vec<usize> = errors
+        .iter()
+        .map(|e| crate::error::line_col(&source, e.span().unwrap().start).0)
+        .collect();
+    assert_eq!(lines, [6, 14, 15]);
+    // The playground highlights the first of them.
+    assert_eq!(err.span(), errors[0].span());
 }
 
-failing_test!(failed_add,                   "FailedAdd.txt");
-failing_test!(no_semicolons,                "NoSemicolons.txt");
-failing_test!(missing_semicolon,            "MissingSemicolon.txt");
-failing_test!(bad_expression,               "BadExpression.txt");
-failing_test!(undefined_variable,           "UndefinedVariable.txt");
-failing_test!(type_mismatch,                "TypeMismatch.txt");
-failing_test!(wrong_arg_count,              "WrongArgCount.txt");
-failing_test!(unknown_function,             "UnknownFunction.txt");
-failing_test!(division_by_zero,             "DivisionByZero.txt");
-failing_test!(pb_function_without_conf,     "PbFunctionWithoutConfidence.txt");
+#[test]
+fn pattern_error_span_points_at_pattern() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("Sample/Deterministic/Failing/PatternTypeMismatch.txt");
+    let source = std::fs::read_to_string(&path).unwrap();
+    let err = try_run_program(&source).unwrap_err();
+    let span = err.span().expect("pattern error should carry a span");
+    assert_eq!(&source[span.start..span.end], "true");
+}
+
+#[test]
+fn signature_type_error_span_points_at_type() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("Sample/Deterministic/Failing/UnknownParamType.txt");
+    let source = std::fs::read_to_string(&path).unwrap();
+    let err = try_run_program(&source).unwrap_err();
+    let span = err.span().expect("type error should carry a span");
+    assert_eq!(&source[span.start..span.end], "Colour");
+}
+
+// Seeded runs
+
+#[test]
+fn same_seed_gives_identical_output() {
+    for file in ["Sample/Probabilistic/SolovayStrassenPrimeConf9.txt", "Sample/Deterministic/Passing/Infer.txt"] {
+        let source = std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(file)).unwrap();
+        let items = crate::parser::parse(&source).unwrap();
+        crate::typecheck::check(&items).unwrap();
+        let first = run_to_string(&items, Some(2024)).unwrap();
+        let second = run_to_string(&items, Some(2024)).unwrap();
+        assert_eq!(first, second, "{} is not reproducible with a fixed seed", file);
+    }
+}
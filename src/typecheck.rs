@@ -0,0 +1,878 @@
+/// Static type checking, run on a parsed program before it executes.
+///
+/// Types are inferred bottom-up from literals, declared parameter and return
+/// types, enum definitions and the built-ins. Whatever the checker cannot work
+/// out (a variable it has not seen, the result of `bind`, a field of an `Info`)
+/// is `Unknown` and accepted everywhere, so a program is only rejected for a
+/// mismatch that is certain. Every mismatch found is reported, not just the first.
+///
+/// Every `match` must also cover all the values its scrutinee can take.
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::*;
+use crate::error::{Result, YapplError, type_err};
+
+/// The static type of an expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Int,
+    Float,
+    /// The quotient of two ints: a float at runtime, but often used as an int.
+    Num,
+    Bool,
+    Enum(String),
+    Array(Box<Ty>),
+    Dist(Box<Ty>),
+    /// What a pb function round returns: `Certain(v)`, `Uncertain(v)` or `Fail`.
+    Round(Box<Ty>),
+    Fn(Vec<Ty>, Box<Ty>),
+    Unknown,
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Int => write!(f, "int"),
+            Ty::Float => write!(f, "float"),
+            Ty::Num => write!(f, "number"),
+            Ty::Bool => write!(f, "bool"),
+            Ty::Enum(name) => write!(f, "{}", name),
+            Ty::Array(elem) => write!(f, "[{}]", elem),
+            Ty::Dist(elem) => write!(f, "Discrete<{}>", elem),
+            Ty::Round(inner) => write!(f, "Certain({0}) | Uncertain({0})", inner),
+            Ty::Fn(params, ret) => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), ret)
+            }
+            Ty::Unknown => write!(f, "_"),
+        }
+    }
+}
+
+impl Ty {
+    fn is_numeric(&self) -> bool {
+        matches!(self, Ty::Int | Ty::Float | Ty::Num | Ty::Unknown)
+    }
+
+    /// Whether a value of type `self` can be used where `expected` is declared.
+    /// An int may stand in for a float, and a quotient for either.
+    fn fits(&self, expected: &Ty) -> bool {
+        match (self, expected) {
+            (Ty::Unknown, _) | (_, Ty::Unknown) | (Ty::Int, Ty::Float) => true,
+            (Ty::Num, b) | (b, Ty::Num) => b.is_numeric(),
+            (Ty::Array(a), Ty::Array(b)) | (Ty::Dist(a), Ty::Dist(b)) | (Ty::Round(a), Ty::Round(b)) => a.fits(b),
+            (Ty::Fn(ps, r), Ty::Fn(qs, s)) => {
+                ps.len() == qs.len() && qs.iter().zip(ps).all(|(q, p)| q.fits(p)) && r.fits(s)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// The most specific type covering both `self` and `other`.
+    fn join(self, other: Ty) -> Ty {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Ty::Int, Ty::Float) | (Ty::Float, Ty::Int) => Ty::Float,
+            (Ty::Num, b) | (b, Ty::Num) if b.is_numeric() => Ty::Num,
+            (Ty::Array(a), Ty::Array(b)) => Ty::Array(Box::new(a.join(*b))),
+            (Ty::Dist(a), Ty::Dist(b)) => Ty::Dist(Box::new(a.join(*b))),
+            (Ty::Round(a), Ty::Round(b)) => Ty::Round(Box::new(a.join(*b))),
+            _ => Ty::Unknown,
+        }
+    }
+
+    /// `self`, with any unknown parts filled in from `other`.
+    fn refine(self, other: Ty) -> Ty {
+        match (self, other) {
+            (Ty::Unknown, t) => t,
+            (Ty::Array(a), Ty::Array(b)) => Ty::Array(Box::new(a.refine(*b))),
+            (Ty::Dist(a), Ty::Dist(b)) => Ty::Dist(Box::new(a.refine(*b))),
+            (Ty::Round(a), Ty::Round(b)) => Ty::Round(Box::new(a.refine(*b))),
+            (t, _) => t,
+        }
+    }
+
+    fn elem(&self) -> Ty {
+        match self {
+            Ty::Array(elem) | Ty::Dist(elem) | Ty::Round(elem) => (**elem).clone(),
+            _ => Ty::Unknown,
+        }
+    }
+}
+
+/// Check `items`, returning the only type error found, or all of them together.
+pub fn check(items: &[ProgramItem]) -> Result<()> {
+    let mut checker = Checker::new(items);
+    checker.check_program(items);
+    let mut errors = checker.errors;
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(YapplError::TypeErrors(errors)),
+    }
+}
+
+/// What the function being checked may return.
+struct FnCtx {
+    name: String,
+    /// The declared return type; `None` for a lambda, whose type is inferred.
+    declared: Option<Ty>,
+    pb: bool,
+    /// Every type actually returned, joined.
+    returned: Option<Ty>,
+}
+
+type Scope = HashMap<String, Ty>;
+
+struct Checker<'a> {
+    funcs: HashMap<&'a str, &'a FuncDef>,
+    pb_funcs: HashMap<&'a str, &'a PbFuncDef>,
+    /// Enum type name → variants.
+    enums: HashMap<&'a str, &'a [String]>,
+    /// Variant name → enum type name.
+    variants: HashMap<&'a str, &'a str>,
+    errors: Vec<YapplError>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(items: &'a [ProgramItem]) -> Self {
+        let mut checker = Checker {
+            funcs: HashMap::new(),
+            pb_funcs: HashMap::new(),
+            enums: HashMap::new(),
+            variants: HashMap::new(),
+            errors: Vec::new(),
+        };
+        for item in items {
+            match item {
+                ProgramItem::FuncDef(f) => {
+                    checker.funcs.insert(&f.name, f);
+                }
+                ProgramItem::PbFuncDef(f) => {
+                    checker.pb_funcs.insert(&f.name, f);
+                }
+                ProgramItem::EnumDef(e) => {
+                    checker.enums.insert(&e.name, &e.variants);
+                    for variant in &e.variants {
+                        checker.variants.insert(variant, &e.name);
+                    }
+                }
+                ProgramItem::Statement(_) => {}
+            }
+        }
+        checker
+    }
+
+    fn error(&mut self, span: Span, err: YapplError) {
+        self.errors.push(err.with_span(span));
+    }
+
+    fn check_program(&mut self, items: &'a [ProgramItem]) {
+        let mut globals = Scope::new();
+        let mut top = FnCtx { name: String::new(), declared: None, pb: false, returned: None };
+        for item in items {
+            match item {
+                ProgramItem::FuncDef(f) => {
+                    self.check_signature(&f.name, &f.params, (&f.return_type, f.return_type_span));
+                    let declared = Some(self.resolve(&f.return_type));
+                    let mut ctx = FnCtx { name: f.name.clone(), declared, pb: false, returned: None };
+                    self.check_body(&f.params, &f.body, &mut ctx);
+                }
+                ProgramItem::PbFuncDef(f) => {
+                    self.check_signature(&f.name, &f.params, (&f.return_type, f.return_type_span));
+                    let declared = Some(self.resolve(&f.return_type));
+                    let mut ctx = FnCtx { name: f.name.clone(), declared, pb: true, returned: None };
+                    self.check_body(&f.params, &f.body, &mut ctx);
+                }
+                ProgramItem::EnumDef(_) => {}
+                ProgramItem::Statement(stmt) => self.check_stmt(stmt, &mut globals, &mut top),
+            }
+        }
+    }
+
+    /// Functions only see their own parameters, not the program's variables.
+    fn check_body(&mut self, params: &[FuncParam], body: &[Statement], ctx: &mut FnCtx) {
+        let mut scope: Scope = params.iter().map(|p| (p.name.clone(), self.resolve(&p.ty))).collect();
+        self.check_block(body, &mut scope, ctx);
+    }
+
+    /// Report declared types that name no enum.
+    fn check_signature(&mut self, func: &str, params: &[FuncParam], return_type: (&Type, Span)) {
+        let declared = params.iter().map(|p| (&p.ty, p.ty_span)).chain([return_type]);
+        for (ty, span) in declared {
+            if let Some(name) = self.unknown_type_name(ty) {
+                self.error(span, type_err!("unknown type '{}' in the signature of '{}'", name, func));
+            }
+        }
+    }
+
+    fn unknown_type_name(&self, ty: &Type) -> Option<String> {
+        match ty {
+            Type::Named(name) if !self.enums.contains_key(name.as_str()) => Some(name.clone()),
+            Type::DistOf(name)
+                if !matches!(name.as_str(), "int" | "float" | "bool") && !self.enums.contains_key(name.as_str()) =>
+            {
+                Some(name.clone())
+            }
+            Type::Array(elem) => self.unknown_type_name(elem),
+            Type::Fn(params, ret) => params.iter().chain([&**ret]).find_map(|t| self.unknown_type_name(t)),
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, ty: &Type) -> Ty {
+        match ty {
+            Type::Int => Ty::Int,
+            Type::Float => Ty::Float,
+            Type::Bool => Ty::Bool,
+            Type::Named(name) if self.enums.contains_key(name.as_str()) => Ty::Enum(name.clone()),
+            Type::Named(_) => Ty::Unknown,
+            Type::DistOf(name) => Ty::Dist(Box::new(match name.as_str() {
+                "int" => Ty::Int,
+                "float" => Ty::Float,
+                "bool" => Ty::Bool,
+                _ => self.resolve(&Type::Named(name.clone())),
+            })),
+            Type::Array(elem) => Ty::Array(Box::new(self.resolve(elem))),
+            Type::Fn(params, ret) => {
+                Ty::Fn(params.iter().map(|p| self.resolve(p)).collect(), Box::new(self.resolve(ret)))
+            }
+        }
+    }
+
+    fn func_ty(&self, params: &[FuncParam], return_type: &Type) -> Ty {
+        Ty::Fn(params.iter().map(|p| self.resolve(&p.ty)).collect(), Box::new(self.resolve(return_type)))
+    }
+
+    // Statements
+
+    fn check_block(&mut self, stmts: &[Statement], scope: &mut Scope, ctx: &mut FnCtx) {
+        for stmt in stmts {
+            self.check_stmt(stmt, scope, ctx);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Statement, scope: &mut Scope, ctx: &mut FnCtx) {
+        match &stmt.kind {
+            StmtKind::Decl(name) => {
+                if let ExprKind::Var(name) = &name.kind {
+                    scope.insert(name.clone(), Ty::Unknown);
+                }
+            }
+            StmtKind::DeclAssign { name, value } => {
+                let ty = self.infer(value, scope);
+                if let ExprKind::Var(name) = &name.kind {
+                    scope.insert(name.clone(), ty);
+                }
+            }
+            StmtKind::Assign { name, value } => {
+                let ty = self.infer(value, scope);
+                match &name.kind {
+                    // Variables may change type; keep what both have in common.
+                    ExprKind::Var(name) => {
+                        let joined = match scope.remove(name) {
+                            Some(old) => old.join(ty),
+                            None => ty,
+                        };
+                        scope.insert(name.clone(), joined);
+                    }
+                    _ => {
+                        self.infer(name, scope);
+                    }
+                }
+            }
+            StmtKind::HardcodedOutput(expr) => {
+                self.infer(expr, scope);
+            }
+            StmtKind::Return(Some(expr)) => {
+                let ty = self.infer(expr, scope);
+                self.check_return(ty, expr.span, ctx);
+            }
+            StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::If { cond, then_block, else_block } => {
+                self.expect_bool("an if condition", cond, scope);
+                self.check_block(then_block, scope, ctx);
+                if let Some(block) = else_block {
+                    self.check_block(block, scope, ctx);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.expect_bool("a while condition", cond, scope);
+                self.check_block(body, scope, ctx);
+            }
+            StmtKind::For { var, iterable, body } => {
+                let ty = match iterable {
+                    ForIterable::Range(a, b) => {
+                        for bound in [a, b] {
+                            let ty = self.infer(bound, scope);
+                            if !ty.fits(&Ty::Int) {
+                                self.error(bound.span, type_err!("a range bound must be int, got {}", ty));
+                            }
+                        }
+                        Ty::Int
+                    }
+                    ForIterable::Array(arr) => match self.infer(arr, scope) {
+                        Ty::Array(elem) => *elem,
+                        Ty::Unknown => Ty::Unknown,
+                        ty => {
+                            self.error(arr.span, type_err!("cannot iterate over {}", ty));
+                            Ty::Unknown
+                        }
+                    },
+                };
+                scope.insert(var.clone(), ty);
+                self.check_block(body, scope, ctx);
+            }
+            StmtKind::Observe(cond) => self.expect_bool("observe()", cond, scope),
+            StmtKind::ObserveFrom { dist, value } => {
+                self.infer(dist, scope);
+                self.infer(value, scope);
+            }
+            StmtKind::SetSeed(seed) => {
+                let ty = self.infer(seed, scope);
+                if !ty.fits(&Ty::Int) {
+                    self.error(seed.span, type_err!("set_seed() expects int, got {}", ty));
+                }
+            }
+            StmtKind::PbCallAssign { result_var, info_var, func_name, args, .. } => {
+                let ret = match self.pb_funcs.get(func_name.as_str()).copied() {
+                    Some(f) => {
+                        let (params, ret) = (f.params.clone(), self.resolve(&f.return_type));
+                        self.check_call_args(func_name, &params, args, scope);
+                        ret
+                    }
+                    None => Ty::Unknown,
+                };
+                scope.insert(result_var.clone(), ret);
+                scope.insert(info_var.clone(), Ty::Unknown);
+            }
+            StmtKind::MapCallAssign { var, info_var, func_name, array_expr, .. } => {
+                let arr = self.infer(array_expr, scope);
+                let ret = match self.callable(func_name, scope) {
+                    Some(Ty::Fn(params, ret)) => {
+                        if let [param] = params.as_slice()
+                            && !arr.elem().fits(param)
+                        {
+                            self.error(
+                                array_expr.span,
+                                type_err!("map({}, ...) expects an array of {}, got {}", func_name, param, arr),
+                            );
+                        }
+                        *ret
+                    }
+                    _ => Ty::Unknown,
+                };
+                scope.insert(var.clone(), Ty::Array(Box::new(ret)));
+                if let Some(info) = info_var {
+                    scope.insert(info.clone(), Ty::Unknown);
+                }
+            }
+            StmtKind::Infer { var, info_var, func_name, args, .. } => {
+                let ret = match self.funcs.get(func_name.as_str()).copied() {
+                    Some(f) => {
+                        let (params, ret) = (f.params.clone(), self.resolve(&f.return_type));
+                        self.check_call_args(func_name, &params, args, scope);
+                        ret
+                    }
+                    None => Ty::Unknown,
+                };
+                scope.insert(var.clone(), Ty::Dist(Box::new(ret)));
+                if let Some(info) = info_var {
+                    scope.insert(info.clone(), Ty::Unknown);
+                }
+            }
+            StmtKind::DistributionOf { var, func_name, args, .. } => {
+                if let Some(f) = self.pb_funcs.get(func_name.as_str()).copied() {
+                    self.check_call_args(func_name, &f.params.clone(), args, scope);
+                }
+                scope.insert(var.clone(), Ty::Dist(Box::new(Ty::Unknown)));
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                let bound = self.check_match(scrutinee, arms, scope, stmt.span);
+                for (arm, names) in arms.iter().zip(bound) {
+                    // As at runtime, the bindings go out of scope after the arm.
+                    let shadowed: Vec<(String, Option<Ty>)> =
+                        names.iter().map(|(name, _)| (name.clone(), scope.get(name).cloned())).collect();
+                    scope.extend(names);
+                    self.check_block(&arm.body, scope, ctx);
+                    for (name, outer) in shadowed.into_iter().rev() {
+                        match outer {
+                            Some(ty) => scope.insert(name, ty),
+                            None => scope.remove(&name),
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_return(&mut self, ty: Ty, span: Span, ctx: &mut FnCtx) {
+        ctx.returned = Some(match ctx.returned.take() {
+            Some(prev) => prev.join(ty.clone()),
+            None => ty.clone(),
+        });
+        let Some(declared) = &ctx.declared else { return };
+        if ctx.pb {
+            match ty {
+                Ty::Round(inner) if !inner.fits(declared) => self.error(
+                    span,
+                    type_err!("pb function '{}' returns Certain/Uncertain of {}, but is declared to return {}", ctx.name, inner, declared),
+                ),
+                Ty::Round(_) | Ty::Unknown => {}
+                ty => self.error(
+                    span,
+                    type_err!("pb function '{}' must return Certain(...), Uncertain(...) or Fail, got {}", ctx.name, ty),
+                ),
+            }
+        } else if !ty.fits(declared) {
+            self.error(span, type_err!("function '{}' returns {}, but is declared to return {}", ctx.name, ty, declared));
+        }
+    }
+
+    fn expect_bool(&mut self, what: &str, cond: &Expr, scope: &mut Scope) {
+        let ty = self.infer(cond, scope);
+        if !ty.fits(&Ty::Bool) {
+            self.error(cond.span, type_err!("{} must be bool, got {}", what, ty));
+        }
+    }
+
+    /// The type of a name used as a function: a variable, or a named function.
+    fn callable(&self, name: &str, scope: &Scope) -> Option<Ty> {
+        if let Some(ty) = scope.get(name) {
+            return Some(ty.clone());
+        }
+        if let Some(f) = self.funcs.get(name) {
+            return Some(self.func_ty(&f.params, &f.return_type));
+        }
+        self.pb_funcs.get(name).map(|f| self.func_ty(&f.params, &f.return_type))
+    }
+
+    /// Check each argument against its parameter's declared type. A wrong
+    /// number of arguments is left to the interpreter's arity error.
+    fn check_call_args(&mut self, func: &str, params: &[FuncParam], args: &[Expr], scope: &mut Scope) {
+        let expected: Vec<Ty> = params.iter().map(|p| self.resolve(&p.ty)).collect();
+        self.check_args(func, &expected, args, scope);
+    }
+
+    fn check_args(&mut self, func: &str, expected: &[Ty], args: &[Expr], scope: &mut Scope) {
+        let actual: Vec<Ty> = args.iter().map(|a| self.infer(a, scope)).collect();
+        if actual.len() != expected.len() {
+            return;
+        }
+        for (i, ((arg, ty), want)) in args.iter().zip(&actual).zip(expected).enumerate() {
+            if !ty.fits(want) {
+                self.error(arg.span, type_err!("argument {} of '{}' expects {}, got {}", i + 1, func, want, ty));
+            }
+        }
+    }
+
+    // Expressions
+
+    fn infer(&mut self, expr: &Expr, scope: &mut Scope) -> Ty {
+        match &expr.kind {
+            ExprKind::Int(_) => Ty::Int,
+            ExprKind::Float(_) | ExprKind::Frac(_) => Ty::Float,
+            ExprKind::Bool(_) => Ty::Bool,
+            ExprKind::Var(name) => match scope.get(name) {
+                Some(ty) => ty.clone(),
+                None => match self.variants.get(name.as_str()) {
+                    Some(enum_name) => Ty::Enum(enum_name.to_string()),
+                    None => self.callable(name, scope).unwrap_or(Ty::Unknown),
+                },
+            },
+
+            ExprKind::Neg(inner) => match self.infer(inner, scope) {
+                ty if ty.is_numeric() => ty,
+                ty => {
+                    self.error(expr.span, type_err!("cannot negate {}", ty));
+                    Ty::Unknown
+                }
+            },
+            ExprKind::Not(inner) => {
+                let ty = self.infer(inner, scope);
+                if !ty.fits(&Ty::Bool) {
+                    self.error(expr.span, type_err!("'!' requires bool, got {}", ty));
+                }
+                Ty::Bool
+            }
+
+            ExprKind::Add(a, b) => {
+                let (x, y) = (self.infer(a, scope), self.infer(b, scope));
+                match (&x, &y) {
+                    (Ty::Dist(_), Ty::Dist(_)) => x.join(y),
+                    _ => self.arithmetic(expr.span, x, y, |x, y| format!("cannot add {} and {}", x, y)),
+                }
+            }
+            ExprKind::Sub(a, b) => {
+                let (x, y) = (self.infer(a, scope), self.infer(b, scope));
+                self.arithmetic(expr.span, x, y, |x, y| format!("cannot subtract {} from {}", y, x))
+            }
+            ExprKind::Mul(a, b) => {
+                let (x, y) = (self.infer(a, scope), self.infer(b, scope));
+                self.arithmetic(expr.span, x, y, |x, y| format!("cannot multiply {} and {}", x, y))
+            }
+            ExprKind::Mod(a, b) => {
+                let (x, y) = (self.infer(a, scope), self.infer(b, scope));
+                self.arithmetic(expr.span, x, y, |x, y| format!("cannot compute {} mod {}", x, y))
+            }
+            ExprKind::Div(a, b) => {
+                let (x, y) = (self.infer(a, scope), self.infer(b, scope));
+                match self.arithmetic(expr.span, x, y, |x, y| format!("cannot divide {} by {}", x, y)) {
+                    Ty::Int => Ty::Num,
+                    ty => ty,
+                }
+            }
+
+            ExprKind::Eq(a, b) | ExprKind::Neq(a, b) => {
+                self.infer(a, scope);
+                self.infer(b, scope);
+                Ty::Bool
+            }
+            ExprKind::Lt(a, b) | ExprKind::Lte(a, b) | ExprKind::Gt(a, b) | ExprKind::Gte(a, b) => {
+                let (x, y) = (self.infer(a, scope), self.infer(b, scope));
+                if !x.is_numeric() || !y.is_numeric() {
+                    self.error(expr.span, type_err!("cannot compare {} and {}", x, y));
+                }
+                Ty::Bool
+            }
+            ExprKind::And(a, b) | ExprKind::Or(a, b) => {
+                let (x, y) = (self.infer(a, scope), self.infer(b, scope));
+                if !x.fits(&Ty::Bool) || !y.fits(&Ty::Bool) {
+                    let op = if matches!(expr.kind, ExprKind::And(..)) { "&&" } else { "||" };
+                    self.error(expr.span, type_err!("'{}' requires bool operands, got {} and {}", op, x, y));
+                }
+                Ty::Bool
+            }
+
+            ExprKind::Array(elems) => {
+                let mut elem: Option<Ty> = None;
+                for e in elems {
+                    let ty = self.infer(e, scope);
+                    elem = Some(match elem {
+                        Some(prev) => prev.join(ty),
+                        None => ty,
+                    });
+                }
+                Ty::Array(Box::new(elem.unwrap_or(Ty::Unknown)))
+            }
+            ExprKind::Index(arr, idx) => {
+                let index = self.infer(idx, scope);
+                if !index.fits(&Ty::Int) {
+                    self.error(idx.span, type_err!("an array index must be int, got {}", index));
+                }
+                match self.infer(arr, scope) {
+                    Ty::Array(elem) => *elem,
+                    Ty::Unknown => Ty::Unknown,
+                    ty => {
+                        self.error(arr.span, type_err!("cannot index {}", ty));
+                        Ty::Unknown
+                    }
+                }
+            }
+            ExprKind::Slice { expr: arr, start, end } => {
+                for bound in [start, end].into_iter().flatten() {
+                    self.infer(bound, scope);
+                }
+                match self.infer(arr, scope) {
+                    ty @ (Ty::Array(_) | Ty::Unknown) => ty,
+                    ty => {
+                        self.error(arr.span, type_err!("cannot slice {}", ty));
+                        Ty::Unknown
+                    }
+                }
+            }
+
+            ExprKind::Dist(d) => Ty::Dist(Box::new(self.dist_elem(d, scope))),
+            ExprKind::DistMethodCall { args, .. } => {
+                for arg in args {
+                    self.infer(arg, scope);
+                }
+                Ty::Unknown
+            }
+            ExprKind::ExprMethodCall { expr: target, method, args } => {
+                let target = self.infer(target, scope);
+                for arg in args {
+                    self.infer(arg, scope);
+                }
+                match (&target, method.as_str()) {
+                    (Ty::Dist(elem), "sample" | "median" | "mode" | "quantile" | "min" | "max") => (**elem).clone(),
+                    (Ty::Dist(elem), "support") => Ty::Array(elem.clone()),
+                    (Ty::Dist(_), "expect" | "prob" | "mean" | "variance" | "stddev" | "cdf" | "pdf" | "entropy") => {
+                        Ty::Float
+                    }
+                    _ => Ty::Unknown,
+                }
+            }
+            ExprKind::Field { expr: target, .. } => {
+                self.infer(target, scope);
+                Ty::Unknown
+            }
+
+            ExprKind::Certain(inner) | ExprKind::Uncertain(inner) => Ty::Round(Box::new(self.infer(inner, scope))),
+            ExprKind::Fail => Ty::Round(Box::new(Ty::Unknown)),
+
+            ExprKind::FuncCall(name, args) => self.infer_call(name, args, scope),
+            ExprKind::Lambda { params, body } => {
+                // The body sees the variables around it as well as its parameters.
+                let mut inner = scope.clone();
+                let param_tys: Vec<Ty> = params.iter().map(|p| self.resolve(&p.ty)).collect();
+                for (p, ty) in params.iter().zip(&param_tys) {
+                    inner.insert(p.name.clone(), ty.clone());
+                }
+                let mut ctx = FnCtx { name: "lambda".to_string(), declared: None, pb: false, returned: None };
+                self.check_block(body, &mut inner, &mut ctx);
+                Ty::Fn(param_tys, Box::new(ctx.returned.unwrap_or(Ty::Unknown)))
+            }
+
+            ExprKind::Match { scrutinee, arms } => {
+                let bound = self.check_match(scrutinee, arms, scope, expr.span);
+                let mut result: Option<Ty> = None;
+                for (arm, names) in arms.iter().zip(bound) {
+                    let mut inner = scope.clone();
+                    inner.extend(names);
+                    let ty = self.infer(&arm.body, &mut inner);
+                    result = Some(match result {
+                        Some(prev) => prev.join(ty),
+                        None => ty,
+                    });
+                }
+                result.unwrap_or(Ty::Unknown)
+            }
+
+            ExprKind::ApproxEq(a, b, tol) => {
+                self.infer(a, scope);
+                self.infer(b, scope);
+                if let Some(t) = tol {
+                    self.infer(t, scope);
+                }
+                Ty::Bool
+            }
+        }
+    }
+
+    // Pattern Matching
+
+    /// Check every pattern of a `match` against the scrutinee's type, and that
+    /// together they cover every value it can take (reported at `span`, the
+    /// whole match). Returns the names each arm binds, with their types.
+    fn check_match<B>(
+        &mut self,
+        scrutinee: &Expr,
+        arms: &[MatchArm<B>],
+        scope: &mut Scope,
+        span: Span,
+    ) -> Vec<Vec<(String, Ty)>> {
+        let mut ty = self.infer(scrutinee, scope);
+        let errors = self.errors.len();
+        let bound: Vec<Vec<(String, Ty)>> = arms
+            .iter()
+            .map(|arm| {
+                let mut names = Vec::new();
+                for pattern in &arm.patterns {
+                    self.check_pattern(pattern, &mut ty, &mut names);
+                }
+                names
+            })
+            .collect();
+        // Coverage means little once a pattern is of the wrong type.
+        if self.errors.len() == errors {
+            let patterns: Vec<&Pattern> = arms.iter().flat_map(|arm| &arm.patterns).collect();
+            let missing = self.uncovered(&patterns, &ty);
+            if !missing.is_empty() {
+                self.error(span, type_err!("non-exhaustive match on {}: {} not covered", ty, missing.join(", ")));
+            }
+        }
+        bound
+    }
+
+    /// Check that `pattern` can match a value of type `expected`, filling in
+    /// whatever the pattern reveals about an unknown scrutinee.
+    fn check_pattern(&mut self, pattern: &Pattern, expected: &mut Ty, names: &mut Vec<(String, Ty)>) {
+        let span = pattern.span;
+        let ty = match &pattern.kind {
+            PatternKind::Wildcard => return,
+            PatternKind::Binding(name) => {
+                names.push((name.clone(), expected.clone()));
+                return;
+            }
+            PatternKind::Int(_) => Ty::Int,
+            PatternKind::Bool(_) => Ty::Bool,
+            PatternKind::Variant(variant) => match self.variants.get(variant.as_str()) {
+                Some(enum_name) => Ty::Enum(enum_name.to_string()),
+                None => {
+                    self.error(span, type_err!("unknown enum variant '{}' in pattern", variant));
+                    return;
+                }
+            },
+            PatternKind::Certain(inner) | PatternKind::Uncertain(inner) => {
+                let mut inner_ty = expected.elem();
+                self.check_pattern(inner, &mut inner_ty, names);
+                Ty::Round(Box::new(inner_ty))
+            }
+            PatternKind::Fail => Ty::Round(Box::new(Ty::Unknown)),
+        };
+        if ty.fits(expected) {
+            *expected = std::mem::replace(expected, Ty::Unknown).refine(ty);
+        } else {
+            self.error(span, type_err!("pattern {} cannot match a value of type {}", pattern, expected));
+        }
+    }
+
+    /// The values of type `ty` that none of `patterns` match, written as
+    /// patterns; empty when the match is exhaustive. Only enums, bools and
+    /// `Certain`/`Uncertain` results can be covered without a `_` or binding
+    /// arm. `Fail` need not be covered, since only ZPP rounds produce it.
+    fn uncovered(&self, patterns: &[&Pattern], ty: &Ty) -> Vec<String> {
+        if patterns.iter().any(|p| p.is_irrefutable()) {
+            return Vec::new();
+        }
+        match ty {
+            Ty::Enum(name) => self.enums[name.as_str()]
+                .iter()
+                .filter(|v| !patterns.iter().any(|p| matches!(&p.kind, PatternKind::Variant(w) if w == *v)))
+                .cloned()
+                .collect(),
+            Ty::Bool => [true, false]
+                .into_iter()
+                .filter(|b| !patterns.iter().any(|p| matches!(p.kind, PatternKind::Bool(c) if c == *b)))
+                .map(|b| b.to_string())
+                .collect(),
+            Ty::Round(inner) => {
+                let certain: Vec<&Pattern> = patterns
+                    .iter()
+                    .filter_map(|p| if let PatternKind::Certain(q) = &p.kind { Some(&**q) } else { None })
+                    .collect();
+                let uncertain: Vec<&Pattern> = patterns
+                    .iter()
+                    .filter_map(|p| if let PatternKind::Uncertain(q) = &p.kind { Some(&**q) } else { None })
+                    .collect();
+                let mut missing = Vec::new();
+                for (ctor, inner_patterns) in [("Certain", certain), ("Uncertain", uncertain)] {
+                    let inner_missing = if inner_patterns.is_empty() {
+                        vec!["_".to_string()]
+                    } else {
+                        self.uncovered(&inner_patterns, inner)
+                    };
+                    missing.extend(inner_missing.into_iter().map(|m| format!("{}({})", ctor, m)));
+                }
+                missing
+            }
+            _ => vec!["_".to_string()],
+        }
+    }
+
+    /// Result type of `+`, `-`, `*` or `%` on numbers, reporting `message` otherwise.
+    fn arithmetic(&mut self, span: Span, x: Ty, y: Ty, message: impl Fn(&Ty, &Ty) -> String) -> Ty {
+        if !x.is_numeric() || !y.is_numeric() {
+            self.error(span, YapplError::TypeError(message(&x, &y)));
+            return Ty::Unknown;
+        }
+        match (x, y) {
+            (Ty::Unknown, _) | (_, Ty::Unknown) => Ty::Unknown,
+            (Ty::Int, Ty::Int) => Ty::Int,
+            (Ty::Num, _) | (_, Ty::Num) => Ty::Num,
+            _ => Ty::Float,
+        }
+    }
+
+    /// The type of the values a distribution literal produces.
+    fn dist_elem(&mut self, dist: &Dist, scope: &mut Scope) -> Ty {
+        match dist {
+            Dist::Discrete(pairs) => {
+                let mut elem: Option<Ty> = None;
+                for (value, prob) in pairs {
+                    self.infer(prob, scope);
+                    let ty = self.infer(value, scope);
+                    elem = Some(match elem {
+                        Some(prev) => prev.join(ty),
+                        None => ty,
+                    });
+                }
+                elem.unwrap_or(Ty::Unknown)
+            }
+            Dist::CombinedDist(a, b) => self.dist_elem(a, scope).join(self.dist_elem(b, scope)),
+            Dist::Bernoulli(p) => {
+                self.infer(p, scope);
+                Ty::Bool
+            }
+            Dist::Uniform(a, b) | Dist::Binomial(a, b) | Dist::NegativeBinomial(a, b) => {
+                self.infer(a, scope);
+                self.infer(b, scope);
+                Ty::Int
+            }
+            Dist::Geometric(p) | Dist::Poisson(p) => {
+                self.infer(p, scope);
+                Ty::Int
+            }
+            Dist::UniformContinuous(a, b) | Dist::Beta(a, b) | Dist::Normal(a, b) | Dist::Gamma(a, b) => {
+                self.infer(a, scope);
+                self.infer(b, scope);
+                Ty::Float
+            }
+            Dist::Exponential(rate) => {
+                self.infer(rate, scope);
+                Ty::Float
+            }
+        }
+    }
+
+    fn infer_call(&mut self, name: &str, args: &[Expr], scope: &mut Scope) -> Ty {
+        // Variables holding functions shadow everything else.
+        if let Some(ty) = scope.get(name).cloned() {
+            return match ty {
+                Ty::Fn(params, ret) => {
+                    self.check_args(name, &params, args, scope);
+                    *ret
+                }
+                _ => {
+                    for arg in args {
+                        self.infer(arg, scope);
+                    }
+                    Ty::Unknown
+                }
+            };
+        }
+
+        let arg_tys: Vec<Ty> = match name {
+            // User functions are checked against their signatures below.
+            _ if self.funcs.contains_key(name) || self.pb_funcs.contains_key(name) => Vec::new(),
+            _ => args.iter().map(|a| self.infer(a, scope)).collect(),
+        };
+        let arg = |i: usize| arg_tys.get(i).cloned().unwrap_or(Ty::Unknown);
+        let fn_ret = |ty: Ty| match ty {
+            Ty::Fn(_, ret) => *ret,
+            _ => Ty::Unknown,
+        };
+        match name {
+            "len" | "jacobi" | "mod_exp" => Ty::Int,
+            "range" => Ty::Array(Box::new(Ty::Int)),
+            "push" => Ty::Array(Box::new(arg(0).elem().join(arg(1)))),
+            "sum" => match arg(0).elem() {
+                Ty::Bool => Ty::Int,
+                ty @ (Ty::Int | Ty::Float | Ty::Num) => ty,
+                _ => Ty::Unknown,
+            },
+            "zip" => Ty::Array(Box::new(Ty::Array(Box::new(arg(0).elem().join(arg(1).elem()))))),
+            "filter" => arg(1),
+            "fold" => fn_ret(arg(0)),
+            "map" => Ty::Array(Box::new(fn_ret(arg(0)))),
+            "posterior" => Ty::Dist(Box::new(fn_ret(arg(0)))),
+            "bind" | "step" | "stationary" | "absorption_probs" => Ty::Dist(Box::new(Ty::Unknown)),
+            "hitting_time" => Ty::Float,
+            "is_ergodic" => Ty::Bool,
+            _ => match (self.funcs.get(name).copied(), self.pb_funcs.get(name).copied()) {
+                (Some(f), _) => {
+                    self.check_call_args(name, &f.params.clone(), args, scope);
+                    self.resolve(&f.return_type)
+                }
+                // A pb function called from inside another gives its answer directly.
+                (None, Some(f)) => {
+                    self.check_call_args(name, &f.params.clone(), args, scope);
+                    self.resolve(&f.return_type)
+                }
+                (None, None) => Ty::Unknown,
+            },
+        }
+    }
+}
@@ -1,8 +1,8 @@
-/// Distribution visualiser — produces ASCII histograms for the CLI and
-/// inline SVG bar charts for the web playground.
-///
-/// The two render functions are pure: all distribution data has already
-/// been reduced to (label, probability) pairs by the interpreter.
+//! Distribution visualiser — produces ASCII histograms for the CLI and
+//! inline SVG bar charts for the web playground.
+//!
+//! The two render functions are pure: all distribution data has already
+//! been reduced to (label, probability) pairs by the interpreter.
 
 // Data Types
 
@@ -1,7 +1,9 @@
 use std::panic::{self, AssertUnwindSafe};
 
+use crate::error::{YapplError, line_col};
 use crate::interpreter::run_to_html;
 use crate::parser::parse;
+use crate::typecheck::check;
 
 const HTML: &str = include_str!("../static/index.html");
 const DOCS_HTML: &str = include_str!("../static/documentation.html");
@@ -23,9 +25,10 @@ pub fn serve(port: u16) {
     for mut request in server.incoming_requests() {
         let method = request.method().clone();
         let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
 
         // all the routes we need for AJAX reqs
-        match (method, url.as_str()) {
+        match (method, path) {
             (tiny_http::Method::Get, "/") => {
                 let response = tiny_http::Response::from_string(HTML).with_header(
                     tiny_http::Header::from_bytes("Content-Type", "text/html; charset=utf-8")
@@ -95,27 +98,46 @@ pub fn serve(port: u16) {
                 let _ = request.respond(response);
             }
 
+            // Body: the program source. Query: optional `seed=N` for reproducible runs.
             (tiny_http::Method::Post, "/run") => {
                 let mut code = String::new();
                 let _ = request.as_reader().read_to_string(&mut code);
 
-                let result = panic::catch_unwind(AssertUnwindSafe(|| {
-                    let stmts = parse(&code);
-                    run_to_html(&stmts)
-                }));
-
-                let (status, content_type, body) = match result {
-                    Ok(html) => (200u16, "text/html; charset=utf-8", html),
-                    Err(e) => {
-                        let msg = e
-                            .downcast_ref::<String>()
-                            .cloned()
-                            .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
-                            .unwrap_or_else(|| "Unknown error".to_string());
-                        // Return plain-text error so the JS can detect it via
-                        // Content-Type and display it differently.
-                        (500, "text/plain; charset=utf-8", format!("Error: {}", msg))
-                    }
+                let seed = query_param(query, "seed").map(str::parse::<u64>).transpose();
+
+                let (status, content_type, body) = match seed {
+                    // Like `--seed`, a seed that is not a number is refused rather
+                    // than silently giving an unseeded run.
+                    Err(_) => (
+                        400u16,
+                        "application/json; charset=utf-8",
+                        format!(
+                            r#"{{"error": {}, "span": null}}"#,
+                            json_string("Error: seed expects a non-negative integer")
+                        ),
+                    ),
+                    // Errors come back as `YapplError`s; the unwind guard is a safety
+                    // net so that a panic left in the interpreter cannot take the
+                    // whole server down.
+                    Ok(seed) => match panic::catch_unwind(AssertUnwindSafe(|| {
+                        parse(&code).and_then(|stmts| {
+                            check(&stmts)?;
+                            run_to_html(&stmts, seed)
+                        })
+                    })) {
+                        Ok(Ok(html)) => (200, "text/html; charset=utf-8", html),
+                        // Return a JSON error so the JS can detect it via Content-Type,
+                        // display the message and highlight the offending range.
+                        Ok(Err(e)) => (500, "application/json; charset=utf-8", error_json(&e, &code)),
+                        Err(_) => (
+                            500,
+                            "application/json; charset=utf-8",
+                            format!(
+                                r#"{{"error": {}, "span": null}}"#,
+                                json_string("Error: the interpreter crashed on this program")
+                            ),
+                        ),
+                    },
                 };
 
                 let response = tiny_http::Response::from_string(body)
@@ -134,3 +156,47 @@ pub fn serve(port: u16) {
         }
     }
 }
+
+/// The value of `name` in a `key=value&...` query string.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// `{"error": "...", "span": {...} | null}` for a failed `/run`.
+/// Lines and columns are 1-based; columns count characters, not bytes.
+fn error_json(err: &YapplError, source: &str) -> String {
+    let span = match err.span() {
+        Some(span) => {
+            let (line, column) = line_col(source, span.start);
+            let (end_line, end_column) = line_col(source, span.end);
+            format!(
+                r#"{{"start": {}, "end": {}, "line": {}, "column": {}, "end_line": {}, "end_column": {}}}"#,
+                span.start, span.end, line, column, end_line, end_column
+            )
+        }
+        None => "null".to_string(),
+    };
+    format!(r#"{{"error": {}, "span": {}}}"#, json_string(&format!("Error: {}", err)), span)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
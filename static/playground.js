@@ -407,7 +407,8 @@ output(info3);`
 // The per-round probability of returning Certain is the key quantity.
 // distribution_of() exposes this three ways:
 //
-//   analytical : derived purely from the error class (no sampling).
+//   analytical : the exact Bernoulli(p) when a round's draws can be
+//                enumerated, otherwise the error class's worst case.
 //   empirical  : estimated by running N independent single rounds.
 //   bayesian   : Beta posterior after N rounds with uniform prior Beta(1,1).
 
@@ -433,7 +434,8 @@ pb function is_prime(p: int) -> bool {
 // Almost every witness detects 119 as composite on the first try, so the
 // per-round Certain probability should be very close to 1.
 
-// Analytical: RP worst case gives rounds-until-Certain ~ Geometric(0.5).
+// Analytical: the witness draw is enumerable, so this is the exact
+// per-round probability of Certain as a Bernoulli.
 let d_analytic = distribution_of(is_prime(119), analytical);
 output(d_analytic:visualise());
 
@@ -519,22 +521,31 @@ output(d:expect(3));`
 CodeMirror.defineMode("yappl", function () {
     // Language keywords
     const keywords = new Set([
-        "let", "output", "if", "else", "return",
-        "fn", "pb", "function", "with", "confidence",
+        "let", "output", "if", "else", "return", "match",
+        "while", "for", "in", "break", "continue", "observe", "set_seed",
+        "infer", "method", "samples", "rejection", "importance", "mh",
+        "fn", "pb", "function", "with", "confidence", "adaptive", "budget",
         "true", "false", "map",
-        "distribution_of", "analytical", "empirical", "bayesian",
+        "distribution_of", "analytical", "empirical", "bayesian", "outputs", "rounds",
     ]);
     // Distribution type names and built-in functions
     const builtins = new Set([
         "uniform", "uniformContinuous", "Discrete",
         "Bernoulli", "Binomial", "Geometric", "Beta",
-        "Certain", "Uncertain",
+        "Normal", "Poisson", "Exponential", "Gamma", "NegativeBinomial", "Categorical",
+        "Certain", "Uncertain", "Fail",
         "jacobi", "mod_exp",
+        "len", "push", "sum", "filter", "fold", "range", "zip", "posterior", "verify",
+        "stationary", "absorption_probs", "hitting_time", "is_ergodic", "hmm",
     ]);
     // Error-class keywords (only meaningful inside pb metadata blocks)
-    const errorClasses = new Set(["RP", "coRP", "BPP"]);
+    const errorClasses = new Set(["RP", "coRP", "BPP", "ZPP"]);
     // Distribution / value methods (appear after ':' or '.')
-    const methods = new Set(["expect", "min", "max", "mean", "sample", "visualise", "visualize"]);
+    const methods = new Set([
+        "expect", "min", "max", "mean", "sample", "visualise", "visualize",
+        "variance", "stddev", "cdf", "pdf", "quantile", "median", "mode", "support", "entropy", "prob",
+        "forward", "likelihood", "viterbi",
+    ]);
 
     return {
         startState: function () {
@@ -676,19 +687,43 @@ function editorRedo() {
     if (editor) editor.execCommand('redo');
 }
 
+// Error Highlighting
+
+let errorMark = null;
+
+function clearErrorMark() {
+    if (errorMark) {
+        errorMark.clear();
+        errorMark = null;
+    }
+}
+
+// Underline the source range of an error (1-based lines/columns from /run).
+function markErrorSpan(span) {
+    if (!editor || !span) return;
+    const from = { line: span.line - 1, ch: span.column - 1 };
+    let to = { line: span.end_line - 1, ch: span.end_column - 1 };
+    // Zero-width spans (e.g. end of input) still get a one-character mark.
+    if (to.line === from.line && to.ch <= from.ch) to = { line: from.line, ch: from.ch + 1 };
+    errorMark = editor.markText(from, to, { className: "cm-yappl-error" });
+    editor.scrollIntoView(from, 50);
+}
+
 // Run Code
 
 async function runCode() {
     const code = editor ? editor.getValue() : document.getElementById("code").value;
     const out = document.getElementById("output");
     const btn = document.getElementById("run-btn");
+    const seed = document.getElementById("seed-input").value.trim();
 
+    clearErrorMark();
     out.className = "mt-3 rounded p-3";
     out.textContent = "Running...";
     btn.disabled = true;
 
     try {
-        const res = await fetch("/run", {
+        const res = await fetch(seed ? `/run?seed=${encodeURIComponent(seed)}` : "/run", {
             method: "POST",
             headers: { "Content-Type": "text/plain" },
             body: code,
@@ -705,8 +740,14 @@ async function runCode() {
             }
         } else {
             out.className += " error";
-            // Errors are always plain text
-            out.textContent = text;
+            if (ct.includes("application/json")) {
+                // { error: "...", span: { line, column, end_line, end_column, ... } | null }
+                const err = JSON.parse(text);
+                out.textContent = err.error;
+                markErrorSpan(err.span);
+            } else {
+                out.textContent = text;
+            }
         }
     } catch (e) {
         out.className += " error";